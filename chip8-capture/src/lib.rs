@@ -0,0 +1,444 @@
+//! Screenshots and recordings of the emulator's display.
+//!
+//! Frames are the framebuffer returned by `Chip8::get_display`, one byte per pixel where
+//! nonzero is lit, coloured with a [`Palette`]. Platforms with colour displays capture RGB
+//! frames instead, one `[r, g, b]` per pixel. Frames are scaled up by an integer factor.
+//! Recordings are written as animated GIFs, or as uncompressed Y4M video for editing.
+
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufWriter, Error, ErrorKind, Write},
+    path::Path,
+};
+
+/// Frame rate of the emulator's display.
+const FRAME_RATE: u32 = 60;
+
+/// Levels of each channel in the colours used for GIF frames with more than 256 colours.
+const CUBE_LEVELS: u8 = 6;
+
+/// A frame to capture.
+#[derive(Debug, Clone, PartialEq)]
+enum Pixels {
+    /// Nonzero is lit
+    Lit(Vec<u8>),
+    Rgb(Vec<[u8; 3]>),
+}
+
+impl Pixels {
+    fn len(&self) -> usize {
+        match self {
+            Pixels::Lit(pixels) => pixels.len(),
+            Pixels::Rgb(pixels) => pixels.len(),
+        }
+    }
+
+    fn colors(&self, palette: &Palette) -> Vec<[u8; 3]> {
+        match self {
+            Pixels::Lit(pixels) => pixels.iter().map(|pixel| palette.color(*pixel)).collect(),
+            Pixels::Rgb(pixels) => pixels.clone(),
+        }
+    }
+}
+
+/// Colours of lit and unlit pixels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Palette {
+    pub foreground: [u8; 3],
+    pub background: [u8; 3],
+}
+
+impl Palette {
+    fn color(&self, pixel: u8) -> [u8; 3] {
+        if pixel != 0 {
+            self.foreground
+        } else {
+            self.background
+        }
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Self {
+            foreground: [0xFF; 3],
+            background: [0x00; 3],
+        }
+    }
+}
+
+/// Size and colouring of captured images.
+#[derive(Debug, Clone, Copy)]
+pub struct Format {
+    /// Display size in pixels
+    pub width: usize,
+    pub height: usize,
+    /// Size of each display pixel in the image
+    pub scale: usize,
+    pub palette: Palette,
+}
+
+impl Format {
+    fn image_size(&self) -> (usize, usize) {
+        (self.width * self.scale, self.height * self.scale)
+    }
+
+    /// Scale up a frame, keeping its pixel type.
+    fn scale<T: Copy>(&self, pixels: &[T]) -> Vec<T> {
+        let (width, height) = self.image_size();
+        let mut scaled = Vec::with_capacity(width * height);
+        for row in pixels.chunks(self.width) {
+            let start = scaled.len();
+            for pixel in row {
+                scaled.extend(std::iter::repeat_n(*pixel, self.scale));
+            }
+            for _ in 1..self.scale {
+                scaled.extend_from_within(start..start + width);
+            }
+        }
+        scaled
+    }
+
+    fn scale_pixels(&self, pixels: &Pixels) -> Pixels {
+        match pixels {
+            Pixels::Lit(pixels) => Pixels::Lit(self.scale(pixels)),
+            Pixels::Rgb(pixels) => Pixels::Rgb(self.scale(pixels)),
+        }
+    }
+}
+
+/// Save a frame as a PNG.
+pub fn save_png<P: AsRef<Path>>(path: P, format: &Format, pixels: &[u8]) -> std::io::Result<()> {
+    write_png(path.as_ref(), format, &Pixels::Lit(pixels.to_vec()))
+}
+
+/// Save an RGB frame as a PNG. The palette of `format` isn't used.
+pub fn save_png_rgb<P: AsRef<Path>>(
+    path: P,
+    format: &Format,
+    pixels: &[[u8; 3]],
+) -> std::io::Result<()> {
+    write_png(path.as_ref(), format, &Pixels::Rgb(pixels.to_vec()))
+}
+
+fn write_png(path: &Path, format: &Format, pixels: &Pixels) -> std::io::Result<()> {
+    let (width, height) = format.image_size();
+    let file = BufWriter::new(File::create(path)?);
+    let mut encoder = png::Encoder::new(file, width as u32, height as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    let data = format.scale_pixels(pixels).colors(&format.palette).concat();
+    encoder.write_header()?.write_image_data(&data)?;
+    Ok(())
+}
+
+/// Writes every frame passed to [`Recorder::record_frame`] to a GIF or Y4M file.
+pub struct Recorder {
+    format: Format,
+    output: Output,
+    frames: u64,
+}
+
+enum Output {
+    Gif {
+        encoder: gif::Encoder<BufWriter<File>>,
+        /// The last frame, held back until the next different frame so runs of identical
+        /// frames become one longer frame
+        pending: Option<Pixels>,
+        /// Frame count when the pending frame was first shown
+        pending_since: u64,
+    },
+    Y4m(BufWriter<File>),
+}
+
+impl Recorder {
+    /// Start a recording, choosing the container from the extension of `path` (`.gif` or
+    /// `.y4m`).
+    pub fn create<P: AsRef<Path>>(path: P, format: Format) -> std::io::Result<Recorder> {
+        let path = path.as_ref();
+        let extension = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_ascii_lowercase());
+        let (width, height) = format.image_size();
+        let mut file = BufWriter::new(File::create(path)?);
+        let output = match extension.as_deref() {
+            Some("gif") => {
+                let Palette {
+                    foreground,
+                    background,
+                } = format.palette;
+                let palette = [background, foreground].concat();
+                let mut encoder = gif::Encoder::new(file, width as u16, height as u16, &palette)
+                    .map_err(Error::other)?;
+                encoder
+                    .set_repeat(gif::Repeat::Infinite)
+                    .map_err(Error::other)?;
+                Output::Gif {
+                    encoder,
+                    pending: None,
+                    pending_since: 0,
+                }
+            }
+            Some("y4m") => {
+                writeln!(
+                    file,
+                    "YUV4MPEG2 W{width} H{height} F{FRAME_RATE}:1 Ip A1:1 C444"
+                )?;
+                Output::Y4m(file)
+            }
+            _ => {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    "recordings must end in .gif or .y4m",
+                ))
+            }
+        };
+        Ok(Self {
+            format,
+            output,
+            frames: 0,
+        })
+    }
+
+    /// Add a frame. Call once per displayed frame. Fails if the frame isn't the size the
+    /// recording was started with.
+    pub fn record_frame(&mut self, pixels: &[u8]) -> std::io::Result<()> {
+        self.record(Pixels::Lit(pixels.to_vec()))
+    }
+
+    /// Add an RGB frame, like [`Recorder::record_frame`]. Frames of both kinds can be mixed in
+    /// one recording.
+    pub fn record_frame_rgb(&mut self, pixels: &[[u8; 3]]) -> std::io::Result<()> {
+        self.record(Pixels::Rgb(pixels.to_vec()))
+    }
+
+    fn record(&mut self, pixels: Pixels) -> std::io::Result<()> {
+        if pixels.len() != self.format.width * self.format.height {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "frame size differs from the recording's",
+            ));
+        }
+        match &mut self.output {
+            Output::Gif {
+                encoder,
+                pending,
+                pending_since,
+            } => {
+                let scaled = self.format.scale_pixels(&pixels);
+                if pending.as_ref() != Some(&scaled) {
+                    if let Some(frame) = pending.take() {
+                        write_gif_frame(encoder, &self.format, frame, *pending_since, self.frames)?;
+                    }
+                    *pending = Some(scaled);
+                    *pending_since = self.frames;
+                }
+            }
+            Output::Y4m(file) => {
+                let scaled: Vec<[u8; 3]> = self
+                    .format
+                    .scale_pixels(&pixels)
+                    .colors(&self.format.palette)
+                    .into_iter()
+                    .map(to_ycbcr)
+                    .collect();
+                file.write_all(b"FRAME\n")?;
+                for plane in 0..3 {
+                    let plane: Vec<u8> = scaled.iter().map(|pixel| pixel[plane]).collect();
+                    file.write_all(&plane)?;
+                }
+            }
+        }
+        self.frames += 1;
+        Ok(())
+    }
+
+    /// Number of frames recorded so far.
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// Write any buffered frames and close the file.
+    pub fn finish(self) -> std::io::Result<()> {
+        match self.output {
+            Output::Gif {
+                mut encoder,
+                pending,
+                pending_since,
+            } => {
+                if let Some(frame) = pending {
+                    write_gif_frame(
+                        &mut encoder,
+                        &self.format,
+                        frame,
+                        pending_since,
+                        self.frames,
+                    )?;
+                }
+                encoder.into_inner().map_err(Error::other)?.flush()
+            }
+            Output::Y4m(mut file) => file.flush(),
+        }
+    }
+}
+
+/// Write a GIF frame shown from frame `start` until frame `end`. GIF delays are in
+/// hundredths of a second, so delays are rounded from the start of the recording to avoid
+/// drift.
+fn write_gif_frame<W: Write>(
+    encoder: &mut gif::Encoder<W>,
+    format: &Format,
+    pixels: Pixels,
+    start: u64,
+    end: u64,
+) -> std::io::Result<()> {
+    let centiseconds = |frames: u64| (frames * 100 + FRAME_RATE as u64 / 2) / FRAME_RATE as u64;
+    let (width, height) = format.image_size();
+    let (indices, palette) = match pixels {
+        // indices into the global palette of background and foreground
+        Pixels::Lit(pixels) => (pixels.into_iter().map(|p| (p != 0) as u8).collect(), None),
+        Pixels::Rgb(pixels) => {
+            let (indices, palette) = index_colors(&pixels);
+            (indices, Some(palette))
+        }
+    };
+    let mut frame = gif::Frame::from_indexed_pixels(width as u16, height as u16, indices, None);
+    frame.palette = palette;
+    // most viewers slow down delays under 2 to 10
+    frame.delay = (centiseconds(end) - centiseconds(start)).clamp(2, u16::MAX as u64) as u16;
+    encoder.write_frame(&frame).map_err(Error::other)
+}
+
+/// Give each colour of an RGB frame an index into a palette of at most 256 colours, returning
+/// the indices and the palette as RGB bytes. Frames with more colours are reduced to a fixed
+/// colour cube.
+fn index_colors(pixels: &[[u8; 3]]) -> (Vec<u8>, Vec<u8>) {
+    let mut colors: HashMap<[u8; 3], u8> = HashMap::new();
+    let mut palette = Vec::new();
+    let mut indices = Vec::with_capacity(pixels.len());
+    for pixel in pixels {
+        let next = colors.len();
+        if next > u8::MAX as usize && !colors.contains_key(pixel) {
+            return index_cube(pixels);
+        }
+        indices.push(*colors.entry(*pixel).or_insert_with(|| {
+            palette.extend_from_slice(pixel);
+            next as u8
+        }));
+    }
+    (indices, palette)
+}
+
+fn index_cube(pixels: &[[u8; 3]]) -> (Vec<u8>, Vec<u8>) {
+    let step = 255 / (CUBE_LEVELS - 1);
+    let level = |channel: u8| (channel as u16 * (CUBE_LEVELS - 1) as u16 + 127) / 255;
+    let indices = pixels
+        .iter()
+        .map(|[r, g, b]| {
+            let levels = CUBE_LEVELS as u16;
+            ((level(*r) * levels + level(*g)) * levels + level(*b)) as u8
+        })
+        .collect();
+    let palette = (0..CUBE_LEVELS)
+        .flat_map(|r| (0..CUBE_LEVELS).flat_map(move |g| (0..CUBE_LEVELS).map(move |b| [r, g, b])))
+        .flat_map(|color| color.map(|level| level * step))
+        .collect();
+    (indices, palette)
+}
+
+/// Convert RGB to full range BT.601 Y'CbCr.
+fn to_ycbcr([r, g, b]: [u8; 3]) -> [u8; 3] {
+    let (r, g, b) = (r as f32, g as f32, b as f32);
+    let y = 0.299 * r + 0.587 * g + 0.114 * b;
+    let cb = 128.0 - 0.168736 * r - 0.331264 * g + 0.5 * b;
+    let cr = 128.0 + 0.5 * r - 0.418688 * g - 0.081312 * b;
+    [y, cb, cr].map(|c| c.round().clamp(0.0, 255.0) as u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FORMAT: Format = Format {
+        width: 4,
+        height: 2,
+        scale: 2,
+        palette: Palette {
+            foreground: [0xFF, 0x00, 0x00],
+            background: [0x00, 0x00, 0x00],
+        },
+    };
+
+    #[test]
+    fn scale() {
+        let scaled = FORMAT.scale(&[1, 0, 0, 0, 0, 0, 0, 1]);
+        assert_eq!(
+            scaled,
+            [
+                1, 1, 0, 0, 0, 0, 0, 0, //
+                1, 1, 0, 0, 0, 0, 0, 0, //
+                0, 0, 0, 0, 0, 0, 1, 1, //
+                0, 0, 0, 0, 0, 0, 1, 1, //
+            ]
+        );
+    }
+
+    #[test]
+    fn write_files() {
+        let dir = std::env::temp_dir();
+        let frame = [1, 0, 0, 0, 0, 0, 0, 1];
+
+        let png = dir.join("chip8-capture-test.png");
+        save_png(&png, &FORMAT, &frame).unwrap();
+        assert!(std::fs::read(&png).unwrap().starts_with(b"\x89PNG"));
+
+        let y4m = dir.join("chip8-capture-test.y4m");
+        let mut recorder = Recorder::create(&y4m, FORMAT).unwrap();
+        recorder.record_frame(&frame).unwrap();
+        recorder.record_frame(&frame).unwrap();
+        recorder.finish().unwrap();
+        let header = b"YUV4MPEG2 W8 H4 F60:1 Ip A1:1 C444\n";
+        let frame_size = b"FRAME\n".len() + 8 * 4 * 3;
+        assert_eq!(
+            std::fs::read(&y4m).unwrap().len(),
+            header.len() + 2 * frame_size
+        );
+
+        let gif = dir.join("chip8-capture-test.gif");
+        let mut recorder = Recorder::create(&gif, FORMAT).unwrap();
+        for _ in 0..3 {
+            recorder.record_frame(&frame).unwrap();
+        }
+        recorder.record_frame(&[0; 8]).unwrap();
+        recorder.record_frame_rgb(&[[0x10, 0x20, 0x30]; 8]).unwrap();
+        assert!(recorder.record_frame_rgb(&[[0; 3]; 4]).is_err());
+        assert_eq!(recorder.frames(), 5);
+        recorder.finish().unwrap();
+        assert!(std::fs::read(&gif).unwrap().starts_with(b"GIF89a"));
+
+        assert!(Recorder::create(dir.join("chip8-capture-test.avi"), FORMAT).is_err());
+    }
+
+    #[test]
+    fn rgb_frames() {
+        let red = [0xFF, 0x00, 0x00];
+        let blue = [0x00, 0x00, 0xFF];
+        assert_eq!(
+            index_colors(&[red, blue, red]),
+            (vec![0, 1, 0], [red, blue].concat())
+        );
+
+        // too many colours for one GIF palette
+        let pixels: Vec<[u8; 3]> = (0..300).map(|i| [i as u8, (i / 256) as u8, 0]).collect();
+        let (indices, palette) = index_colors(&pixels);
+        assert_eq!(palette.len(), 216 * 3);
+        assert_eq!(indices[0], 0);
+        assert_eq!(&palette[indices[255] as usize * 3..][..3], [0xFF, 0, 0]);
+
+        let png = std::env::temp_dir().join("chip8-capture-test-rgb.png");
+        save_png_rgb(&png, &FORMAT, &[red; 8]).unwrap();
+        assert!(std::fs::read(&png).unwrap().starts_with(b"\x89PNG"));
+    }
+}
@@ -0,0 +1,429 @@
+//! Octo cartridges: GIF images that carry a program and its options, with a label showing
+//! the game.
+//!
+//! The payload is a JSON object with a `program` string of Octo source and an `options`
+//! object, prefixed by its length as a 32 bit big endian integer. Each payload byte is spread
+//! over four pixels, two bits at a time starting with the most significant, in the low two
+//! bits of the pixels' palette indices. Every colour of the label appears four times in the
+//! palette, so the data doesn't change how the label looks. Payloads too big for one frame
+//! continue in the following frames.
+//!
+//! Without an Octo assembler, only programs written as byte literals can be loaded, which is
+//! how [`Cartridge::write`] stores them. Other programs are reported as unsupported.
+
+use std::{
+    fmt::Write as _,
+    fs::File,
+    io::{BufReader, BufWriter, Error, ErrorKind, Read, Write},
+    path::Path,
+};
+
+use chip8_core::chip8::Chip8;
+use serde_json::{json, Map, Value};
+
+/// Size of a cartridge image.
+const WIDTH: usize = 160;
+const HEIGHT: usize = 128;
+/// Size of each display pixel in the label.
+const LABEL_SCALE: usize = 2;
+/// Octo runs this many instructions per 60 Hz frame unless a cartridge says otherwise.
+const DEFAULT_TICKRATE: u32 = 20;
+
+/// Whether a file is named like a cartridge, i.e. ends in `.gif`.
+pub fn is_cartridge(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("gif"))
+}
+
+/// Quirks of CHIP-8 interpreters a program expects, named as in Octo.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Quirks {
+    pub shift: bool,
+    pub load_store: bool,
+    pub vf_order: bool,
+    pub clip: bool,
+    pub vblank: bool,
+    pub jump: bool,
+    pub logic: bool,
+}
+
+/// How a cartridge's program should be run.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Options {
+    /// Instructions per 60 Hz frame
+    pub tickrate: u32,
+    /// Unlit pixels
+    pub background_color: [u8; 3],
+    /// Pixels lit in plane 1
+    pub fill_color: [u8; 3],
+    /// Pixels lit in plane 2
+    pub fill_color2: [u8; 3],
+    /// Pixels lit in both planes
+    pub blend_color: [u8; 3],
+    /// Border while the sound timer is active, and while it isn't
+    pub buzz_color: [u8; 3],
+    pub quiet_color: [u8; 3],
+    pub quirks: Quirks,
+    /// Options this emulator doesn't use, kept so they survive being saved again
+    pub other: Map<String, Value>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            tickrate: DEFAULT_TICKRATE,
+            background_color: [0x99, 0x66, 0x00],
+            fill_color: [0xFF, 0xCC, 0x00],
+            fill_color2: [0xFF, 0x66, 0x00],
+            blend_color: [0x66, 0x22, 0x00],
+            buzz_color: [0xFF, 0xAA, 0x00],
+            quiet_color: [0x00, 0x00, 0x00],
+            quirks: Quirks::default(),
+            other: Map::new(),
+        }
+    }
+}
+
+/// Option names of the quirks, in the same order as [`Options::quirks_mut`].
+const QUIRK_NAMES: [&str; 7] = [
+    "shiftQuirks",
+    "loadStoreQuirks",
+    "vfOrderQuirks",
+    "clipQuirks",
+    "vBlankQuirks",
+    "jumpQuirks",
+    "logicQuirks",
+];
+
+/// Option names of the colours, in the same order as [`Options::colors_mut`].
+const COLOR_NAMES: [&str; 6] = [
+    "backgroundColor",
+    "fillColor",
+    "fillColor2",
+    "blendColor",
+    "buzzColor",
+    "quietColor",
+];
+
+impl Options {
+    /// Display colours indexed by which planes a pixel is lit in: unlit, plane 1, plane 2
+    /// and both planes.
+    pub fn display_colors(&self) -> [[u8; 3]; 4] {
+        [
+            self.background_color,
+            self.fill_color,
+            self.fill_color2,
+            self.blend_color,
+        ]
+    }
+
+    fn colors_mut(&mut self) -> [&mut [u8; 3]; 6] {
+        [
+            &mut self.background_color,
+            &mut self.fill_color,
+            &mut self.fill_color2,
+            &mut self.blend_color,
+            &mut self.buzz_color,
+            &mut self.quiet_color,
+        ]
+    }
+
+    fn quirks_mut(&mut self) -> [&mut bool; 7] {
+        let quirks = &mut self.quirks;
+        [
+            &mut quirks.shift,
+            &mut quirks.load_store,
+            &mut quirks.vf_order,
+            &mut quirks.clip,
+            &mut quirks.vblank,
+            &mut quirks.jump,
+            &mut quirks.logic,
+        ]
+    }
+
+    fn from_json(json: &Map<String, Value>) -> std::io::Result<Options> {
+        let mut options = Options::default();
+        let mut other = json.clone();
+        if let Some(tickrate) = other.remove("tickrate") {
+            options.tickrate = tickrate
+                .as_u64()
+                .and_then(|t| u32::try_from(t).ok())
+                .ok_or_else(|| invalid(format!("invalid tickrate {tickrate}")))?;
+        }
+        for (name, color) in COLOR_NAMES.into_iter().zip(options.colors_mut()) {
+            if let Some(value) = other.remove(name) {
+                *color = value
+                    .as_str()
+                    .and_then(parse_color)
+                    .ok_or_else(|| invalid(format!("invalid {name} {value}")))?;
+            }
+        }
+        for (name, quirk) in QUIRK_NAMES.into_iter().zip(options.quirks_mut()) {
+            if let Some(value) = other.remove(name) {
+                *quirk = value
+                    .as_bool()
+                    .ok_or_else(|| invalid(format!("invalid {name} {value}")))?;
+            }
+        }
+        options.other = other;
+        Ok(options)
+    }
+
+    fn to_json(&self) -> Value {
+        let mut json = self.other.clone();
+        json.insert("tickrate".to_string(), json!(self.tickrate));
+        let mut options = self.clone();
+        for (name, [r, g, b]) in COLOR_NAMES.into_iter().zip(options.colors_mut()) {
+            json.insert(name.to_string(), json!(format!("#{r:02X}{g:02X}{b:02X}")));
+        }
+        for (name, quirk) in QUIRK_NAMES.into_iter().zip(options.quirks_mut()) {
+            json.insert(name.to_string(), json!(quirk));
+        }
+        Value::Object(json)
+    }
+}
+
+/// A program and the options to run it with.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cartridge {
+    pub program: Vec<u8>,
+    pub options: Options,
+}
+
+impl Cartridge {
+    /// A cartridge for the ROM loaded in `chip8`, running at its current speed.
+    pub fn from_chip8(chip8: &Chip8, mut options: Options) -> std::io::Result<Cartridge> {
+        let program = chip8
+            .rom()
+            .ok_or_else(|| Error::other("no rom is loaded"))?
+            .to_vec();
+        options.tickrate = (chip8.speed() / 60).max(1);
+        Ok(Cartridge { program, options })
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> std::io::Result<Cartridge> {
+        Cartridge::read(BufReader::new(File::open(path)?))
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P, label: &[u8]) -> std::io::Result<()> {
+        let mut file = BufWriter::new(File::create(path)?);
+        self.write(&mut file, label)?;
+        file.flush()
+    }
+
+    /// Decode a cartridge from a GIF.
+    pub fn read<R: Read>(reader: R) -> std::io::Result<Cartridge> {
+        let mut options = gif::DecodeOptions::new();
+        options.set_color_output(gif::ColorOutput::Indexed);
+        let mut decoder = options.read_info(reader).map_err(invalid)?;
+        let mut bits = Vec::new();
+        while let Some(frame) = decoder.read_next_frame().map_err(invalid)? {
+            bits.extend(frame.buffer.iter().map(|index| index & 3));
+        }
+
+        let mut bytes = bits
+            .chunks_exact(4)
+            .map(|pairs| pairs.iter().fold(0, |byte, pair| (byte << 2) | pair));
+        let mut length = [0; 4];
+        for byte in length.iter_mut() {
+            *byte = bytes.next().ok_or_else(|| invalid("not a cartridge"))?;
+        }
+        let length = u32::from_be_bytes(length) as usize;
+        let payload: Vec<u8> = bytes.take(length).collect();
+        if payload.len() != length {
+            return Err(invalid("cartridge payload is truncated"));
+        }
+
+        let payload: Value = serde_json::from_slice(&payload).map_err(invalid)?;
+        let source = payload["program"]
+            .as_str()
+            .ok_or_else(|| invalid("cartridge doesn't contain a program"))?;
+        let options = match &payload["options"] {
+            Value::Object(options) => Options::from_json(options)?,
+            Value::Null => Options::default(),
+            _ => return Err(invalid("cartridge options aren't an object")),
+        };
+        Ok(Cartridge {
+            program: parse_source(source)?,
+            options,
+        })
+    }
+
+    /// Encode the cartridge as a GIF, labelled with a display frame in the format returned by
+    /// `Chip8::get_display`.
+    pub fn write<W: Write>(&self, writer: W, label: &[u8]) -> std::io::Result<()> {
+        let payload = json!({
+            "program": to_source(&self.program),
+            "options": self.options.to_json(),
+        })
+        .to_string();
+        let length = u32::try_from(payload.len()).map_err(Error::other)?;
+        let bits: Vec<u8> = length
+            .to_be_bytes()
+            .iter()
+            .chain(payload.as_bytes())
+            .flat_map(|byte| [6, 4, 2, 0].map(|shift| (byte >> shift) & 3))
+            .collect();
+
+        let palette: Vec<u8> = self
+            .options
+            .display_colors()
+            .iter()
+            .flat_map(|color| color.repeat(4))
+            .collect();
+        let label = draw_label(label);
+        let mut encoder = gif::Encoder::new(writer, WIDTH as u16, HEIGHT as u16, &palette)
+            .map_err(Error::other)?;
+        encoder
+            .set_repeat(gif::Repeat::Infinite)
+            .map_err(Error::other)?;
+        for chunk in bits.chunks(WIDTH * HEIGHT) {
+            let mut pixels = label.clone();
+            for (pixel, bits) in pixels.iter_mut().zip(chunk) {
+                *pixel |= bits;
+            }
+            let frame = gif::Frame::from_indexed_pixels(WIDTH as u16, HEIGHT as u16, pixels, None);
+            encoder.write_frame(&frame).map_err(Error::other)?;
+        }
+        Ok(())
+    }
+
+    /// Load the program into `chip8` and set its speed from the tick rate.
+    pub fn apply(&self, chip8: &mut Chip8) -> std::io::Result<()> {
+        chip8.load_rom_bytes(&self.program)?;
+        chip8.set_speed(self.options.tickrate.saturating_mul(60));
+        Ok(())
+    }
+}
+
+/// Palette indices of the label, the display scaled up and centred on the background, with
+/// the low two bits left clear for data.
+fn draw_label(display: &[u8]) -> Vec<u8> {
+    let (width, height) = (64, display.len() / 64);
+    let left = (WIDTH - width * LABEL_SCALE) / 2;
+    let top = (HEIGHT - height * LABEL_SCALE) / 2;
+    let mut pixels = vec![0; WIDTH * HEIGHT];
+    for (i, pixel) in pixels.iter_mut().enumerate() {
+        let (x, y) = (i % WIDTH, i / WIDTH);
+        if (left..left + width * LABEL_SCALE).contains(&x)
+            && (top..top + height * LABEL_SCALE).contains(&y)
+        {
+            let value = display[(y - top) / LABEL_SCALE * width + (x - left) / LABEL_SCALE];
+            *pixel = (value & 3) << 2;
+        }
+    }
+    pixels
+}
+
+/// Write a program as Octo source made of byte literals.
+fn to_source(program: &[u8]) -> String {
+    let mut source = ": main\n".to_string();
+    for line in program.chunks(16) {
+        let bytes: Vec<String> = line.iter().map(|byte| format!("0x{byte:02X}")).collect();
+        let _ = writeln!(source, "{}", bytes.join(" "));
+    }
+    source
+}
+
+/// Read Octo source made of byte literals, labels and comments.
+fn parse_source(source: &str) -> std::io::Result<Vec<u8>> {
+    let mut program = Vec::new();
+    for line in source.lines() {
+        let line = line.split('#').next().unwrap_or_default();
+        let mut tokens = line.split_whitespace();
+        while let Some(token) = tokens.next() {
+            if token == ":" {
+                // label name
+                tokens.next();
+                continue;
+            }
+            let byte = parse_byte(token).ok_or_else(|| {
+                Error::new(
+                    ErrorKind::Unsupported,
+                    format!(
+                        "can't load cartridge: `{token}` needs an Octo assembler, only byte \
+                         literals are supported"
+                    ),
+                )
+            })?;
+            program.push(byte);
+        }
+    }
+    Ok(program)
+}
+
+fn parse_byte(token: &str) -> Option<u8> {
+    let value = if let Some(hex) = token.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()?
+    } else if let Some(binary) = token.strip_prefix("0b") {
+        i64::from_str_radix(binary, 2).ok()?
+    } else {
+        token.parse().ok()?
+    };
+    match value {
+        -128..=-1 => Some(value as i8 as u8),
+        0..=255 => Some(value as u8),
+        _ => None,
+    }
+}
+
+fn parse_color(value: &str) -> Option<[u8; 3]> {
+    let hex = value.strip_prefix('#')?;
+    if hex.len() != 6 {
+        return None;
+    }
+    let color = u32::from_str_radix(hex, 16).ok()?;
+    Some([16, 8, 0].map(|shift| (color >> shift) as u8))
+}
+
+fn invalid<E: Into<Box<dyn std::error::Error + Send + Sync>>>(e: E) -> Error {
+    Error::new(ErrorKind::InvalidData, e)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let mut options = Options {
+            tickrate: 100,
+            fill_color: [0x12, 0x34, 0x56],
+            ..Options::default()
+        };
+        options.quirks.shift = true;
+        options.other.insert("fontStyle".to_string(), json!("octo"));
+        let cartridge = Cartridge {
+            // big enough to need a second frame
+            program: (0..6000).map(|i| i as u8).collect(),
+            options,
+        };
+        let mut label = vec![0; 64 * 32];
+        label[0] = 1;
+
+        let mut gif = Vec::new();
+        cartridge.write(&mut gif, &label).unwrap();
+        assert_eq!(Cartridge::read(&gif[..]).unwrap(), cartridge);
+
+        let mut chip8 = Chip8::default();
+        Cartridge {
+            program: vec![0x12, 0x00],
+            ..cartridge
+        }
+        .apply(&mut chip8)
+        .unwrap();
+        assert_eq!(chip8.speed(), 6000);
+        assert_eq!(chip8.rom(), Some(&[0x12, 0x00][..]));
+    }
+
+    #[test]
+    fn source() {
+        let source = "# comment\n: main 0x6A 2 0b1 -1 # another\n: loop 0x12 0x02";
+        assert_eq!(
+            parse_source(source).unwrap(),
+            [0x6A, 2, 1, 0xFF, 0x12, 0x02]
+        );
+        let e = parse_source(": main\n  v0 := 1").unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::Unsupported);
+    }
+}
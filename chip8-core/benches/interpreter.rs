@@ -0,0 +1,58 @@
+//! Instructions per second of the interpreter on the bundled ROMs, with and without the decode
+//! cache. Run with `cargo bench -p chip8-core`.
+
+use std::path::Path;
+
+use chip8_core::chip8::Chip8;
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+
+/// Instructions executed per iteration.
+const INSTRUCTIONS: u64 = 100_000;
+
+fn machine(rom: &[u8], decode_cache: bool) -> Chip8 {
+    let mut chip8 = Chip8::default();
+    chip8.set_trace(false);
+    chip8.set_decode_cache(decode_cache);
+    chip8.seed(0);
+    chip8.load_rom_bytes(rom).unwrap();
+    chip8
+}
+
+fn run(chip8: &mut Chip8) {
+    for _ in 0..INSTRUCTIONS {
+        chip8.step_instruction();
+    }
+}
+
+fn interpreter(c: &mut Criterion) {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("../roms");
+    let mut roms: Vec<_> = std::fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    roms.sort();
+
+    for path in roms {
+        let name = path.file_name().unwrap().to_string_lossy().into_owned();
+        let rom = std::fs::read(&path).unwrap();
+        // a program that faults stops executing, which would make it look very fast
+        let mut chip8 = machine(&rom, false);
+        run(&mut chip8);
+        if let Some(fault) = chip8.fault() {
+            eprintln!("skipping {name}: {fault}");
+            continue;
+        }
+
+        let mut group = c.benchmark_group(&name);
+        group.throughput(Throughput::Elements(INSTRUCTIONS));
+        for (id, decode_cache) in [("interpreter", false), ("decode cache", true)] {
+            group.bench_function(id, |b| {
+                b.iter_batched_ref(|| machine(&rom, decode_cache), run, BatchSize::SmallInput)
+            });
+        }
+        group.finish();
+    }
+}
+
+criterion_group!(benches, interpreter);
+criterion_main!(benches);
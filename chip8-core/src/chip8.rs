@@ -1,9 +1,133 @@
 use std::{
+    collections::HashSet,
+    fmt,
     io::Read,
     path::{Path, PathBuf},
+    str::FromStr,
 };
 
-use crate::{cpu::Cpu, keyboard::Keyboard};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+
+use crate::{
+    color::{DisplayColors, COLORS},
+    cpu::{Cpu, STACK_SIZE},
+    font::{FontSet, FONT_SIZE},
+    hooks::{HookEvent, HookId},
+    instructions::Instruction,
+    megachip::Sample,
+    platform::Platform,
+    profiler::Profile,
+};
+
+/// Magic bytes at the start of every save state.
+const STATE_MAGIC: &[u8; 4] = b"C8ST";
+/// Save state format version, bumped whenever the layout changes.
+const STATE_VERSION: u8 = 4;
+/// Lowest address programs are loaded at on any platform. The font has to fit below it.
+const PROGRAM_START: usize = 0x200;
+/// Timer frequency, which is also the rate frames are run at.
+const FRAME_RATE: u32 = 60;
+
+/// A CPU register, as seen by [`Chip8::get_register`] and [`Chip8::set_register`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Register {
+    /// General purpose register V0 - VF
+    V(u8),
+    I,
+    Pc,
+    /// Stack pointer, the number of return addresses on the stack
+    Sp,
+    /// Delay timer
+    Dt,
+    /// Sound timer
+    St,
+}
+
+impl FromStr for Register {
+    type Err = String;
+
+    /// Parse a register name such as `V3`, `I`, `PC`, `SP`, `DT` or `ST`, ignoring case.
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.to_ascii_uppercase().as_str() {
+            "I" => Ok(Register::I),
+            "PC" => Ok(Register::Pc),
+            "SP" => Ok(Register::Sp),
+            "DT" => Ok(Register::Dt),
+            "ST" => Ok(Register::St),
+            upper => match upper.strip_prefix('V') {
+                Some(x) if x.len() == 1 => u8::from_str_radix(x, 16)
+                    .map(Register::V)
+                    .map_err(|_| format!("unknown register `{name}`")),
+                _ => Err(format!("unknown register `{name}`")),
+            },
+        }
+    }
+}
+
+/// Behaviours that differ between CHIP-8 interpreters. All are off by default.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Quirks {
+    /// Fx0A waits for a key to be pressed and then released, like the original COSMAC VIP
+    /// interpreter, instead of completing as soon as a key is pressed
+    pub key_release: bool,
+}
+
+/// An error in the running program that stops the machine until it's reset.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    /// The instruction at `address` isn't a CHIP-8 instruction
+    InvalidInstruction { address: u16, instruction: u16 },
+    /// The CALL at `address` exceeded the maximum stack depth
+    StackOverflow { address: u16 },
+    /// The RET at `address` was executed with an empty stack
+    StackUnderflow { address: u16 },
+    /// The instruction at `address` accessed memory at `access`, past the end of memory
+    MemoryOutOfRange { address: u16, access: u32 },
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Fault::InvalidInstruction {
+                address,
+                instruction,
+            } => write!(f, "invalid instruction {instruction:04X} at {address:03X}"),
+            Fault::StackOverflow { address } => write!(f, "stack overflow at {address:03X}"),
+            Fault::StackUnderflow { address } => write!(f, "stack underflow at {address:03X}"),
+            Fault::MemoryOutOfRange { address, access } => {
+                write!(
+                    f,
+                    "memory access out of range at {address:03X}: {access:04X}"
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for Fault {}
+
+/// Why [`Chip8::run_frame`] returned before finishing the frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stop {
+    /// The instruction at this breakpoint is about to execute
+    Breakpoint(u16),
+    Fault(Fault),
+}
+
+/// What happened during a call to [`Chip8::run_frame`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FrameSummary {
+    /// Number of instructions executed
+    pub cycles: u32,
+    /// Whether any pixel changed
+    pub display_changed: bool,
+    /// Whether the sound timer is active at the end of the frame
+    pub sound: bool,
+    /// Whether the program is blocked on Fx0A waiting for a key press, or on CHIP-8X input
+    pub waiting_for_key: bool,
+    /// Set if the frame ended early
+    pub stop: Option<Stop>,
+}
 
 #[derive(Debug)]
 pub struct Chip8 {
@@ -11,7 +135,14 @@ pub struct Chip8 {
     speed: u32,
     cpu: Cpu,
     rom_path: Option<PathBuf>,
+    rom: Option<Vec<u8>>,
     loaded: bool,
+    /// Set when the program faults, until the machine is reset
+    fault: Option<Fault>,
+    breakpoints: HashSet<u16>,
+    /// Execution counts, kept after profiling stops until cleared
+    profile: Option<Profile>,
+    profiling: bool,
 }
 
 impl Chip8 {
@@ -20,64 +151,554 @@ impl Chip8 {
         self.speed = speed;
     }
 
-    pub fn load_rom<P: AsRef<Path>>(&mut self, path: P) -> std::io::Result<()>
-    where
-        PathBuf: From<P>,
-    {
+    /// Get emulation clock speed in Hz.
+    pub fn speed(&self) -> u32 {
+        self.speed
+    }
+
+    /// Enable or disable printing CPU state and instructions to stdout.
+    pub fn set_trace(&mut self, trace: bool) {
+        self.cpu.trace = trace;
+    }
+
+    /// Enable or disable caching decoded instructions, which is on by default. Cached
+    /// instructions are dropped when the memory they were decoded from is written, so this only
+    /// affects speed.
+    pub fn set_decode_cache(&mut self, enabled: bool) {
+        self.cpu.decode_cache = enabled;
+    }
+
+    /// Set how many calls can be nested before CALL faults with [`Fault::StackOverflow`],
+    /// between 1 and 16. The default is 16; the COSMAC VIP interpreter allowed 12.
+    pub fn set_stack_depth(&mut self, depth: usize) {
+        self.cpu.stack_depth = depth.clamp(1, STACK_SIZE);
+    }
+
+    pub fn stack_depth(&self) -> usize {
+        self.cpu.stack_depth
+    }
+
+    /// Keep the call stack in memory at 0xEA0, where the COSMAC VIP interpreter kept it, so
+    /// programs that read or change return addresses there work. Off by default.
+    pub fn set_memory_stack(&mut self, enabled: bool) {
+        self.cpu.memory_stack = enabled;
+    }
+
+    pub fn memory_stack(&self) -> bool {
+        self.cpu.memory_stack
+    }
+
+    /// Choose the font `LD F, Vx` points at. It's loaded into memory right away.
+    pub fn set_font(&mut self, font: FontSet) {
+        self.cpu.set_font(font, self.cpu.font_address);
+    }
+
+    pub fn font(&self) -> FontSet {
+        self.cpu.font
+    }
+
+    /// Move the font, which is at 0x000 by default. Some programs expect it at 0x050. It has to
+    /// fit below the program at 0x200.
+    pub fn set_font_address(&mut self, address: u16) -> std::io::Result<()> {
+        if address as usize + FONT_SIZE > PROGRAM_START {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!(
+                    "font at {address:03X} would overlap the program, it can be at most at {:03X}",
+                    PROGRAM_START - FONT_SIZE
+                ),
+            ));
+        }
+        self.cpu.set_font(self.cpu.font, address);
+        Ok(())
+    }
+
+    pub fn font_address(&self) -> u16 {
+        self.cpu.font_address
+    }
+
+    /// Load a ROM file. ROMs can also be loaded from `.gz` files and `.zip` archives, see
+    /// [`crate::rom::read`].
+    pub fn load_rom<P: AsRef<Path>>(&mut self, path: P) -> std::io::Result<()> {
+        let path = path.as_ref();
+        let rom = crate::rom::read(path)?;
+        self.load_rom_bytes(&rom)?;
+        self.rom_path = Some(path.to_path_buf());
+        Ok(())
+    }
+
+    /// Load a ROM image from a reader, e.g. a network stream or an embedded resource.
+    pub fn load_rom_reader<R: Read>(&mut self, reader: R) -> std::io::Result<()> {
+        // read one byte more than fits, so oversized ROMs are still reported as too large
+        let mut rom = Vec::new();
+        reader
+            .take(self.max_rom_size() as u64 + 1)
+            .read_to_end(&mut rom)?;
+        self.load_rom_bytes(&rom)
+    }
+
+    /// Load a ROM image that is already in memory.
+    pub fn load_rom_bytes(&mut self, rom: &[u8]) -> std::io::Result<()> {
+        if rom.len() > self.max_rom_size() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!(
+                    "rom is too large: {} bytes, but programs can be at most {} bytes",
+                    rom.len(),
+                    self.max_rom_size()
+                ),
+            ));
+        }
         if self.loaded {
             self.reset();
         }
-        let path = PathBuf::from(path);
-        let mut file = std::fs::File::open(&path)?;
-        let mut buf: Vec<u8> = Vec::new();
-        let _ = file.read_to_end(&mut buf)?;
-        self.cpu.bus.memory.write_slice(0x200, &buf);
+        let start = self.program_start();
+        self.cpu.bus.memory.write_slice(start as usize, rom);
+        if self.platform() == Platform::Hires && rom.starts_with(&[0x12, 0x60]) {
+            // skip over where the hi-res interpreter extension would have been
+            self.cpu
+                .bus
+                .memory
+                .write_slice(start as usize, &[0x12, 0xC0]);
+        }
+        self.cpu.set_register(Register::Pc, start);
         self.loaded = true;
-        self.rom_path = Some(path);
+        self.rom = Some(rom.to_vec());
+        Ok(())
+    }
+
+    /// Size of the largest program that fits in memory after the program start address.
+    pub fn max_rom_size(&self) -> usize {
+        self.cpu.bus.memory.get().len() - self.program_start() as usize
+    }
+
+    /// Switch to another platform. The machine is reset, and the loaded ROM, if any, is loaded
+    /// again at the new platform's start address.
+    pub fn set_platform(&mut self, platform: Platform) -> std::io::Result<()> {
+        self.cpu.set_platform(platform);
+        match self.rom.clone() {
+            Some(rom) => {
+                let path = self.rom_path.clone();
+                self.load_rom_bytes(&rom)?;
+                self.rom_path = path;
+            }
+            None => self.reset(),
+        }
         Ok(())
     }
 
+    pub fn platform(&self) -> Platform {
+        self.cpu.platform()
+    }
+
+    /// Address the ROM is loaded at and runs from.
+    pub fn program_start(&self) -> u16 {
+        self.cpu.platform().program_start()
+    }
+
     pub fn reload_rom(&mut self) -> std::io::Result<()> {
-        if let Some(rom) = self.rom_path.clone() {
-            self.load_rom(&rom)?;
+        if let Some(path) = self.rom_path.clone() {
+            self.load_rom(&path)?;
+            Ok(())
+        } else if let Some(rom) = self.rom.clone() {
+            self.load_rom_bytes(&rom)?;
             Ok(())
         } else {
-            Err(std::io::Error::new(
-                std::io::ErrorKind::Other,
+            Err(std::io::Error::other(
                 "can't reload rom if no rom is loaded",
             ))
         }
     }
 
+    /// Reset the machine, unloading the ROM. The profile is cleared too.
     pub fn reset(&mut self) {
         self.cpu.reset();
+        self.clear_profile();
+        self.fault = None;
         self.loaded = false;
         self.rom_path = None;
+        self.rom = None;
     }
 
-    pub fn run(&mut self) {
+    /// Run frames as fast as possible until the program faults or reaches a breakpoint.
+    pub fn run(&mut self) -> Stop {
         loop {
-            self.step();
+            if let Some(stop) = self.run_frame().stop {
+                return stop;
+            }
+        }
+    }
+
+    /// Execute one 60 Hz frame's worth of instructions at the configured speed, then tick the
+    /// timers once. Unlike [`Chip8::step`] this doesn't depend on wall clock time.
+    pub fn run_frame(&mut self) -> FrameSummary {
+        let cycles = (self.speed as f64 / FRAME_RATE as f64).round() as u32;
+        self.run_frame_budget(cycles.max(1))
+    }
+
+    /// Execute up to `budget` instructions as one frame, then tick the timers once.
+    ///
+    /// The frame ends early, without ticking the timers, if the program faults or is about to
+    /// execute an instruction at a breakpoint. The first instruction of a frame never stops at a
+    /// breakpoint, so calling this again continues past it. The frame also ends early if the
+    /// program is blocked on Fx0A, since it can't continue until the keys change.
+    pub fn run_frame_budget(&mut self, budget: u32) -> FrameSummary {
+        let mut summary = FrameSummary::default();
+        let generation = self.display_generation();
+        if let Some(fault) = self.fault {
+            summary.stop = Some(Stop::Fault(fault));
+            return summary;
+        }
+        for _ in 0..budget {
+            let pc = self.cpu.register(Register::Pc);
+            if summary.cycles > 0 && self.breakpoints.contains(&pc) {
+                summary.stop = Some(Stop::Breakpoint(pc));
+                break;
+            }
+            if let Err(fault) = self.execute() {
+                summary.stop = Some(Stop::Fault(fault));
+                break;
+            }
+            summary.cycles += 1;
+            if self.cpu.waiting {
+                break;
+            }
+        }
+        if summary.stop.is_none() {
+            self.tick_timers();
         }
+        summary.display_changed = self.display_generation() != generation;
+        summary.sound = self.is_sound_active();
+        summary.waiting_for_key = self.cpu.waiting;
+        summary
     }
 
+    /// Execute a single instruction, ticking the timers based on elapsed real time.
     pub fn step(&mut self) {
-        let ins = self.cpu.fetch();
-        self.cpu.execute(ins);
+        if self.execute().is_ok() && self.cpu.tick() {
+            self.tick_profile();
+        }
+    }
+
+    /// Execute a single instruction without touching the timers, for frontends that tick them
+    /// with [`Chip8::tick_timers`], e.g. using a [`crate::scheduler::Scheduler`].
+    pub fn step_instruction(&mut self) {
+        let _ = self.execute();
+    }
+
+    /// Decrement the delay and sound timers once. Call at 60 Hz of emulated time.
+    pub fn tick_timers(&mut self) {
+        self.cpu.tick_timers();
+        self.tick_profile();
+    }
+
+    fn tick_profile(&mut self) {
+        if let Some(profile) = self.profile.as_mut().filter(|_| self.profiling) {
+            profile.tick();
+        }
+    }
+
+    /// Execute one instruction unless the machine has faulted, recording any new fault.
+    fn execute(&mut self) -> Result<(), Fault> {
+        if let Some(fault) = self.fault {
+            return Err(fault);
+        }
+        let pc = self.cpu.register(Register::Pc);
+        let result = self.cpu.fetch().and_then(|(opcode, instruction)| {
+            self.cpu.execute(opcode, instruction)?;
+            Ok(instruction)
+        });
+        self.fault = result.err();
+        let instruction = result?;
+        if let Some(profile) = self.profile.as_mut().filter(|_| self.profiling) {
+            let depth = self.cpu.register(Register::Sp) as usize;
+            profile.record(pc, instruction, depth, self.cpu.waiting);
+        }
+        Ok(())
+    }
+
+    /// Start or stop counting executed instructions. Stopping keeps what was counted so far,
+    /// and starting again adds to it.
+    pub fn set_profiling(&mut self, enabled: bool) {
+        self.profiling = enabled;
+        if enabled && self.profile.is_none() {
+            self.profile = Some(Profile::default());
+        }
+    }
+
+    pub fn profiling(&self) -> bool {
+        self.profiling
+    }
+
+    /// What was counted while profiling, or None if profiling was never started.
+    pub fn profile(&self) -> Option<&Profile> {
+        self.profile.as_ref()
+    }
+
+    /// Discard what was counted. Profiling continues if it's on.
+    pub fn clear_profile(&mut self) {
+        self.profile = self.profiling.then(Profile::default);
+    }
+
+    /// The opcode at `address` and its disassembly, or None if it's past the end of memory.
+    pub fn disassemble(&self, address: u16) -> Option<(u16, String)> {
+        let bytes = self.read_memory(address as usize, 2)?;
+        let opcode = u16::from_be_bytes([bytes[0], bytes[1]]);
+        Some((
+            opcode,
+            Instruction::decode(opcode, self.platform()).to_string(),
+        ))
+    }
+
+    /// The fault that stopped the program, if any. Cleared by resetting or loading a ROM or
+    /// save state.
+    pub fn fault(&self) -> Option<Fault> {
+        self.fault
+    }
+
+    /// Stop [`Chip8::run_frame`] before the instruction at `pc` executes.
+    pub fn add_breakpoint(&mut self, pc: u16) {
+        self.breakpoints.insert(pc);
+    }
+
+    pub fn remove_breakpoint(&mut self, pc: u16) {
+        self.breakpoints.remove(&pc);
+    }
+
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear();
+    }
+
+    /// Seed the random number generator used by the RND instruction.
+    pub fn seed(&mut self, seed: u64) {
+        self.cpu.seed(seed);
+    }
+
+    /// Queue a [`HookEvent::Pc`] whenever the instruction at `pc` is about to execute.
+    pub fn add_pc_hook(&mut self, pc: u16) -> HookId {
+        self.cpu.hooks.add_pc(pc)
+    }
+
+    /// Queue a [`HookEvent::Write`] whenever the program writes to `start..=end`.
+    pub fn add_write_hook(&mut self, start: u16, end: u16) -> HookId {
+        self.cpu.hooks.add_write(start, end)
+    }
+
+    /// Queue a [`HookEvent::Read`] whenever the program reads from `start..=end`.
+    pub fn add_read_hook(&mut self, start: u16, end: u16) -> HookId {
+        self.cpu.hooks.add_read(start, end)
+    }
+
+    /// Remove a hook added by one of the `add_*_hook` functions. Other hooks on the same
+    /// addresses are kept.
+    pub fn remove_hook(&mut self, id: HookId) {
+        self.cpu.hooks.remove(id);
+    }
+
+    /// Remove every hook and discard pending events.
+    pub fn clear_hooks(&mut self) {
+        self.cpu.hooks.clear();
+    }
+
+    /// Returns true if any of `hooks` fired since their events were last taken.
+    pub fn has_hook_events(&self, hooks: &[HookId]) -> bool {
+        self.cpu.hooks.has_events(hooks)
+    }
+
+    /// Take the events fired by `hooks` since the last call, oldest first, with the hook that
+    /// fired each one. Events from other hooks stay queued for whoever added them.
+    pub fn take_hook_events(&mut self, hooks: &[HookId]) -> Vec<(HookId, HookEvent)> {
+        self.cpu.hooks.take_events(hooks)
+    }
+
+    pub fn get_register(&self, register: Register) -> u16 {
+        self.cpu.register(register)
     }
 
+    pub fn set_register(&mut self, register: Register, value: u16) {
+        self.cpu.set_register(register, value);
+    }
+
+    /// Press or release a key. Fx0A only sees keys pressed while it's waiting, so a key
+    /// held from before doesn't satisfy it.
     pub fn set_key_state(&mut self, key: usize, state: bool) {
         self.cpu.bus.keyboard.set_key_state(key, state);
     }
 
+    pub fn get_key_state(&self, key: usize) -> bool {
+        self.cpu.bus.keyboard.is_pressed(key as u8)
+    }
+
+    /// Press or release a key on the second CHIP-8X keypad.
+    pub fn set_keypad2_state(&mut self, key: usize, state: bool) {
+        self.cpu.bus.keyboard.set_keypad2_state(key, state);
+    }
+
+    /// Get the byte last written to the CHIP-8X I/O port by `FxF8`, which the VP-595 sound
+    /// board uses as the tone frequency. Returns None if nothing was written since the last
+    /// call.
+    pub fn take_io_output(&mut self) -> Option<u8> {
+        self.cpu.io_output.take()
+    }
+
+    /// Provide the byte the next CHIP-8X `FxFB` reads from the I/O port, replacing any byte
+    /// not read yet.
+    pub fn set_io_input(&mut self, byte: u8) {
+        self.cpu.io_input = Some(byte);
+    }
+
+    /// Get the keys the program has checked with SKP, SKNP or Fx0A since the last call, with
+    /// bit n set for key n. Fx0A counts as checking every key.
+    pub fn take_polled_keys(&mut self) -> u16 {
+        self.cpu.bus.keyboard.take_polled()
+    }
+
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.cpu.quirks = quirks;
+    }
+
+    pub fn quirks(&self) -> Quirks {
+        self.cpu.quirks
+    }
+
     pub fn get_display(&self) -> &[u8] {
         self.cpu.bus.display.get()
     }
 
+    /// Get the colour of each pixel on CHIP-8X, or None on other platforms, which are
+    /// monochrome.
+    pub fn get_display_colors(&self) -> Option<DisplayColors> {
+        (self.platform() == Platform::Chip8x).then(|| self.cpu.bus.display.colors())
+    }
+
+    /// Get the pixels as ARGB while a MegaChip program is in MegaChip mode, or None otherwise.
+    pub fn get_display_rgba(&self) -> Option<&[u32]> {
+        self.cpu.bus.display.rgba()
+    }
+
+    /// Get the pixels as RGB when the display is in colour, e.g. for screenshots, or None when
+    /// it's monochrome and the frontend colours [`Chip8::get_display`] itself.
+    pub fn get_display_rgb(&self) -> Option<Vec<[u8; 3]>> {
+        if let Some(colors) = self.get_display_colors() {
+            let pixels = self.get_display().iter().zip(&colors.foreground);
+            let code = |(lit, code): (&u8, &u8)| if *lit != 0 { *code } else { colors.background };
+            let rgb = pixels.map(|pixel| COLORS[code(pixel) as usize & 7]);
+            return Some(rgb.collect());
+        }
+        let pixels = self.get_display_rgba()?;
+        let rgb = pixels.iter().map(|pixel| {
+            let [_, r, g, b] = pixel.to_be_bytes();
+            [r, g, b]
+        });
+        Some(rgb.collect())
+    }
+
+    /// Get the display as one bitmask per row, with the leftmost pixel in the most
+    /// significant bit.
+    pub fn get_display_rows(&self) -> &[u64] {
+        self.cpu.bus.display.rows()
+    }
+
+    /// A counter that changes whenever any pixel does. Frontends can compare it with the value
+    /// they last drew to skip redrawing an unchanged display.
+    pub fn display_generation(&self) -> u64 {
+        self.cpu.bus.display.generation()
+    }
+
+    /// Get display width and height in pixels.
+    pub fn get_display_size(&self) -> (usize, usize) {
+        self.cpu.bus.display.size()
+    }
+
+    /// Read `length` bytes of memory starting at `address`, or None if out of range.
+    pub fn read_memory(&self, address: usize, length: usize) -> Option<&[u8]> {
+        self.cpu
+            .bus
+            .memory
+            .get()
+            .get(address..address.checked_add(length)?)
+    }
+
+    /// Write `data` into memory starting at `address`. Returns false if out of range.
+    pub fn write_memory(&mut self, address: usize, data: &[u8]) -> bool {
+        match address.checked_add(data.len()) {
+            Some(end) if end <= self.cpu.bus.memory.get().len() => {
+                self.cpu.bus.memory.write_slice(address, data);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Returns true while the sound timer is running and the buzzer should sound.
+    pub fn is_sound_active(&self) -> bool {
+        self.cpu.sound_timer() > 0
+    }
+
+    /// The MegaChip digitized sound that's playing, if any.
+    pub fn sample(&self) -> Option<&Sample> {
+        self.cpu.sample.as_ref()
+    }
+
+    /// Fill `out` with the next part of the MegaChip digitized sound at `output_rate` Hz, or
+    /// with silence if none is playing. Returns false once there's nothing left to play.
+    pub fn render_sample(&mut self, out: &mut [i16], output_rate: u32) -> bool {
+        let playing = match &mut self.cpu.sample {
+            Some(sample) => sample.fill(out, output_rate),
+            None => {
+                out.fill(0);
+                false
+            }
+        };
+        if !playing {
+            self.cpu.sample = None;
+        }
+        playing
+    }
+
+    /// Serialize the full machine state into a byte buffer.
+    pub fn save_state(&self) -> std::io::Result<Vec<u8>> {
+        let mut buf = Vec::new();
+        buf.extend_from_slice(STATE_MAGIC);
+        buf.write_u8(STATE_VERSION)?;
+        buf.write_u32::<BigEndian>(self.speed)?;
+        self.cpu.save_state(&mut buf)?;
+        Ok(buf)
+    }
+
+    /// Restore machine state from a buffer produced by [`Chip8::save_state`].
+    pub fn load_state(&mut self, state: &[u8]) -> std::io::Result<()> {
+        let mut r = state;
+        let mut magic = [0; 4];
+        r.read_exact(&mut magic)?;
+        if &magic != STATE_MAGIC || r.read_u8()? != STATE_VERSION {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "not a compatible save state",
+            ));
+        }
+        let speed = r.read_u32::<BigEndian>()?;
+        self.cpu.load_state(&mut r)?;
+        self.speed = speed;
+        self.fault = None;
+        self.loaded = true;
+        Ok(())
+    }
+
     pub fn is_loaded(&self) -> bool {
         self.loaded
     }
 
+    /// The loaded ROM image.
+    pub fn rom(&self) -> Option<&[u8]> {
+        self.rom.as_deref()
+    }
+
+    /// Path of the loaded ROM, if it was loaded from a file.
+    pub fn rom_path(&self) -> Option<&Path> {
+        self.rom_path.as_deref()
+    }
+
     pub fn get_rom_name(&self) -> Option<String> {
         if let Some(path) = self.rom_path.clone() {
             let name = path.file_name().unwrap().to_string_lossy().to_string();
@@ -95,6 +716,415 @@ impl Default for Chip8 {
             cpu: Cpu::new(),
             loaded: false,
             rom_path: None,
+            rom: None,
+            fault: None,
+            breakpoints: HashSet::new(),
+            profile: None,
+            profiling: false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::profiler::InstructionClass;
+
+    #[test]
+    fn save_load_state() {
+        let mut chip8 = Chip8::default();
+        chip8.set_trace(false);
+        // LD V3, 0x42; CALL 0x208; ...; LD I, 0x123
+        chip8
+            .cpu
+            .bus
+            .memory
+            .write_slice(0x200, &[0x63, 0x42, 0x22, 0x08]);
+        chip8.cpu.bus.memory.write_slice(0x208, &[0xA1, 0x23]);
+        chip8.loaded = true;
+        for _ in 0..3 {
+            chip8.step();
+        }
+        let state = chip8.save_state().unwrap();
+
+        let mut restored = Chip8::default();
+        restored.load_state(&state).unwrap();
+        assert!(restored.is_loaded());
+        assert_eq!(restored.save_state().unwrap(), state);
+
+        assert!(restored.load_state(&state[..10]).is_err());
+        assert!(restored.load_state(b"nope").is_err());
+    }
+
+    #[test]
+    fn load_state_leaves_megachip() {
+        let mut chip8 = Chip8::default();
+        chip8.set_trace(false);
+        chip8.loaded = true;
+        chip8.set_register(Register::V(3), 0x42);
+        let state = chip8.save_state().unwrap();
+
+        // MEGAON
+        let mut mega = Chip8::default();
+        mega.set_trace(false);
+        mega.set_platform(Platform::MegaChip).unwrap();
+        mega.load_rom_bytes(&[0x00, 0x11]).unwrap();
+        mega.step();
+        assert_eq!(mega.get_display_size(), (256, 192));
+
+        mega.load_state(&state).unwrap();
+        assert_eq!(mega.platform(), Platform::Chip8);
+        assert_eq!(mega.get_display_size(), (64, 32));
+        assert_eq!(mega.get_display_rgba(), None);
+        assert_eq!(mega.save_state().unwrap(), state);
+    }
+
+    #[test]
+    fn rom_size() {
+        let mut chip8 = Chip8::default();
+        let max = chip8.max_rom_size();
+        assert_eq!(max, 0xE00);
+        assert!(chip8.load_rom_bytes(&vec![0; max]).is_ok());
+        assert!(chip8.load_rom_bytes(&vec![0; max + 1]).is_err());
+        assert!(chip8.load_rom_reader(&[0x12, 0x00][..]).is_ok());
+        assert_eq!(chip8.read_memory(0x200, 2), Some(&[0x12, 0x00][..]));
+        assert!(chip8.load_rom_reader(&vec![0; max + 10][..]).is_err());
+    }
+
+    #[test]
+    fn key_wait() {
+        let mut chip8 = Chip8::default();
+        chip8.set_trace(false);
+        // LD V1, K; JP 0x202
+        let rom = [0xF1, 0x0A, 0x12, 0x02];
+        chip8.load_rom_bytes(&rom).unwrap();
+
+        // a key held from before the wait doesn't count
+        chip8.set_key_state(3, true);
+        assert!(chip8.run_frame_budget(5).waiting_for_key);
+        assert_eq!(chip8.take_polled_keys(), 0xFFFF);
+        assert_eq!(chip8.take_polled_keys(), 0);
+        chip8.set_key_state(3, false);
+        chip8.set_key_state(7, true);
+        assert!(!chip8.run_frame_budget(5).waiting_for_key);
+        assert_eq!(chip8.get_register(Register::V(1)), 7);
+
+        chip8.set_quirks(Quirks { key_release: true });
+        chip8.load_rom_bytes(&rom).unwrap();
+        assert!(chip8.run_frame_budget(5).waiting_for_key);
+        chip8.set_key_state(4, true);
+        assert!(chip8.run_frame_budget(5).waiting_for_key);
+        chip8.set_key_state(4, false);
+        assert!(!chip8.run_frame_budget(5).waiting_for_key);
+        assert_eq!(chip8.get_register(Register::V(1)), 4);
+    }
+
+    #[test]
+    fn run_frame_stops() {
+        let mut chip8 = Chip8::default();
+        chip8.set_trace(false);
+        // DRW V0, V0, 1; LD V0, 1; LD V1, 2; LD V2, K; RET
+        chip8
+            .load_rom_bytes(&[0xD0, 0x01, 0x60, 0x01, 0x61, 0x02, 0xF2, 0x0A, 0x00, 0xEE])
+            .unwrap();
+        chip8.add_breakpoint(0x204);
+
+        let summary = chip8.run_frame_budget(10);
+        assert_eq!(summary.cycles, 2);
+        assert!(summary.display_changed);
+        assert_eq!(summary.stop, Some(Stop::Breakpoint(0x204)));
+
+        let summary = chip8.run_frame_budget(10);
+        assert_eq!(summary.cycles, 2);
+        assert!(!summary.display_changed);
+        assert!(summary.waiting_for_key);
+        assert_eq!(summary.stop, None);
+
+        chip8.set_key_state(5, true);
+        let summary = chip8.run_frame_budget(10);
+        assert_eq!(chip8.get_register(Register::V(2)), 5);
+        assert_eq!(
+            summary.stop,
+            Some(Stop::Fault(Fault::StackUnderflow { address: 0x208 }))
+        );
+        assert_eq!(chip8.run_frame().cycles, 0);
+    }
+
+    #[test]
+    fn hooks() {
+        let mut chip8 = Chip8::default();
+        chip8.set_trace(false);
+        // LD V0, 5; LD I, 0x300; LD [I], V0; JP 0x206
+        let rom = [0x60, 0x05, 0xA3, 0x00, 0xF0, 0x55, 0x12, 0x06];
+        chip8.load_rom_bytes(&rom).unwrap();
+        let first = chip8.add_write_hook(0x300, 0x300);
+        let second = chip8.add_write_hook(0x300, 0x300);
+        let pc = chip8.add_pc_hook(0x204);
+
+        // removing one hook leaves the other on the same address
+        chip8.remove_hook(first);
+        for _ in 0..3 {
+            chip8.step();
+        }
+        let write = HookEvent::Write {
+            address: 0x300,
+            value: 5,
+        };
+        assert_eq!(chip8.take_hook_events(&[second]), [(second, write)]);
+        // taking one hook's events leaves the others queued
+        assert!(chip8.has_hook_events(&[pc]));
+        assert_eq!(chip8.take_hook_events(&[pc]), [(pc, HookEvent::Pc(0x204))]);
+
+        chip8.remove_hook(second);
+        chip8.load_rom_bytes(&rom).unwrap();
+        for _ in 0..3 {
+            chip8.step();
         }
+        assert!(!chip8.has_hook_events(&[second]));
+        assert!(chip8.has_hook_events(&[pc]));
+    }
+
+    #[test]
+    fn call_stack() {
+        let mut chip8 = Chip8::default();
+        chip8.set_trace(false);
+        chip8.set_stack_depth(2);
+        // CALL 0x200
+        chip8.load_rom_bytes(&[0x22, 0x00]).unwrap();
+        assert_eq!(
+            chip8.run_frame_budget(10).stop,
+            Some(Stop::Fault(Fault::StackOverflow { address: 0x200 }))
+        );
+        assert_eq!(chip8.get_register(Register::Sp), 2);
+
+        // CALL 0x206; LD V0, 1; JP 0x204
+        // LD I, 0xEA1; LD V0, 4; LD [I], V0; RET
+        let rom = [
+            0x22, 0x06, 0x60, 0x01, 0x12, 0x04, 0xAE, 0xA1, 0x60, 0x04, 0xF0, 0x55, 0x00, 0xEE,
+        ];
+        chip8.set_memory_stack(true);
+        chip8.load_rom_bytes(&rom).unwrap();
+        chip8.run_frame_budget(1);
+        assert_eq!(chip8.read_memory(0xEA0, 2), Some(&[0x02, 0x02][..]));
+        // the subroutine changes its return address to skip LD V0, 1
+        chip8.run_frame_budget(10);
+        assert_eq!(chip8.get_register(Register::Pc), 0x204);
+        assert_eq!(chip8.get_register(Register::V(0)), 4);
+    }
+
+    #[test]
+    fn font() {
+        let mut chip8 = Chip8::default();
+        chip8.set_trace(false);
+        chip8.set_font(FontSet::Eti660);
+        chip8.set_font_address(0x050).unwrap();
+        assert!(chip8.set_font_address(0x1A0).is_err());
+        // LD V0, 0xB; LD F, V0
+        chip8.load_rom_bytes(&[0x60, 0x0B, 0xF0, 0x29]).unwrap();
+        // loading again resets the machine, which keeps the font
+        chip8.load_rom_bytes(&[0x60, 0x0B, 0xF0, 0x29]).unwrap();
+        chip8.run_frame_budget(2);
+
+        assert_eq!(chip8.get_register(Register::I), 0x050 + 0xB * 5);
+        assert_eq!(
+            chip8.read_memory(0x050 + 0xB * 5, 5),
+            Some(&[0x80, 0x80, 0xE0, 0xA0, 0xE0][..])
+        );
+        assert_eq!(chip8.read_memory(0, 5), Some(&[0; 5][..]));
+
+        // LD V0, 8; LD HF, V0; LD V1, 0; DRW V1, V1, 10
+        chip8
+            .load_rom_bytes(&[0x60, 0x08, 0xF0, 0x30, 0x61, 0x00, 0xD1, 0x1A])
+            .unwrap();
+        chip8.run_frame_budget(4);
+        assert_eq!(chip8.get_register(Register::I), 0x050 + 80 + 8 * 10);
+        let row = |y: usize| &chip8.get_display()[y * 64..y * 64 + 8];
+        // 0x3C, 0x7E, 0xC3, ...
+        assert_eq!(row(0), [0, 0, 1, 1, 1, 1, 0, 0]);
+        assert_eq!(row(1), [0, 1, 1, 1, 1, 1, 1, 0]);
+        assert_eq!(row(9), [0, 0, 1, 1, 1, 1, 0, 0]);
+    }
+
+    #[test]
+    fn platforms() {
+        let mut chip8 = Chip8::default();
+        chip8.set_trace(false);
+        // LD V0, 5
+        chip8.load_rom_bytes(&[0x60, 0x05]).unwrap();
+        chip8.set_platform(Platform::Eti660).unwrap();
+        assert_eq!(chip8.max_rom_size(), 0xA00);
+        assert_eq!(chip8.get_register(Register::Pc), 0x600);
+        chip8.run_frame_budget(1);
+        assert_eq!(chip8.get_register(Register::V(0)), 5);
+
+        // JP 0x260, then at 0x2C0: CLS (hires); JP 0x2C2
+        let mut rom = vec![0; 0xC4];
+        rom[..2].copy_from_slice(&[0x12, 0x60]);
+        rom[0xC0..].copy_from_slice(&[0x02, 0x30, 0x12, 0xC2]);
+        chip8.set_platform(Platform::Hires).unwrap();
+        chip8.load_rom_bytes(&rom).unwrap();
+        assert_eq!(chip8.get_display_size(), (64, 64));
+        assert_eq!(chip8.run_frame_budget(3).stop, None);
+        assert_eq!(chip8.get_register(Register::Pc), 0x2C2);
+
+        let state = chip8.save_state().unwrap();
+        chip8.set_platform(Platform::Chip8).unwrap();
+        assert_eq!(chip8.get_display_size(), (64, 32));
+        chip8.load_state(&state).unwrap();
+        assert_eq!(chip8.platform(), Platform::Hires);
+        assert_eq!(chip8.get_display_size(), (64, 64));
+    }
+
+    #[test]
+    fn chip8x() {
+        let mut chip8 = Chip8::default();
+        chip8.set_trace(false);
+        // LD V0, 0x10; LD V1, 0; LD V2, 4; COL V0, V2, 0; BGCYCLE; LD V3, 7; OUT V3; IN V4;
+        // SKP2 V3; JP 0x312; JP 0x314
+        let rom = [
+            0x60, 0x10, 0x61, 0x00, 0x62, 0x04, 0xB0, 0x20, 0x02, 0xA0, 0x63, 0x07, 0xF3, 0xF8,
+            0xF4, 0xFB, 0xE3, 0xF2, 0x13, 0x12, 0x13, 0x14,
+        ];
+        chip8.load_rom_bytes(&rom[6..8]).unwrap();
+        assert!(matches!(
+            chip8.run_frame_budget(1).stop,
+            Some(Stop::Fault(Fault::InvalidInstruction { .. }))
+        ));
+        assert_eq!(chip8.get_display_colors(), None);
+
+        chip8.set_platform(Platform::Chip8x).unwrap();
+        chip8.load_rom_bytes(&rom).unwrap();
+        assert!(chip8.run_frame_budget(10).waiting_for_key);
+        let colors = chip8.get_display_colors().unwrap();
+        // columns 0 and 1 of the top 4 rows are green, the rest still red
+        assert_eq!(colors.foreground[0], 4);
+        assert_eq!(colors.foreground[3 * 64 + 15], 4);
+        assert_eq!(colors.foreground[16], 1);
+        assert_eq!(colors.foreground[4 * 64], 1);
+        assert_eq!(colors.background, 0);
+        assert_eq!(chip8.take_io_output(), Some(7));
+        assert_eq!(chip8.take_io_output(), None);
+
+        chip8.set_io_input(9);
+        chip8.set_keypad2_state(7, true);
+        chip8.run_frame_budget(3);
+        assert_eq!(chip8.get_register(Register::V(4)), 9);
+        assert_eq!(chip8.get_register(Register::Pc), 0x314);
+
+        // LD I, 0x000; DRW V0, V0, 1, the top row of a 0
+        chip8.load_rom_bytes(&[0xA0, 0x00, 0xD0, 0x01]).unwrap();
+        chip8.run_frame_budget(2);
+        let rgb = chip8.get_display_rgb().unwrap();
+        let background = chip8.get_display_colors().unwrap().background;
+        assert_eq!(rgb[0], COLORS[1]);
+        assert_eq!(rgb[4], COLORS[background as usize]);
+    }
+
+    #[test]
+    fn megachip() {
+        let mut chip8 = Chip8::default();
+        chip8.set_trace(false);
+        // MEGAON; LDHI I, 0x000240; LDPAL 1; SPRW 2; SPRH 1; LD I, 0x244; LD V0, 10;
+        // DRW V0, V0; CLS; DRW V0, V0; CCOL 1; DRW V0, V0; LD I, 0x248; DIGISND 1; JP 0x21E
+        let mut rom = vec![
+            0x00, 0x11, 0x01, 0x00, 0x02, 0x40, 0x02, 0x01, 0x03, 0x02, 0x04, 0x01, 0xA2, 0x44,
+            0x60, 0x0A, 0xD0, 0x01, 0x00, 0xE0, 0xD0, 0x01, 0x09, 0x01, 0xD0, 0x01, 0xA2, 0x48,
+            0x06, 0x01, 0x12, 0x1E,
+        ];
+        rom.resize(0x40, 0);
+        // red, a 2x1 sprite of it, and 2 samples at 8 kHz
+        rom.extend_from_slice(&[0xFF, 0xFF, 0x00, 0x00, 0x01, 0x01, 0x00, 0x00]);
+        rom.extend_from_slice(&[0x1F, 0x40, 0x00, 0x00, 0x02, 0x00, 0x80, 0xFF]);
+        chip8.load_rom_bytes(&rom).unwrap();
+        assert!(matches!(
+            chip8.run_frame_budget(1).stop,
+            Some(Stop::Fault(Fault::InvalidInstruction { .. }))
+        ));
+
+        chip8.set_platform(Platform::MegaChip).unwrap();
+        assert_eq!(chip8.max_rom_size(), 0x100_0000 - 0x200);
+        chip8.run_frame_budget(16);
+        assert_eq!(chip8.get_display_size(), (256, 192));
+        assert_eq!(chip8.get_register(Register::Pc), 0x21E);
+        let pixels = chip8.get_display_rgba().unwrap();
+        assert_eq!(pixels[10 * 256 + 11], 0xFFFF_0000);
+        assert_eq!(pixels[10 * 256 + 12], 0xFF00_0000);
+        let rgb = chip8.get_display_rgb().unwrap();
+        assert_eq!(rgb[10 * 256 + 11], [0xFF, 0, 0]);
+        assert_eq!(chip8.get_display()[10 * 256 + 10], 1);
+        // the second sprite drawn after CLS landed on the first in colour 1
+        assert_eq!(chip8.get_register(Register::V(0xF)), 1);
+        assert!(chip8.save_state().is_err());
+
+        let mut out = [1; 4];
+        assert!(!chip8.render_sample(&mut out, 8000));
+        assert_eq!(out, [0, 0x7F00, 0, 0]);
+        assert_eq!(chip8.sample(), None);
+
+        // memory goes on past 64 KiB, but PC can't: LD V0, 1; SE V0, 1
+        chip8.write_memory(0xFFFC, &[0x60, 0x01, 0x30, 0x01]);
+        chip8.set_register(Register::Pc, 0xFFFC);
+        chip8.step();
+        chip8.step();
+        assert_eq!(
+            chip8.fault(),
+            Some(Fault::MemoryOutOfRange {
+                address: 0xFFFE,
+                access: 0x10002
+            })
+        );
+
+        chip8.set_platform(Platform::Chip8).unwrap();
+        assert_eq!(chip8.get_display_rgba(), None);
+        assert_eq!(chip8.get_display_rgb(), None);
+        assert_eq!(chip8.get_display_size(), (64, 32));
+    }
+
+    #[test]
+    fn profiler() {
+        let mut chip8 = Chip8::default();
+        chip8.set_trace(false);
+        // LD V0, 3; LD DT, V0; CALL 0x20A; LD V1, K; JP 0x208
+        // 0x20A: LD V2, DT; SE V2, 0; JP 0x20A; RET
+        let rom = [
+            0x60, 0x03, 0xF0, 0x15, 0x22, 0x0A, 0xF1, 0x0A, 0x12, 0x08, 0xF2, 0x07, 0x32, 0x00,
+            0x12, 0x0A, 0x00, 0xEE,
+        ];
+        chip8.load_rom_bytes(&rom).unwrap();
+        chip8.set_profiling(true);
+        for _ in 0..5 {
+            chip8.run_frame_budget(10);
+        }
+
+        let profile = chip8.profile().unwrap();
+        assert_eq!(profile.frames(), 5);
+        assert_eq!(profile.timer_wait_frames(), 3);
+        assert_eq!(profile.key_wait_frames(), 2);
+        assert_eq!(profile.count(0x200), 1);
+        // Fx0A is counted once however long it waits
+        assert_eq!(profile.count(0x206), 1);
+        assert!(profile.count(0x20A) > 5);
+        assert_eq!(profile.max_count(), profile.count(0x20A));
+        assert_eq!(
+            profile.class_count(InstructionClass::Timer),
+            profile.count(0x20A) + 1
+        );
+
+        let mut csv = Vec::new();
+        profile
+            .write_csv(&mut csv, |address| chip8.disassemble(address))
+            .unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        assert!(csv.starts_with("address,opcode,instruction,count\n"));
+        assert!(csv.contains("\n0x20A,F207,\"LD V2, DT\","));
+        let mut folded = Vec::new();
+        profile.write_folded(&mut folded, "rom").unwrap();
+        let folded = String::from_utf8(folded).unwrap();
+        let lines: Vec<&str> = folded.lines().collect();
+        assert_eq!(lines[0], "rom 4");
+        assert!(lines[1].starts_with("rom;sub_20A "));
+
+        chip8.reset();
+        assert!(chip8.profile().unwrap().counts().is_empty());
     }
 }
@@ -0,0 +1,29 @@
+//! Colours of the VP-590 colour board used by CHIP-8X.
+
+/// The eight colours as RGB, in the order of their codes: black, red, blue, violet, green,
+/// yellow, aqua and white.
+pub const COLORS: [[u8; 3]; 8] = [
+    [0x00, 0x00, 0x00],
+    [0xFF, 0x00, 0x00],
+    [0x00, 0x00, 0xFF],
+    [0xFF, 0x00, 0xFF],
+    [0x00, 0xFF, 0x00],
+    [0xFF, 0xFF, 0x00],
+    [0x00, 0xFF, 0xFF],
+    [0xFF, 0xFF, 0xFF],
+];
+
+/// Background colours `02A0` cycles through: blue, black, green and red.
+pub const BACKGROUNDS: [u8; 4] = [2, 0, 4, 1];
+
+/// Colour of every zone after a reset, red.
+pub const DEFAULT_FOREGROUND: u8 = 1;
+
+/// Colours of the CHIP-8X display, as codes indexing [`COLORS`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DisplayColors {
+    /// Colour of each pixel when it's lit, in the same order as the pixels
+    pub foreground: Vec<u8>,
+    /// Colour of unlit pixels
+    pub background: u8,
+}
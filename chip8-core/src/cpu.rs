@@ -1,12 +1,46 @@
-use std::time::Instant;
+use std::{
+    io::{Read, Write},
+    time::Instant,
+};
 
-use byteorder::{BigEndian, ReadBytesExt};
-use rand::Rng;
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use rand::{rngs::StdRng, Rng, SeedableRng};
 
-use crate::bus::Bus;
+use crate::{
+    bus::Bus,
+    chip8::{Fault, Quirks, Register},
+    display::{self, Display},
+    font::{FontSet, FONT_SIZE, SMALL_FONT_SIZE},
+    hooks::Hooks,
+    instructions::Instruction,
+    megachip::{BlendMode, Sample},
+    memory::Memory,
+    platform::Platform,
+};
 
 const TIMERS_WAIT_MICROS: u128 = ((1f64 / 60f64) * 1_000_000f64) as u128;
 
+/// Maximum depth of the call stack, which is also the number of slots stored in a save state.
+pub(crate) const STACK_SIZE: usize = 16;
+
+/// Where the COSMAC VIP interpreter keeps its call stack, used when the stack is kept in
+/// memory. Each return address takes two bytes, big-endian, starting with the outermost call.
+pub(crate) const STACK_ADDRESS: u16 = 0xEA0;
+
+/// Print to stdout only when tracing is enabled on the given cpu.
+macro_rules! trace {
+    ($cpu:expr) => {
+        if $cpu.trace {
+            println!();
+        }
+    };
+    ($cpu:expr, $($arg:tt)*) => {
+        if $cpu.trace {
+            println!($($arg)*);
+        }
+    };
+}
+
 #[derive(Debug)]
 pub(crate) struct Cpu {
     pub bus: Bus,
@@ -14,8 +48,8 @@ pub(crate) struct Cpu {
     pc: u16,
     /// CPU registers
     vx: [u8; 16],
-    /// I register
-    i: u16,
+    /// I register, 24 bits wide for MegaChip and 12 bits used otherwise
+    i: u32,
     /// Sound timer
     st: u8,
     /// Delay timer
@@ -25,7 +59,37 @@ pub(crate) struct Cpu {
     stack: Vec<u16>,
     /// Time since last DT/ST tick
     last_tick_time: Instant,
-
+    /// Source of random numbers for RND
+    rng: StdRng,
+    /// Whether the last instruction was Fx0A and no key was pressed
+    pub waiting: bool,
+    /// Key pressed while waiting in Fx0A, which completes when it's released with the
+    /// key release quirk
+    key_wait: Option<u8>,
+    pub quirks: Quirks,
+    /// Number of nested calls allowed before CALL faults, at most `STACK_SIZE`
+    pub stack_depth: usize,
+    /// Also write return addresses to memory at `STACK_ADDRESS`, and return to the address
+    /// found there
+    pub memory_stack: bool,
+    /// Font loaded into memory on reset
+    pub font: FontSet,
+    /// Where the font is loaded, and where `LD F, Vx` points
+    pub font_address: u16,
+    /// Last byte written to the CHIP-8X I/O port, until it's taken
+    pub io_output: Option<u8>,
+    /// Byte for the next CHIP-8X input from the I/O port
+    pub io_input: Option<u8>,
+    /// Machine being emulated, set with `set_platform`
+    platform: Platform,
+    /// MegaChip digitized sound started by `060n`, until it finishes or `0700` stops it
+    pub sample: Option<Sample>,
+    /// Print CPU state and instructions as they execute
+    pub trace: bool,
+    /// Addresses that queue an event when executed or written
+    pub hooks: Hooks,
+    /// Reuse decoded instructions instead of decoding every time one executes
+    pub decode_cache: bool,
 }
 
 impl Cpu {
@@ -39,177 +103,452 @@ impl Cpu {
             pc: 0x200,
             stack: Vec::with_capacity(16),
             last_tick_time: Instant::now(),
+            rng: StdRng::from_entropy(),
+            waiting: false,
+            key_wait: None,
+            quirks: Quirks::default(),
+            stack_depth: STACK_SIZE,
+            memory_stack: false,
+            font: FontSet::default(),
+            font_address: 0,
+            io_output: None,
+            io_input: None,
+            platform: Platform::default(),
+            sample: None,
+            trace: true,
+            hooks: Hooks::default(),
+            decode_cache: true,
         }
     }
 
     pub fn reset(&mut self) {
+        let trace = self.trace;
+        let decode_cache = self.decode_cache;
+        let quirks = self.quirks;
+        let (stack_depth, memory_stack) = (self.stack_depth, self.memory_stack);
+        let (font, font_address) = (self.font, self.font_address);
+        let platform = self.platform;
+        let mut hooks = std::mem::take(&mut self.hooks);
+        hooks.clear_events();
+        // keep the display, cleared, so its generation counter never goes backwards
+        let mut display = std::mem::replace(&mut self.bus.display, Display::new());
+        display.set_mega(false);
+        display.clear();
+        display.reset_colors();
         *self = Cpu::new();
+        self.trace = trace;
+        self.decode_cache = decode_cache;
+        self.quirks = quirks;
+        self.stack_depth = stack_depth;
+        self.memory_stack = memory_stack;
+        self.set_platform(platform);
+        self.set_font(font, font_address);
+        self.pc = platform.program_start();
+        self.hooks = hooks;
+        self.bus.display = display;
     }
 
-    pub fn fetch(&mut self) -> u16 {
-        let mut instruction = self.bus.memory.read_slice(self.pc as usize, 2);
-        instruction.read_u16::<BigEndian>().unwrap()
+    pub fn platform(&self) -> Platform {
+        self.platform
     }
 
-    pub fn execute(&mut self, instruction: u16) {
-        let nnn = instruction & 0x0FFF; // 12-bit address, lower 12 bits of instruction
-        let kk = (instruction & 0x00FF) as u8; // 8-bit value, lower 8 bits of instruction
-        let n = (instruction & 0x000F) as u8; // 4-bit value, lowest 4 bits of instruction
-        let x = ((instruction & 0x0F00) >> 8) as u8; // 4-bit value, lower 4 bits of upper byte
-        let y = ((instruction & 0x00F0) >> 4) as u8; // 4-bit value, upper 4 bits of lower byte
-
-        self.print_state();
-        println!("Instruction: 0x{instruction:04X}");
-
-        match (instruction & 0xF000) >> 12 {
-            0x00 => match kk {
-                0xE0 => {
-                    println!("CLS");
-                    self.bus.display.clear();
-                    self.pc += 2;
-                }
-                0xEE => {
-                    println!("RET");
-                    self.pc = self.stack.pop().unwrap();
-                }
-                _ => panic!("unrecognized instruction: 0x{instruction:04X?}"),
-            },
-            0x01 => {
-                println!("JP {nnn:04X}");
-                self.pc = nnn;
+    /// Switch platform, resizing the display and memory and leaving MegaChip mode. Memory is
+    /// only cleared if its size changes. The program counter is left alone.
+    pub fn set_platform(&mut self, platform: Platform) {
+        self.platform = platform;
+        if self.bus.memory.get().len() != platform.memory_size() {
+            self.bus.memory = Memory::with_size(platform.memory_size());
+            self.bus
+                .memory
+                .load_font(self.font, self.font_address as usize);
+        } else {
+            // instructions decode differently on each platform
+            self.bus.memory.clear_decoded();
+        }
+        self.sample = None;
+        self.bus.display.set_mega(false);
+        self.bus.display.set_height(platform.display_height());
+    }
+
+    /// Move the font to `address` or change it, clearing the memory the old font used.
+    pub fn set_font(&mut self, font: FontSet, address: u16) {
+        self.bus
+            .memory
+            .set(self.font_address as usize, FONT_SIZE, 0);
+        self.font = font;
+        self.font_address = address;
+        self.bus.memory.load_font(font, address as usize);
+    }
+
+    /// Reseed the random number generator used by RND.
+    pub fn seed(&mut self, seed: u64) {
+        self.rng = StdRng::seed_from_u64(seed);
+    }
+
+    /// Read a register, widened to 16 bits.
+    pub fn register(&self, register: Register) -> u16 {
+        match register {
+            Register::V(x) => self.vx[x as usize & 0xF] as u16,
+            Register::I => self.i as u16,
+            Register::Pc => self.pc,
+            Register::Sp => self.stack.len() as u16,
+            Register::Dt => self.dt as u16,
+            Register::St => self.st as u16,
+        }
+    }
+
+    /// Write a register, truncating the value to the register's width.
+    pub fn set_register(&mut self, register: Register, value: u16) {
+        match register {
+            Register::V(x) => self.vx[x as usize & 0xF] = value as u8,
+            Register::I => self.i = value as u32,
+            Register::Pc => self.pc = value,
+            Register::Sp => self.stack.resize((value as usize).min(self.stack_depth), 0),
+            Register::Dt => self.dt = value as u8,
+            Register::St => self.st = value as u8,
+        }
+    }
+
+    /// Returns the current value of the sound timer.
+    pub fn sound_timer(&self) -> u8 {
+        self.st
+    }
+
+    /// Serialize the platform, registers, timers, stack, memory, display and CHIP-8X colours.
+    pub fn save_state<W: Write>(&self, w: &mut W) -> std::io::Result<()> {
+        if self.bus.display.mega().is_some() {
+            return Err(std::io::Error::other("can't save state in MegaChip mode"));
+        }
+        if self.stack.len() > STACK_SIZE {
+            return Err(std::io::Error::other("stack too deep to save"));
+        }
+        w.write_u8(self.platform as u8)?;
+        w.write_u16::<BigEndian>(self.pc)?;
+        w.write_u32::<BigEndian>(self.i)?;
+        w.write_all(&self.vx)?;
+        w.write_u8(self.dt)?;
+        w.write_u8(self.st)?;
+        w.write_u8(self.stack.len() as u8)?;
+        for slot in 0..STACK_SIZE {
+            w.write_u16::<BigEndian>(self.stack.get(slot).copied().unwrap_or(0))?;
+        }
+        w.write_all(self.bus.memory.get())?;
+        w.write_all(self.bus.display.get())?;
+        w.write_all(self.bus.display.zones())?;
+        w.write_u8(self.bus.display.background())?;
+        Ok(())
+    }
+
+    /// Restore state written by [`Cpu::save_state`].
+    pub fn load_state<R: Read>(&mut self, r: &mut R) -> std::io::Result<()> {
+        // states are always monochrome, so the display sizes below are the non-Mega ones
+        self.bus.display.set_mega(false);
+        let platform = *Platform::ALL.get(r.read_u8()? as usize).ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "unknown platform in save state",
+            )
+        })?;
+        let pc = r.read_u16::<BigEndian>()?;
+        let i = r.read_u32::<BigEndian>()? & 0xFF_FFFF;
+        let mut vx = [0; 16];
+        r.read_exact(&mut vx)?;
+        let dt = r.read_u8()?;
+        let st = r.read_u8()?;
+        let depth = r.read_u8()? as usize;
+        if depth > STACK_SIZE {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "invalid stack depth in save state",
+            ));
+        }
+        let mut stack = Vec::with_capacity(STACK_SIZE);
+        for slot in 0..STACK_SIZE {
+            let value = r.read_u16::<BigEndian>()?;
+            if slot < depth {
+                stack.push(value);
             }
-            0x02 => {
-                println!("CALL {nnn:04X}");
-                self.stack.push(self.pc + 2);
-                self.pc = nnn;
+        }
+        let mut memory = vec![0; platform.memory_size()];
+        r.read_exact(&mut memory)?;
+        let mut pixels = vec![0; display::WIDTH * platform.display_height()];
+        r.read_exact(&mut pixels)?;
+        let mut zones = vec![0; self.bus.display.zones().len()];
+        r.read_exact(&mut zones)?;
+        let background = r.read_u8()?;
+
+        self.pc = pc;
+        self.i = i;
+        self.vx = vx;
+        self.dt = dt;
+        self.st = st;
+        self.stack = stack;
+        self.set_platform(platform);
+        self.bus.memory.write_slice(0, &memory);
+        self.bus.display.load(&pixels);
+        self.bus.display.load_colors(&zones, background);
+        self.last_tick_time = Instant::now();
+        Ok(())
+    }
+
+    /// Fetch and decode the instruction at PC, returning the raw opcode and the decoded
+    /// instruction. Decoded instructions are cached when the decode cache is enabled.
+    pub fn fetch(&mut self) -> Result<(u16, Instruction), Fault> {
+        if self.decode_cache {
+            if let Some(cached) = self.bus.memory.decoded(self.pc) {
+                return Ok(cached);
+            }
+        }
+        self.check_range(self.pc as u32, 2)?;
+        let mut opcode = self.bus.memory.read_slice(self.pc as usize, 2);
+        let opcode = opcode.read_u16::<BigEndian>().unwrap();
+        let decoded = (opcode, Instruction::decode(opcode, self.platform));
+        if self.decode_cache {
+            self.bus.memory.cache_decoded(self.pc, decoded);
+        }
+        Ok(decoded)
+    }
+
+    pub fn execute(&mut self, opcode: u16, instruction: Instruction) -> Result<(), Fault> {
+        if self.trace {
+            self.print_state();
+        }
+        trace!(self, "Instruction: 0x{opcode:04X}");
+        trace!(self, "{instruction}");
+        self.hooks.check_pc(self.pc);
+        let was_waiting = std::mem::take(&mut self.waiting);
+
+        match instruction {
+            Instruction::Cls => {
+                self.bus.display.clear();
+                self.advance(2)?;
+            }
+            Instruction::HiresCls => {
+                self.bus.display.clear();
+                self.advance(2)?;
+            }
+            Instruction::CycleBackground => {
+                self.bus.display.cycle_background();
+                self.advance(2)?;
+            }
+            Instruction::MegaOff => {
+                self.bus.display.set_mega(false);
+                self.advance(2)?;
+            }
+            Instruction::MegaOn => {
+                self.bus.display.set_mega(true);
+                self.advance(2)?;
+            }
+            Instruction::LdILong(kk) => {
+                self.check_range(self.pc as u32 + 2, 2)?;
+                let low = self.bus.memory.read_slice(self.pc as usize + 2, 2);
+                self.i = (kk as u32) << 16 | u16::from_be_bytes([low[0], low[1]]) as u32;
+                self.advance(4)?;
+            }
+            Instruction::LoadPalette(kk) => {
+                self.check_range(self.i, kk as usize * 4)?;
+                let colors = self.bus.memory.read_slice(self.i as usize, kk as usize * 4);
+                if let Some(mega) = self.bus.display.mega_mut() {
+                    for (index, argb) in colors.chunks(4).enumerate() {
+                        mega.palette[index + 1] =
+                            u32::from_be_bytes([argb[0], argb[1], argb[2], argb[3]]);
+                    }
+                }
+                self.advance(2)?;
             }
-            0x03 => {
-                println!("SE V{x:X}, {kk:02X}");
-                if self.vx[x as usize] == kk {
-                    self.pc += 2;
+            Instruction::SpriteWidth(kk) => {
+                if let Some(mega) = self.bus.display.mega_mut() {
+                    mega.sprite_width = if kk == 0 { 256 } else { kk as usize };
                 }
-                self.pc += 2;
+                self.advance(2)?;
             }
-            0x04 => {
-                println!("SNE V{x:X}, {kk:02X}");
-                if self.vx[x as usize] != kk {
-                    self.pc += 2;
+            Instruction::SpriteHeight(kk) => {
+                if let Some(mega) = self.bus.display.mega_mut() {
+                    mega.sprite_height = if kk == 0 { 256 } else { kk as usize };
                 }
-                self.pc += 2;
+                self.advance(2)?;
             }
-            0x05 => {
-                println!("SE V{x:X}, V{y:X}");
-                if self.vx[x as usize] == self.vx[y as usize] {
-                    self.pc += 2;
+            Instruction::Alpha(kk) => {
+                if let Some(mega) = self.bus.display.mega_mut() {
+                    mega.alpha = kk;
                 }
-                self.pc += 2;
+                self.advance(2)?;
             }
-            0x06 => {
-                println!("LD V{x:X}, 0x{kk:02X}");
-                self.vx[x as usize] = kk;
-                self.pc += 2;
+            Instruction::PlaySample(n) => {
+                let memory = self
+                    .bus
+                    .memory
+                    .get()
+                    .get(self.i as usize..)
+                    .unwrap_or_default();
+                let sample = Sample::parse(memory, n == 0).ok_or(Fault::MemoryOutOfRange {
+                    address: self.pc,
+                    access: self.i,
+                })?;
+                self.sample = Some(sample);
+                self.advance(2)?;
             }
-            0x07 => {
-                println!("ADD V{x:X}, 0x{kk:02X}");
-                self.vx[x as usize] = self.vx[x as usize].wrapping_add(kk);
-                self.pc += 2;
+            Instruction::StopSample => {
+                self.sample = None;
+                self.advance(2)?;
             }
-            0x08 => match instruction & 0x000F {
-                0x00 => {
-                    println!("LD V{x:X}, V{y:X}");
-                    self.vx[x as usize] = self.vx[y as usize];
-                    self.pc += 2;
-                }
-                0x01 => {
-                    println!("OR V{x:X}, V{y:X}");
-                    self.vx[x as usize] |= self.vx[y as usize];
-                    self.pc += 2;
+            Instruction::Blend(n) => {
+                if let (Some(mega), Some(blend)) =
+                    (self.bus.display.mega_mut(), BlendMode::from_code(n))
+                {
+                    mega.blend = blend;
                 }
-                0x02 => {
-                    println!("AND V{x:X}, V{y:X}");
-                    self.vx[x as usize] &= self.vx[y as usize];
-                    self.pc += 2;
+                self.advance(2)?;
+            }
+            Instruction::CollisionColor(kk) => {
+                if let Some(mega) = self.bus.display.mega_mut() {
+                    mega.collision_color = kk;
                 }
-                0x03 => {
-                    println!("XOR V{x:X}, V{y:X}");
-                    self.vx[x as usize] ^= self.vx[y as usize];
-                    self.pc += 2;
+                self.advance(2)?;
+            }
+            Instruction::Ret => {
+                let address = self
+                    .stack
+                    .pop()
+                    .ok_or(Fault::StackUnderflow { address: self.pc })?;
+                self.pc = if self.memory_stack {
+                    // the program may have changed the return address in memory
+                    let slot = STACK_ADDRESS as u32 + self.stack.len() as u32 * 2;
+                    u16::from_be_bytes([self.read_byte(slot), self.read_byte(slot + 1)])
+                } else {
+                    address
+                };
+            }
+            Instruction::Jp(nnn) => {
+                self.pc = nnn;
+            }
+            Instruction::Call(nnn) => {
+                if self.stack.len() >= self.stack_depth {
+                    return Err(Fault::StackOverflow { address: self.pc });
                 }
-                0x04 => {
-                    println!("ADD V{x:X}, V{y:X}");
-                    let sum = self.vx[x as usize] as u16 + self.vx[y as usize] as u16;
-                    if sum > 255 {
-                        self.vx[0xF] = 1;
-                    } else {
-                        self.vx[0xF] = 0;
-                    }
-                    self.vx[x as usize] = sum as u8;
-                    self.pc += 2;
+                if self.memory_stack {
+                    let slot = STACK_ADDRESS as u32 + self.stack.len() as u32 * 2;
+                    let [high, low] = self.next_pc(2)?.to_be_bytes();
+                    self.write_byte(slot, high);
+                    self.write_byte(slot + 1, low);
                 }
-                0x05 => {
-                    println!("SUB V{x:X}, V{y:X}");
-                    // println!("vx = {}, vy = {}", self.vx[x as usize], self.vx[y as usize]);
-                    if self.vx[x as usize] > self.vx[y as usize] {
-                        self.vx[0xF] = 1;
-                    } else {
-                        self.vx[0xF] = 0;
-                    }
-                    self.vx[x as usize] = self.vx[x as usize].wrapping_sub(self.vx[y as usize]);
-                    self.pc += 2;
+                self.stack.push(self.next_pc(2)?);
+                self.pc = nnn;
+            }
+            Instruction::SeByte(x, kk) => {
+                self.skip(self.vx[x as usize] == kk)?;
+            }
+            Instruction::SneByte(x, kk) => {
+                self.skip(self.vx[x as usize] != kk)?;
+            }
+            Instruction::SeReg(x, y) => {
+                self.skip(self.vx[x as usize] == self.vx[y as usize])?;
+            }
+            Instruction::LdByte(x, kk) => {
+                self.vx[x as usize] = kk;
+                self.advance(2)?;
+            }
+            Instruction::AddByte(x, kk) => {
+                self.vx[x as usize] = self.vx[x as usize].wrapping_add(kk);
+                self.advance(2)?;
+            }
+            Instruction::LdReg(x, y) => {
+                self.vx[x as usize] = self.vx[y as usize];
+                self.advance(2)?;
+            }
+            Instruction::Or(x, y) => {
+                self.vx[x as usize] |= self.vx[y as usize];
+                self.advance(2)?;
+            }
+            Instruction::And(x, y) => {
+                self.vx[x as usize] &= self.vx[y as usize];
+                self.advance(2)?;
+            }
+            Instruction::Xor(x, y) => {
+                self.vx[x as usize] ^= self.vx[y as usize];
+                self.advance(2)?;
+            }
+            Instruction::AddReg(x, y) => {
+                let sum = self.vx[x as usize] as u16 + self.vx[y as usize] as u16;
+                if sum > 255 {
+                    self.vx[0xF] = 1;
+                } else {
+                    self.vx[0xF] = 0;
                 }
-                0x06 => {
-                    println!("SHR V{x:X} {{, V{y:X}}}");
-                    if self.vx[x as usize] & 0b00000001 != 0 {
-                        self.vx[0xF] = 1;
-                    } else {
-                        self.vx[0xF] = 0;
-                    }
-                    self.vx[x as usize] >>= 1;
-                    self.pc += 2;
+                self.vx[x as usize] = sum as u8;
+                self.advance(2)?;
+            }
+            Instruction::Sub(x, y) => {
+                if self.vx[x as usize] > self.vx[y as usize] {
+                    self.vx[0xF] = 1;
+                } else {
+                    self.vx[0xF] = 0;
                 }
-                0x07 => {
-                    println!("SUBN V{x:X}, V{y:X}");
-                    if self.vx[y as usize] > self.vx[x as usize] {
-                        self.vx[0xF] = 1;
-                    } else {
-                        self.vx[0xF] = 0;
-                    }
-                    self.vx[y as usize] = self.vx[y as usize].wrapping_sub(self.vx[x as usize]);
-                    self.pc += 2;
+                self.vx[x as usize] = self.vx[x as usize].wrapping_sub(self.vx[y as usize]);
+                self.advance(2)?;
+            }
+            Instruction::Shr(x, _) => {
+                if self.vx[x as usize] & 0b00000001 != 0 {
+                    self.vx[0xF] = 1;
+                } else {
+                    self.vx[0xF] = 0;
                 }
-                0x0E => {
-                    println!("SHL V{x:X} {{, V{y:X}}}");
-                    if self.vx[x as usize] & 0b10000000 != 0 {
-                        self.vx[0xF] = 1;
-                    } else {
-                        self.vx[0xF] = 0;
-                    }
-                    self.vx[x as usize] <<= 1;
-                    self.pc += 2;
+                self.vx[x as usize] >>= 1;
+                self.advance(2)?;
+            }
+            Instruction::Subn(x, y) => {
+                if self.vx[y as usize] > self.vx[x as usize] {
+                    self.vx[0xF] = 1;
+                } else {
+                    self.vx[0xF] = 0;
                 }
-                _ => panic!("unrecognized instruction: 0x{instruction:04X?}"),
-            },
-            0x09 => {
-                println!("SNE V{x:X}, V{y:X}");
-                if self.vx[x as usize] != self.vx[y as usize] {
-                    self.pc += 2;
+                self.vx[y as usize] = self.vx[y as usize].wrapping_sub(self.vx[x as usize]);
+                self.advance(2)?;
+            }
+            Instruction::Shl(x, _) => {
+                if self.vx[x as usize] & 0b10000000 != 0 {
+                    self.vx[0xF] = 1;
+                } else {
+                    self.vx[0xF] = 0;
                 }
-                self.pc += 2;
+                self.vx[x as usize] <<= 1;
+                self.advance(2)?;
+            }
+            Instruction::SneReg(x, y) => {
+                self.skip(self.vx[x as usize] != self.vx[y as usize])?;
             }
-            0x0A => {
-                println!("LD I, 0x{nnn:04X}");
-                self.i = nnn;
-                self.pc += 2;
+            Instruction::LdI(nnn) => {
+                self.i = nnn as u32;
+                self.advance(2)?;
             }
-            0x0C => {
-                println!("RND V{x:X}, {kk:02X}");
-                self.vx[x as usize] = rand::thread_rng().gen_range(0..=255) & kk;
-                self.pc += 2;
+            Instruction::Rnd(x, kk) => {
+                self.vx[x as usize] = self.rng.gen_range(0..=255) & kk;
+                self.advance(2)?;
             }
-            0x0D => {
-                println!("DRW V{x:X}, V{y:X}, {n}");
+            Instruction::Drw(x, y, _) if self.bus.display.mega().is_some() => {
                 let vx = self.vx[x as usize];
                 let vy = self.vx[y as usize];
+                let (width, height) = self
+                    .bus
+                    .display
+                    .mega()
+                    .map(|mega| (mega.sprite_width, mega.sprite_height))
+                    .unwrap_or_default();
+                self.check_range(self.i, width * height)?;
+                for address in self.i..self.i + (width * height) as u32 {
+                    self.check_read(address);
+                }
+                let sprite = self.bus.memory.read_slice(self.i as usize, width * height);
+                self.vx[0xF] = self.bus.display.draw_mega(vx, vy, sprite) as u8;
+                self.advance(2)?;
+            }
+            Instruction::Drw(x, y, n) => {
+                let vx = self.vx[x as usize];
+                let vy = self.vx[y as usize];
+                self.check_range(self.i, n as usize)?;
+                for address in self.i..self.i + n as u32 {
+                    self.check_read(address);
+                }
                 let sprite = self.bus.memory.read_slice(self.i as usize, n as usize);
                 if self.bus.display.draw(vx, vy, sprite) {
                     self.vx[0xF] = 1;
@@ -217,103 +556,215 @@ impl Cpu {
                     self.vx[0xF] = 0;
                 }
                 // self.bus.display.print();
-                self.pc += 2;
+                self.advance(2)?;
             }
-            0x0E => match kk {
-                0x9E => {
-                    println!("SKP V{x:X}");
-                    if self.bus.keyboard.is_pressed(self.vx[x as usize]) {
-                        self.pc += 2;
-                    }
-                    self.pc += 2;
-                }
-                0xA1 => {
-                    println!("SKNP V{x:X}");
-                    if !self.bus.keyboard.is_pressed(self.vx[x as usize]) {
-                        self.pc += 2;
-                    }
-                    self.pc += 2;
+            Instruction::Skp(x) => {
+                let pressed = self.bus.keyboard.poll(self.vx[x as usize]);
+                self.skip(pressed)?;
+            }
+            Instruction::Sknp(x) => {
+                let pressed = self.bus.keyboard.poll(self.vx[x as usize]);
+                self.skip(!pressed)?;
+            }
+            Instruction::Color(x, y, n) => {
+                let horizontal = self.vx[x as usize] as usize;
+                let vertical = self.vx[(x as usize + 1) & 0xF] as usize;
+                // Vx holds the leftmost column of 8 pixels and the number of columns after it
+                let left = horizontal & 0xF;
+                let columns = left..=left + (horizontal >> 4);
+                let rows = if n == 0 {
+                    // and Vx+1 the same for rows of 4 pixels
+                    let top = (vertical & 0xF) * 4;
+                    top..=top + (vertical >> 4) * 4 + 3
+                } else {
+                    // or the top pixel row, for n single rows
+                    vertical..=vertical + n as usize - 1
+                };
+                let color = self.vx[y as usize] & 0x7;
+                self.bus.display.set_zone_color(columns, rows, color);
+                self.advance(2)?;
+            }
+            Instruction::Skp2(x) => {
+                self.skip(self.bus.keyboard.is_keypad2_pressed(self.vx[x as usize]))?;
+            }
+            Instruction::Sknp2(x) => {
+                self.skip(!self.bus.keyboard.is_keypad2_pressed(self.vx[x as usize]))?;
+            }
+            Instruction::Out(x) => {
+                self.io_output = Some(self.vx[x as usize]);
+                self.advance(2)?;
+            }
+            Instruction::In(x) => {
+                if let Some(byte) = self.io_input.take() {
+                    self.vx[x as usize] = byte;
+                    self.advance(2)?;
+                } else {
+                    // dont increment pc until a byte arrives
+                    self.waiting = true;
                 }
-                _ => panic!("unrecognized instruction: 0x{instruction:04X?}"),
             }
-            0x0F => match kk {
-                0x07 => {
-                    println!("LD V{x:X}, DT");
-                    self.vx[x as usize] = self.dt;
-                    self.pc += 2;
+            Instruction::LdVxDt(x) => {
+                self.vx[x as usize] = self.dt;
+                self.advance(2)?;
+            }
+            Instruction::LdKey(x) => {
+                // wait for a key press, then store the value of the key in Vx
+                trace!(self, "waiting for key press");
+                self.bus.keyboard.poll_all();
+                if !was_waiting {
+                    // only count keys pressed after the wait starts, not ones already held
+                    self.bus.keyboard.clear_events();
+                    self.key_wait = None;
                 }
-                0x0A => {
-                    // wait for a key press, then store the value of the key in Vx
-                    println!("LD V{x:X}, K");
-                    println!("waiting for key press");
-                    if let Some(key) = self.bus.keyboard.get_pressed() {
-                        self.vx[x as usize] = key;
-                        self.pc += 2;
+                let mut key = None;
+                while let Some(event) = self.bus.keyboard.next_event() {
+                    match (event.pressed, self.key_wait) {
+                        (true, None) if self.quirks.key_release => self.key_wait = Some(event.key),
+                        (true, None) => key = Some(event.key),
+                        (false, Some(held)) if held == event.key => key = Some(held),
+                        _ => {}
+                    }
+                    if key.is_some() {
+                        break;
                     }
-                    // dont increment pc if key isn't pressed
-                }
-                0x15 => {
-                    println!("LD DT, V{x:X}");
-                    self.dt = self.vx[x as usize];
-                    self.pc += 2;
-                }
-                0x18 => {
-                    println!("LD ST, V{x:X}");
-                    self.st = self.vx[x as usize];
-                    self.pc += 2;
-                }
-                0x1E => {
-                    println!("ADD I, V{x:X}");
-                    self.i = self.i.wrapping_add(self.vx[x as usize] as u16);
-                    self.pc += 2;
                 }
-                0x29 => {
-                    println!("LD F, V{x:X}");
-                    self.i = self.vx[x as usize] as u16 * 5;
-                    self.pc += 2;
+                if let Some(key) = key {
+                    self.vx[x as usize] = key;
+                    self.key_wait = None;
+                    self.advance(2)?;
+                } else {
+                    // dont increment pc until a key is pressed
+                    self.waiting = true;
                 }
-                0x33 => {
-                    println!("LD B, V{x:X}");
-                    let vx = self.vx[x as usize];
-                    let hundreds = (vx / 100) % 10;
-                    let tens = (vx / 10) % 10;
-                    let ones = (vx / 1) % 10;
-                    self.bus.memory.write_slice(self.i as usize, &[hundreds, tens, ones]);
-                    self.pc += 2;
+            }
+            Instruction::LdDt(x) => {
+                self.dt = self.vx[x as usize];
+                self.advance(2)?;
+            }
+            Instruction::LdSt(x) => {
+                self.st = self.vx[x as usize];
+                self.advance(2)?;
+            }
+            Instruction::AddI(x) => {
+                self.i = (self.i + self.vx[x as usize] as u32) & 0xFF_FFFF;
+                self.advance(2)?;
+            }
+            Instruction::LdFont(x) => {
+                self.i = self.font_address as u32 + (self.vx[x as usize] & 0xF) as u32 * 5;
+                self.advance(2)?;
+            }
+            Instruction::LdLargeFont(x) => {
+                let digit = (self.vx[x as usize] & 0xF) as u32;
+                self.i = self.font_address as u32 + SMALL_FONT_SIZE as u32 + digit * 10;
+                self.advance(2)?;
+            }
+            Instruction::LdBcd(x) => {
+                let vx = self.vx[x as usize];
+                let hundreds = (vx / 100) % 10;
+                let tens = (vx / 10) % 10;
+                let ones = vx % 10;
+                self.check_range(self.i, 3)?;
+                for (i, digit) in [hundreds, tens, ones].into_iter().enumerate() {
+                    self.write_byte(self.i + i as u32, digit);
                 }
-                0x55 => {
-                    println!("LD [I], V{x:X}");
-                    for i in 0..=x as usize {
-                        self.bus.memory.write_byte(self.i as usize + i, self.vx[i]);
-                    }
-                    self.pc += 2;
+                self.advance(2)?;
+            }
+            Instruction::Store(x) => {
+                self.check_range(self.i, x as usize + 1)?;
+                for i in 0..=x as usize {
+                    self.write_byte(self.i + i as u32, self.vx[i]);
                 }
-                0x65 => {
-                    println!("LD V{x:X}, [I]");
-                    for i in 0..=x as usize {
-                        self.vx[i] = self.bus.memory.read_byte(self.i as usize + i);
-                    }
-                    self.pc += 2;
+                self.advance(2)?;
+            }
+            Instruction::Load(x) => {
+                self.check_range(self.i, x as usize + 1)?;
+                for i in 0..=x as usize {
+                    self.vx[i] = self.read_byte(self.i + i as u32);
                 }
-                _ => panic!("unrecognized instruction: 0x{instruction:04X?}"),
-            },
-            _ => panic!("unrecognized instruction: 0x{instruction:04X?}"),
+                self.advance(2)?;
+            }
+            Instruction::Invalid => {
+                return Err(Fault::InvalidInstruction {
+                    address: self.pc,
+                    instruction: opcode,
+                })
+            }
+        }
+
+        trace!(self);
+        Ok(())
+    }
+
+    /// The address `n` bytes past PC. PC can only address the first 64 KiB, so running past
+    /// the end of it faults rather than wrapping around.
+    fn next_pc(&self, n: u16) -> Result<u16, Fault> {
+        self.pc.checked_add(n).ok_or(Fault::MemoryOutOfRange {
+            address: self.pc,
+            access: self.pc as u32 + n as u32,
+        })
+    }
+
+    fn advance(&mut self, n: u16) -> Result<(), Fault> {
+        self.pc = self.next_pc(n)?;
+        Ok(())
+    }
+
+    /// Move to the next instruction, skipping over one more if `condition` holds.
+    fn skip(&mut self, condition: bool) -> Result<(), Fault> {
+        self.advance(if condition { 4 } else { 2 })
+    }
+
+    /// Fail unless `length` bytes starting at `address` are inside memory.
+    fn check_range(&self, address: u32, length: usize) -> Result<(), Fault> {
+        if address as usize + length > self.bus.memory.get().len() {
+            return Err(Fault::MemoryOutOfRange {
+                address: self.pc,
+                access: address,
+            });
         }
+        Ok(())
+    }
 
-        println!("");
+    /// Write a byte on behalf of the program, firing any write hooks. Hooks only cover the
+    /// first 64 KiB.
+    fn write_byte(&mut self, address: u32, value: u8) {
+        self.bus.memory.write_byte(address as usize, value);
+        if let Ok(address) = u16::try_from(address) {
+            self.hooks.check_write(address, value);
+        }
+    }
 
-        self.tick()
+    /// Read a byte on behalf of the program, firing any read hooks.
+    fn read_byte(&mut self, address: u32) -> u8 {
+        self.check_read(address);
+        self.bus.memory.read_byte(address as usize)
     }
 
-    fn tick(&mut self) {
+    fn check_read(&mut self, address: u32) {
+        let value = self.bus.memory.read_byte(address as usize);
+        if let Ok(address) = u16::try_from(address) {
+            self.hooks.check_read(address, value);
+        }
+    }
+
+    /// Tick DT/ST if 1/60th of a second of real time has passed since the last tick.
+    /// Returns true if they ticked.
+    pub fn tick(&mut self) -> bool {
         if self.last_tick_time.elapsed().as_micros() > TIMERS_WAIT_MICROS {
             self.last_tick_time = Instant::now();
-            if self.dt > 0 {
-                self.dt -= 1;
-            }
-            if self.st > 0 {
-                self.st -= 1;
-            }
+            self.tick_timers();
+            return true;
+        }
+        false
+    }
+
+    /// Decrement DT and ST once, as happens at 60 Hz.
+    pub fn tick_timers(&mut self) {
+        if self.dt > 0 {
+            self.dt -= 1;
+        }
+        if self.st > 0 {
+            self.st -= 1;
         }
     }
 
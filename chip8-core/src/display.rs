@@ -1,56 +1,246 @@
-const WIDTH: usize = 64;
+use std::ops::RangeInclusive;
+
+use crate::{
+    color::{DisplayColors, BACKGROUNDS, DEFAULT_FOREGROUND},
+    megachip::{self, MegaScreen},
+};
+
+pub(crate) const WIDTH: usize = 64;
+/// Default height, and the height of every platform but hi-res CHIP-8
 const HEIGHT: usize = 32;
+const MAX_HEIGHT: usize = 64;
+/// CHIP-8X colour zones are 8 pixels wide
+const ZONE_WIDTH: usize = 8;
 
+/// The framebuffer, stored as one bitmask per row with the leftmost pixel in the most
+/// significant bit.
 #[derive(Debug)]
 pub(crate) struct Display {
+    rows: [u64; MAX_HEIGHT],
+    height: usize,
+    /// The same pixels unpacked to one byte each, for frontends. Kept in sync with `rows`
+    /// whenever a row changes.
     pixels: Box<[u8]>,
+    /// Incremented whenever any pixel or colour changes
+    generation: u64,
+    /// CHIP-8X foreground colour of each 8x1 pixel zone, row by row
+    zones: [u8; WIDTH / ZONE_WIDTH * MAX_HEIGHT],
+    /// Index of the CHIP-8X background colour in `BACKGROUNDS`
+    background: usize,
+    /// The colour screen while in MegaChip mode, which replaces the monochrome one
+    mega: Option<Box<MegaScreen>>,
 }
 
 impl Display {
     pub fn new() -> Self {
         Self {
+            rows: [0; MAX_HEIGHT],
+            height: HEIGHT,
             pixels: vec![0; WIDTH * HEIGHT].into_boxed_slice(),
+            generation: 0,
+            zones: [DEFAULT_FOREGROUND; WIDTH / ZONE_WIDTH * MAX_HEIGHT],
+            background: 0,
+            mega: None,
         }
     }
 
+    /// One byte per pixel, 1 if it's lit. In MegaChip mode, pixels that aren't black are lit.
     pub fn get(&self) -> &[u8] {
-        &self.pixels
+        match &self.mega {
+            Some(mega) => mega.lit(),
+            None => &self.pixels,
+        }
+    }
+
+    /// Each row as a bitmask, leftmost pixel in the most significant bit. Empty in MegaChip
+    /// mode, where pixels don't fit in a bitmask.
+    pub fn rows(&self) -> &[u64] {
+        match self.mega {
+            Some(_) => &[],
+            None => &self.rows[..self.height],
+        }
     }
 
+    /// Width and height in pixels.
+    pub fn size(&self) -> (usize, usize) {
+        match self.mega {
+            Some(_) => (megachip::WIDTH, megachip::HEIGHT),
+            None => (WIDTH, self.height),
+        }
+    }
+
+    /// Enter or leave MegaChip mode. Entering starts with a black screen and default
+    /// drawing settings; leaving returns to the monochrome display as it was.
+    pub fn set_mega(&mut self, enabled: bool) {
+        if enabled != self.mega.is_some() {
+            self.mega = enabled.then(|| Box::new(MegaScreen::new()));
+            self.generation = self.generation.wrapping_add(1);
+        }
+    }
+
+    pub fn mega(&self) -> Option<&MegaScreen> {
+        self.mega.as_deref()
+    }
+
+    pub fn mega_mut(&mut self) -> Option<&mut MegaScreen> {
+        self.mega.as_deref_mut()
+    }
+
+    /// The pixels shown in MegaChip mode, as ARGB.
+    pub fn rgba(&self) -> Option<&[u32]> {
+        self.mega.as_ref().map(|mega| mega.visible())
+    }
+
+    /// Draw a MegaChip sprite of palette indices, returning true if it collided.
+    pub fn draw_mega(&mut self, x: u8, y: u8, sprite: &[u8]) -> bool {
+        let Some(mega) = &mut self.mega else {
+            return false;
+        };
+        // only the back buffer changes, so the generation stays the same until it's shown
+        mega.draw(x as usize, y as usize, sprite)
+    }
+
+    /// Change the height, clearing the display. Heights above 64 are clamped.
+    pub fn set_height(&mut self, height: usize) {
+        let height = height.min(MAX_HEIGHT);
+        if height == self.height {
+            return;
+        }
+        self.rows = [0; MAX_HEIGHT];
+        self.height = height;
+        self.pixels = vec![0; WIDTH * height].into_boxed_slice();
+        self.generation = self.generation.wrapping_add(1);
+    }
+
+    /// Counter that changes whenever the display does, so frontends can skip redrawing
+    /// identical frames.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Replace every pixel with the given buffer.
+    pub fn load(&mut self, pixels: &[u8]) {
+        for (y, row) in pixels.chunks(WIDTH).take(self.height).enumerate() {
+            let bits = row
+                .iter()
+                .fold(0u64, |bits, pixel| (bits << 1) | (*pixel != 0) as u64);
+            self.set_row(y, bits);
+        }
+    }
+
+    /// Clear the display. In MegaChip mode, this shows what was drawn since the last clear
+    /// and starts drawing the next frame.
     pub fn clear(&mut self) {
-        for pixel in self.pixels.iter_mut() {
-            *pixel = 0;
+        if let Some(mega) = &mut self.mega {
+            mega.present();
+            self.generation = self.generation.wrapping_add(1);
+            return;
+        }
+        for y in 0..self.height {
+            self.set_row(y, 0);
+        }
+    }
+
+    /// Set the colour of the zones in `columns` of 8 pixels and in pixel `rows`, wrapping
+    /// around the edges.
+    pub fn set_zone_color(
+        &mut self,
+        columns: RangeInclusive<usize>,
+        rows: RangeInclusive<usize>,
+        color: u8,
+    ) {
+        let zones_per_row = WIDTH / ZONE_WIDTH;
+        for y in rows {
+            for x in columns.clone() {
+                let zone = &mut self.zones[y % self.height * zones_per_row + x % zones_per_row];
+                if *zone != color {
+                    *zone = color;
+                    self.generation = self.generation.wrapping_add(1);
+                }
+            }
+        }
+    }
+
+    /// Switch to the next background colour.
+    pub fn cycle_background(&mut self) {
+        self.background = (self.background + 1) % BACKGROUNDS.len();
+        self.generation = self.generation.wrapping_add(1);
+    }
+
+    /// Reset every zone and the background to their initial colours.
+    pub fn reset_colors(&mut self) {
+        if self.background != 0 || self.zones.iter().any(|zone| *zone != DEFAULT_FOREGROUND) {
+            self.zones.fill(DEFAULT_FOREGROUND);
+            self.background = 0;
+            self.generation = self.generation.wrapping_add(1);
+        }
+    }
+
+    /// The colour of each zone, for save states.
+    pub fn zones(&self) -> &[u8] {
+        &self.zones
+    }
+
+    /// The position of the background colour in `BACKGROUNDS`, for save states.
+    pub fn background(&self) -> u8 {
+        self.background as u8
+    }
+
+    /// Restore colours saved from `zones` and `background`.
+    pub fn load_colors(&mut self, zones: &[u8], background: u8) {
+        for (zone, color) in self.zones.iter_mut().zip(zones) {
+            *zone = color & 0x7;
+        }
+        self.background = background as usize % BACKGROUNDS.len();
+        self.generation = self.generation.wrapping_add(1);
+    }
+
+    /// The colour of each pixel and of the background.
+    pub fn colors(&self) -> DisplayColors {
+        let zones_per_row = WIDTH / ZONE_WIDTH;
+        let foreground = (0..WIDTH * self.height)
+            .map(|i| self.zones[i / WIDTH * zones_per_row + i % WIDTH / ZONE_WIDTH])
+            .collect();
+        DisplayColors {
+            foreground,
+            background: BACKGROUNDS[self.background],
         }
     }
 
     /// returns true if there was a collision
     pub fn draw(&mut self, x: u8, y: u8, sprite: &[u8]) -> bool {
+        let x = x as u32 % WIDTH as u32;
         let mut erased = false;
-        for (i, b) in sprite.iter().enumerate() {
-            erased |= self.draw_byte(x, y + i as u8, *b);
+        for (i, byte) in sprite.iter().enumerate() {
+            let y = (y as usize + i) % self.height;
+            // the display is exactly 64 pixels wide, so rotating wraps sprites around the edge
+            let mask = ((*byte as u64) << (WIDTH - 8)).rotate_right(x);
+            let old = self.rows[y];
+            erased |= old & mask != 0;
+            self.set_row(y, old ^ mask);
         }
         erased
     }
 
-    fn draw_byte(&mut self, x: u8, y: u8, mut byte: u8) -> bool {
-        let mut x = x as usize % WIDTH;
-        let y = y as usize % HEIGHT;
-        let mut erased = false;
-        for _ in 0..8 {
-            let i = y * WIDTH + x;
-            let old = self.pixels[i];
-            self.pixels[i] ^= (byte & 0b10000000) >> 7;
-            erased |= old == 1 && self.pixels[i] == 0;
-            x = (x + 1) % WIDTH;
-            byte <<= 1;
+    fn set_row(&mut self, y: usize, bits: u64) {
+        if self.rows[y] == bits {
+            return;
+        }
+        self.rows[y] = bits;
+        self.generation = self.generation.wrapping_add(1);
+        for (x, pixel) in self.pixels[y * WIDTH..(y + 1) * WIDTH]
+            .iter_mut()
+            .enumerate()
+        {
+            *pixel = (bits >> (WIDTH - 1 - x)) as u8 & 1;
         }
-        erased
     }
 
+    #[allow(dead_code)]
     pub fn print(&self) {
-        for y in 0..HEIGHT {
-            for x in 0..WIDTH {
-                if self.pixels[y * WIDTH + x] == 0 {
+        for row in self.rows() {
+            for x in (0..WIDTH).rev() {
+                if row >> x & 1 == 0 {
                     print!(" ");
                 } else {
                     print!("#");
@@ -60,3 +250,32 @@ impl Display {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn draw_wraps_and_collides() {
+        let mut display = Display::new();
+        assert!(!display.draw(60, 31, &[0b11111111, 0b10000000]));
+        assert_eq!(display.rows()[31], 0xF000_0000_0000_000F);
+        assert_eq!(display.rows()[0], 0x8);
+        assert_eq!(display.get()[31 * WIDTH..32 * WIDTH][..4], [1, 1, 1, 1]);
+        assert_eq!(display.get()[60], 1);
+
+        let generation = display.generation();
+        assert!(display.draw(56, 0, &[0b00001000]));
+        assert_eq!(display.rows()[0], 0);
+        assert_ne!(display.generation(), generation);
+
+        // drawing nothing and clearing twice only changes the display once
+        let generation = display.generation();
+        display.draw(0, 0, &[0]);
+        assert_eq!(display.generation(), generation);
+        display.clear();
+        display.clear();
+        assert_eq!(display.generation(), generation + 1);
+        assert!(display.get().iter().all(|pixel| *pixel == 0));
+    }
+}
@@ -0,0 +1,317 @@
+//! Gym-style environment for training agents on CHIP-8 games.
+//!
+//! An [`Env`] runs one ROM under a fixed [`EnvConfig`]. Each [`Env::step`] holds a set of keys
+//! for `frames_per_step` frames and returns the framebuffer, a reward and whether the episode
+//! has ended. Rewards and episode ends are expressions over registers and memory, see
+//! [`EnvConfig::parse`]. [`VecEnv`] steps many environments in parallel across threads.
+
+use std::io::{Error, ErrorKind};
+
+use crate::{
+    chip8::Chip8,
+    expr::{Expr, Snapshot},
+};
+
+/// Keys held during a step, as a bitmask where bit `n` holds key `n`.
+pub type Action = u16;
+
+/// Framebuffer, one byte per pixel in row-major order.
+pub type Observation = Vec<u8>;
+
+/// Per-ROM environment settings.
+#[derive(Debug, Clone)]
+pub struct EnvConfig {
+    /// Number of 60 Hz frames each action is held for
+    pub frames_per_step: u32,
+    /// CPU speed in Hz
+    pub speed: u32,
+    /// End the episode after this many steps
+    pub max_steps: Option<u32>,
+    reward: Expr,
+    done: Expr,
+}
+
+impl EnvConfig {
+    /// Parse a config made of `key = value` lines. Blank lines and lines starting with `#` are
+    /// ignored. Every key is optional:
+    ///
+    /// ```text
+    /// # BRIX
+    /// frames_per_step = 4
+    /// speed = 500
+    /// max_steps = 10000
+    /// reward = mem[0x2F0] - prev(mem[0x2F0])
+    /// done = VE == 0
+    /// ```
+    ///
+    /// `reward` and `done` are integer expressions over the registers `V0`-`VF`, `I`, `PC`,
+    /// `SP`, `DT` and `ST`, memory reads `mem[addr]`, and `prev(...)` which evaluates its argument
+    /// against the state at the end of the previous step. `done` ends the episode when nonzero.
+    pub fn parse(source: &str) -> std::io::Result<EnvConfig> {
+        let mut config = EnvConfig::default();
+        for (number, line) in source.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = |msg: String| {
+                Error::new(
+                    ErrorKind::InvalidData,
+                    format!("line {}: {msg}", number + 1),
+                )
+            };
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| invalid("expected `key = value`".to_string()))?;
+            let value = value.trim();
+            let parse_int = |value: &str| {
+                value
+                    .parse::<u32>()
+                    .map_err(|_| invalid(format!("invalid number `{value}`")))
+            };
+            match key.trim() {
+                "frames_per_step" => config.frames_per_step = parse_int(value)?,
+                "speed" => config.speed = parse_int(value)?,
+                "max_steps" => config.max_steps = Some(parse_int(value)?),
+                "reward" => config.reward = Expr::parse(value).map_err(invalid)?,
+                "done" => config.done = Expr::parse(value).map_err(invalid)?,
+                key => return Err(invalid(format!("unknown key `{key}`"))),
+            }
+        }
+        Ok(config)
+    }
+
+    /// Set the reward expression.
+    pub fn set_reward(&mut self, expr: &str) -> std::io::Result<()> {
+        self.reward = Expr::parse(expr).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+        Ok(())
+    }
+
+    /// Set the episode end expression.
+    pub fn set_done(&mut self, expr: &str) -> std::io::Result<()> {
+        self.done = Expr::parse(expr).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+        Ok(())
+    }
+}
+
+impl Default for EnvConfig {
+    fn default() -> Self {
+        Self {
+            frames_per_step: 4,
+            speed: 500,
+            max_steps: None,
+            reward: Expr::Const(0),
+            done: Expr::Const(0),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Env {
+    chip8: Chip8,
+    rom: Vec<u8>,
+    config: EnvConfig,
+    /// State at the end of the previous step, for `prev(...)`
+    previous: Snapshot,
+    steps: u32,
+}
+
+impl Env {
+    /// Create an environment for the given ROM. Call [`Env::reset`] before stepping.
+    pub fn new(rom: &[u8], config: EnvConfig) -> std::io::Result<Env> {
+        let mut chip8 = Chip8::default();
+        chip8.set_trace(false);
+        chip8.load_rom_bytes(rom)?;
+        let previous = Snapshot::capture(&chip8);
+        Ok(Self {
+            chip8,
+            rom: rom.to_vec(),
+            config,
+            previous,
+            steps: 0,
+        })
+    }
+
+    /// Restart the ROM with the random number generator seeded by `seed` and return the first
+    /// observation.
+    pub fn reset(&mut self, seed: u64) -> Observation {
+        self.chip8
+            .load_rom_bytes(&self.rom)
+            .expect("rom was already loaded once");
+        self.chip8.set_speed(self.config.speed);
+        self.chip8.seed(seed);
+        self.previous = Snapshot::capture(&self.chip8);
+        self.steps = 0;
+        self.observation()
+    }
+
+    /// Hold the keys in `action` for `frames_per_step` frames and return the observation,
+    /// reward and whether the episode is over. The episode is also over once the program
+    /// faults, since it can't run any further.
+    pub fn step(&mut self, action: Action) -> (Observation, f32, bool) {
+        for key in 0..16 {
+            self.chip8.set_key_state(key, action & (1 << key) != 0);
+        }
+        for _ in 0..self.config.frames_per_step {
+            self.chip8.run_frame();
+        }
+        self.steps += 1;
+
+        let current = Snapshot::capture(&self.chip8);
+        let reward = self.config.reward.eval(&current, &self.previous) as f32;
+        let done = self.config.done.eval(&current, &self.previous) != 0
+            || self.config.max_steps.is_some_and(|max| self.steps >= max)
+            || self.chip8.fault().is_some();
+        self.previous = current;
+        (self.observation(), reward, done)
+    }
+
+    pub fn observation(&self) -> Observation {
+        self.chip8.get_display().to_vec()
+    }
+
+    /// Get the underlying machine, e.g. to inspect memory.
+    pub fn chip8(&self) -> &Chip8 {
+        &self.chip8
+    }
+}
+
+/// A batch of environments running the same ROM, stepped in parallel.
+#[derive(Debug)]
+pub struct VecEnv {
+    envs: Vec<Env>,
+    threads: usize,
+}
+
+impl VecEnv {
+    /// Create `count` environments, stepped using one thread per available CPU.
+    pub fn new(rom: &[u8], config: &EnvConfig, count: usize) -> std::io::Result<VecEnv> {
+        let envs = (0..count)
+            .map(|_| Env::new(rom, config.clone()))
+            .collect::<std::io::Result<Vec<_>>>()?;
+        let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+        Ok(Self { envs, threads })
+    }
+
+    /// Set the number of threads used to step environments.
+    pub fn set_threads(&mut self, threads: usize) {
+        self.threads = threads.max(1);
+    }
+
+    pub fn len(&self) -> usize {
+        self.envs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.envs.is_empty()
+    }
+
+    /// Reset every environment, seeding environment `i` with `seed + i`.
+    pub fn reset(&mut self, seed: u64) -> Vec<Observation> {
+        self.envs
+            .iter_mut()
+            .enumerate()
+            .map(|(i, env)| env.reset(seed.wrapping_add(i as u64)))
+            .collect()
+    }
+
+    /// Step each environment with its action. Environments that are done are not reset
+    /// automatically.
+    ///
+    /// # Panics
+    ///
+    /// If `actions.len()` doesn't match the number of environments.
+    pub fn step(&mut self, actions: &[Action]) -> Vec<(Observation, f32, bool)> {
+        assert_eq!(actions.len(), self.envs.len(), "one action per environment");
+        let chunk = self.envs.len().div_ceil(self.threads).max(1);
+        let mut results = vec![(Vec::new(), 0.0, false); self.envs.len()];
+        std::thread::scope(|scope| {
+            for ((envs, actions), results) in self
+                .envs
+                .chunks_mut(chunk)
+                .zip(actions.chunks(chunk))
+                .zip(results.chunks_mut(chunk))
+            {
+                scope.spawn(move || {
+                    for ((env, action), result) in envs.iter_mut().zip(actions).zip(results) {
+                        *result = env.step(*action);
+                    }
+                });
+            }
+        });
+        results
+    }
+
+    /// Get the environment at `index`.
+    pub fn env(&self, index: usize) -> &Env {
+        &self.envs[index]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // loop: RND V0, 0xFF; LD [I], V0 (I = 0x300); ADD V1, 1; JP loop
+    const ROM: [u8; 10] = [0xA3, 0x00, 0xC0, 0xFF, 0xF0, 0x55, 0x71, 0x01, 0x12, 0x02];
+
+    #[test]
+    fn deterministic_with_seed() {
+        let config = EnvConfig::parse(
+            "# test\nframes_per_step = 2\nreward = V1 - prev(V1)\ndone = V1 >= 4\n",
+        )
+        .unwrap();
+        let mut a = Env::new(&ROM, config.clone()).unwrap();
+        let mut b = Env::new(&ROM, config).unwrap();
+        a.reset(7);
+        b.reset(7);
+        let (_, reward, done) = a.step(0);
+        b.step(0);
+        // 16 instructions: LD I, then three and a bit passes through the loop
+        assert_eq!(reward, 4.0);
+        assert!(done);
+        assert_eq!(
+            a.chip8().read_memory(0x300, 1),
+            b.chip8().read_memory(0x300, 1)
+        );
+    }
+
+    #[test]
+    fn vec_env_matches_env() {
+        let config = EnvConfig::parse("max_steps = 2").unwrap();
+        let mut single = Env::new(&ROM, config.clone()).unwrap();
+        let mut batch = VecEnv::new(&ROM, &config, 5).unwrap();
+        batch.set_threads(2);
+        batch.reset(100);
+        single.reset(103);
+        let results = batch.step(&[0; 5]);
+        let (observation, _, done) = single.step(0);
+        assert_eq!(results[3].0, observation);
+        assert!(!done);
+        assert!(batch.step(&[0; 5]).iter().all(|(_, _, done)| *done));
+        single.step(0);
+        assert_eq!(
+            batch.env(3).chip8().read_memory(0x300, 1),
+            single.chip8().read_memory(0x300, 1)
+        );
+    }
+
+    #[test]
+    fn fault_ends_episode() {
+        // ADD V1, 1; an invalid instruction
+        let rom = [0x71, 0x01, 0xFF, 0xFF];
+        let mut env = Env::new(&rom, EnvConfig::parse("reward = V1").unwrap()).unwrap();
+        env.reset(0);
+        let (_, reward, done) = env.step(0);
+        assert_eq!(reward, 1.0);
+        assert!(done);
+    }
+
+    #[test]
+    fn invalid_config() {
+        assert!(EnvConfig::parse("speed = fast").is_err());
+        assert!(EnvConfig::parse("reward = V1 +").is_err());
+        assert!(EnvConfig::parse("colour = red").is_err());
+        assert!(EnvConfig::parse("reward").is_err());
+    }
+}
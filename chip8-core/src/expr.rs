@@ -0,0 +1,335 @@
+//! Small expression language used by [`crate::env::EnvConfig`] to compute rewards.
+//!
+//! Expressions are integer valued and can read registers (`V0`-`VF`, `I`, `PC`, `SP`, `DT`,
+//! `ST`), memory (`mem[0x2F0]`) and the same values as they were at the previous step (`prev(V3)`).
+//! Supported operators, from lowest to highest precedence, are `||`, `&&`, comparisons
+//! (`== != < <= > >=`), `+ -`, `* / % &`, and unary `- !`. Comparisons and logical operators
+//! produce 0 or 1.
+
+use crate::chip8::{Chip8, Register};
+
+/// Registers and memory captured from a [`Chip8`] at one point in time.
+#[derive(Debug, Clone)]
+pub(crate) struct Snapshot {
+    registers: [u16; 21],
+    memory: Vec<u8>,
+}
+
+impl Snapshot {
+    pub fn capture(chip8: &Chip8) -> Self {
+        let mut registers = [0; 21];
+        for (i, register) in registers.iter_mut().enumerate() {
+            *register = chip8.get_register(register_at(i));
+        }
+        let memory = chip8.read_memory(0, 0x1000).unwrap_or_default().to_vec();
+        Self { registers, memory }
+    }
+
+    fn register(&self, register: Register) -> i64 {
+        let i = match register {
+            Register::V(x) => x as usize,
+            Register::I => 16,
+            Register::Pc => 17,
+            Register::Dt => 18,
+            Register::St => 19,
+            Register::Sp => 20,
+        };
+        self.registers[i] as i64
+    }
+
+    fn memory(&self, address: i64) -> i64 {
+        usize::try_from(address)
+            .ok()
+            .and_then(|address| self.memory.get(address))
+            .copied()
+            .unwrap_or(0) as i64
+    }
+}
+
+fn register_at(i: usize) -> Register {
+    match i {
+        0..=15 => Register::V(i as u8),
+        16 => Register::I,
+        17 => Register::Pc,
+        18 => Register::Dt,
+        19 => Register::St,
+        _ => Register::Sp,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum BinOp {
+    Or,
+    And,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    BitAnd,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Expr {
+    Const(i64),
+    Register(Register),
+    Memory(Box<Expr>),
+    Prev(Box<Expr>),
+    Neg(Box<Expr>),
+    Not(Box<Expr>),
+    Binary(BinOp, Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    /// Parse an expression, returning a description of the problem if it is malformed.
+    pub fn parse(source: &str) -> Result<Expr, String> {
+        let tokens = tokenize(source)?;
+        let mut parser = Parser { tokens, pos: 0 };
+        let expr = parser.or()?;
+        match parser.peek() {
+            None => Ok(expr),
+            Some(token) => Err(format!("unexpected {token:?} in `{source}`")),
+        }
+    }
+
+    /// Evaluate against the current and previous snapshots.
+    pub fn eval(&self, current: &Snapshot, previous: &Snapshot) -> i64 {
+        match self {
+            Expr::Const(value) => *value,
+            Expr::Register(register) => current.register(*register),
+            Expr::Memory(address) => current.memory(address.eval(current, previous)),
+            Expr::Prev(expr) => expr.eval(previous, previous),
+            Expr::Neg(expr) => expr.eval(current, previous).wrapping_neg(),
+            Expr::Not(expr) => (expr.eval(current, previous) == 0) as i64,
+            Expr::Binary(op, lhs, rhs) => {
+                let a = lhs.eval(current, previous);
+                let b = rhs.eval(current, previous);
+                match op {
+                    BinOp::Or => (a != 0 || b != 0) as i64,
+                    BinOp::And => (a != 0 && b != 0) as i64,
+                    BinOp::Eq => (a == b) as i64,
+                    BinOp::Ne => (a != b) as i64,
+                    BinOp::Lt => (a < b) as i64,
+                    BinOp::Le => (a <= b) as i64,
+                    BinOp::Gt => (a > b) as i64,
+                    BinOp::Ge => (a >= b) as i64,
+                    BinOp::Add => a.wrapping_add(b),
+                    BinOp::Sub => a.wrapping_sub(b),
+                    BinOp::Mul => a.wrapping_mul(b),
+                    BinOp::Div => a.checked_div(b).unwrap_or(0),
+                    BinOp::Rem => a.checked_rem(b).unwrap_or(0),
+                    BinOp::BitAnd => a & b,
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(i64),
+    Ident(String),
+    Symbol(&'static str),
+}
+
+const SYMBOLS: [&str; 19] = [
+    "||", "&&", "==", "!=", "<=", ">=", "<", ">", "+", "-", "*", "/", "%", "&", "!", "(", ")", "[",
+    "]",
+];
+
+fn tokenize(source: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = source.trim_start();
+    while let Some(c) = rest.chars().next() {
+        if c.is_ascii_alphanumeric() || c == '_' {
+            let end = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            let word = &rest[..end];
+            let token = if c.is_ascii_digit() {
+                let value = match word.strip_prefix("0x").or_else(|| word.strip_prefix("0X")) {
+                    Some(hex) => i64::from_str_radix(hex, 16),
+                    None => word.parse(),
+                };
+                Token::Number(value.map_err(|_| format!("invalid number `{word}`"))?)
+            } else {
+                Token::Ident(word.to_ascii_uppercase())
+            };
+            tokens.push(token);
+            rest = &rest[end..];
+        } else if let Some(symbol) = SYMBOLS.iter().find(|s| rest.starts_with(**s)) {
+            tokens.push(Token::Symbol(symbol));
+            rest = &rest[symbol.len()..];
+        } else {
+            return Err(format!("unexpected character `{c}` in `{source}`"));
+        }
+        rest = rest.trim_start();
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn eat(&mut self, symbol: &str) -> bool {
+        if matches!(self.peek(), Some(Token::Symbol(s)) if *s == symbol) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, symbol: &str) -> Result<(), String> {
+        if self.eat(symbol) {
+            Ok(())
+        } else {
+            Err(format!("expected `{symbol}`"))
+        }
+    }
+
+    /// Parse a left associative chain of the given operators.
+    fn binary(
+        &mut self,
+        ops: &[(&str, BinOp)],
+        operand: fn(&mut Self) -> Result<Expr, String>,
+    ) -> Result<Expr, String> {
+        let mut lhs = operand(self)?;
+        'outer: loop {
+            for (symbol, op) in ops {
+                if self.eat(symbol) {
+                    let rhs = operand(self)?;
+                    lhs = Expr::Binary(*op, Box::new(lhs), Box::new(rhs));
+                    continue 'outer;
+                }
+            }
+            return Ok(lhs);
+        }
+    }
+
+    fn or(&mut self) -> Result<Expr, String> {
+        self.binary(&[("||", BinOp::Or)], Self::and)
+    }
+
+    fn and(&mut self) -> Result<Expr, String> {
+        self.binary(&[("&&", BinOp::And)], Self::comparison)
+    }
+
+    fn comparison(&mut self) -> Result<Expr, String> {
+        self.binary(
+            &[
+                ("==", BinOp::Eq),
+                ("!=", BinOp::Ne),
+                ("<=", BinOp::Le),
+                (">=", BinOp::Ge),
+                ("<", BinOp::Lt),
+                (">", BinOp::Gt),
+            ],
+            Self::sum,
+        )
+    }
+
+    fn sum(&mut self) -> Result<Expr, String> {
+        self.binary(&[("+", BinOp::Add), ("-", BinOp::Sub)], Self::product)
+    }
+
+    fn product(&mut self) -> Result<Expr, String> {
+        self.binary(
+            &[
+                ("*", BinOp::Mul),
+                ("/", BinOp::Div),
+                ("%", BinOp::Rem),
+                ("&", BinOp::BitAnd),
+            ],
+            Self::unary,
+        )
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        if self.eat("-") {
+            Ok(Expr::Neg(Box::new(self.unary()?)))
+        } else if self.eat("!") {
+            Ok(Expr::Not(Box::new(self.unary()?)))
+        } else {
+            self.primary()
+        }
+    }
+
+    fn primary(&mut self) -> Result<Expr, String> {
+        match self.next() {
+            Some(Token::Number(value)) => Ok(Expr::Const(value)),
+            Some(Token::Symbol("(")) => {
+                let expr = self.or()?;
+                self.expect(")")?;
+                Ok(expr)
+            }
+            Some(Token::Ident(name)) => match name.as_str() {
+                "MEM" => {
+                    self.expect("[")?;
+                    let address = self.or()?;
+                    self.expect("]")?;
+                    Ok(Expr::Memory(Box::new(address)))
+                }
+                "PREV" => {
+                    self.expect("(")?;
+                    let expr = self.or()?;
+                    self.expect(")")?;
+                    Ok(Expr::Prev(Box::new(expr)))
+                }
+                _ => name
+                    .parse()
+                    .map(Expr::Register)
+                    .map_err(|_| format!("unknown name `{name}`")),
+            },
+            Some(token) => Err(format!("unexpected {token:?}")),
+            None => Err("unexpected end of expression".to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_and_eval() {
+        let mut chip8 = Chip8::default();
+        chip8.write_memory(0x2F0, &[3]);
+        chip8.set_register(Register::V(0xE), 7);
+        let previous = Snapshot::capture(&chip8);
+        chip8.write_memory(0x2F0, &[5]);
+        let current = Snapshot::capture(&chip8);
+
+        let eval = |source: &str| Expr::parse(source).unwrap().eval(&current, &previous);
+        assert_eq!(eval("mem[0x2F0] - prev(mem[0x2F0])"), 2);
+        assert_eq!(eval("VE * 2 + 1"), 15);
+        assert_eq!(eval("-(1 + 2) * 3"), -9);
+        assert_eq!(eval("ve == 7 && !(mem[0x2F0] < 5)"), 1);
+        assert_eq!(eval("VE & 0x3 % 2"), 1);
+        assert_eq!(eval("10 / 0"), 0);
+
+        assert!(Expr::parse("VG").is_err());
+        assert!(Expr::parse("mem[1").is_err());
+        assert!(Expr::parse("1 2").is_err());
+        assert!(Expr::parse("$").is_err());
+    }
+}
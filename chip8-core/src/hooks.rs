@@ -0,0 +1,151 @@
+//! Hook points that let a frontend react to execution without polling every instruction.
+//!
+//! Hooks are registered on [`crate::chip8::Chip8`]. When one fires a [`HookEvent`] is queued,
+//! and the frontend takes it with [`crate::chip8::Chip8::take_hook_events`]. Adding a hook
+//! returns a [`HookId`] that removes it and takes its events, so a script and a debugger can
+//! hook the same machine without removing or consuming each other's hooks.
+//!
+//! At most [`MAX_EVENTS`] events are queued; past that the oldest are dropped, so hooks whose
+//! owner stops taking events don't grow memory without bound.
+
+use std::collections::VecDeque;
+
+/// Events queued before the oldest are dropped.
+pub const MAX_EVENTS: usize = 4096;
+
+/// Something that happened while executing with hooks registered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HookEvent {
+    /// The instruction at a hooked address is about to execute
+    Pc(u16),
+    /// The program wrote `value` to a hooked address
+    Write { address: u16, value: u8 },
+    /// The program read `value` from a hooked address
+    Read { address: u16, value: u8 },
+}
+
+/// Handle to a registered hook, used to remove it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct HookId(u64);
+
+#[derive(Debug, Default)]
+pub(crate) struct Hooks {
+    pcs: Vec<(HookId, u16)>,
+    /// Inclusive address ranges
+    writes: Vec<(HookId, u16, u16)>,
+    /// Inclusive address ranges
+    reads: Vec<(HookId, u16, u16)>,
+    /// Events with the hook that fired them
+    events: VecDeque<(HookId, HookEvent)>,
+    next_id: u64,
+}
+
+impl Hooks {
+    fn next_id(&mut self) -> HookId {
+        self.next_id += 1;
+        HookId(self.next_id)
+    }
+
+    pub fn add_pc(&mut self, pc: u16) -> HookId {
+        let id = self.next_id();
+        self.pcs.push((id, pc));
+        id
+    }
+
+    pub fn add_write(&mut self, start: u16, end: u16) -> HookId {
+        let id = self.next_id();
+        self.writes.push((id, start, end));
+        id
+    }
+
+    pub fn add_read(&mut self, start: u16, end: u16) -> HookId {
+        let id = self.next_id();
+        self.reads.push((id, start, end));
+        id
+    }
+
+    pub fn remove(&mut self, id: HookId) {
+        self.pcs.retain(|(hook, _)| *hook != id);
+        self.writes.retain(|(hook, _, _)| *hook != id);
+        self.reads.retain(|(hook, _, _)| *hook != id);
+        self.events.retain(|(hook, _)| *hook != id);
+    }
+
+    /// Remove every hook and discard pending events.
+    pub fn clear(&mut self) {
+        self.pcs.clear();
+        self.writes.clear();
+        self.reads.clear();
+        self.events.clear();
+    }
+
+    fn push(events: &mut VecDeque<(HookId, HookEvent)>, id: HookId, event: HookEvent) {
+        if events.len() == MAX_EVENTS {
+            events.pop_front();
+        }
+        events.push_back((id, event));
+    }
+
+    pub fn check_pc(&mut self, pc: u16) {
+        for (id, hook) in &self.pcs {
+            if *hook == pc {
+                Self::push(&mut self.events, *id, HookEvent::Pc(pc));
+            }
+        }
+    }
+
+    pub fn check_write(&mut self, address: u16, value: u8) {
+        for (id, start, end) in &self.writes {
+            if (*start..=*end).contains(&address) {
+                Self::push(&mut self.events, *id, HookEvent::Write { address, value });
+            }
+        }
+    }
+
+    pub fn check_read(&mut self, address: u16, value: u8) {
+        for (id, start, end) in &self.reads {
+            if (*start..=*end).contains(&address) {
+                Self::push(&mut self.events, *id, HookEvent::Read { address, value });
+            }
+        }
+    }
+
+    pub fn has_events(&self, hooks: &[HookId]) -> bool {
+        self.events.iter().any(|(id, _)| hooks.contains(id))
+    }
+
+    /// Take the events fired by `hooks`, leaving any others queued.
+    pub fn take_events(&mut self, hooks: &[HookId]) -> Vec<(HookId, HookEvent)> {
+        let (taken, kept): (VecDeque<_>, _) = std::mem::take(&mut self.events)
+            .into_iter()
+            .partition(|(id, _)| hooks.contains(id));
+        self.events = kept;
+        taken.into()
+    }
+
+    pub fn clear_events(&mut self) {
+        self.events.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn events_capped() {
+        let mut hooks = Hooks::default();
+        let stalled = hooks.add_pc(0x200);
+        let drained = hooks.add_write(0x300, 0x300);
+        for _ in 0..MAX_EVENTS + 10 {
+            hooks.check_pc(0x200);
+        }
+        hooks.check_write(0x300, 7);
+        let write = HookEvent::Write {
+            address: 0x300,
+            value: 7,
+        };
+        assert_eq!(hooks.take_events(&[drained]), [(drained, write)]);
+        assert_eq!(hooks.take_events(&[stalled]).len(), MAX_EVENTS - 1);
+    }
+}
@@ -1,46 +1,260 @@
-// #[derive(Debug)]
-// pub(crate) enum Instruction {
-//     /// CLS - Clear the display
-//     CLS,
-//     /// RET - Return from a subroutine
-//     RET,
-//     // SYS(u16),
-//     /// `JP addr` - Jump to `addr`
-//     JP(u16),
-//     /// `CALL addr` - Call subroutine at `addr`
-//     CALL(u16),
-//     /// `SE Vx, byte` - Skip next instruction if `Vx == byte`
-//     SE((u8, u8)),
-//     /// `SNE Vx, byte` - Skip next instruction if `Vx != byte`
-//     SNE((u8, u8)),
-//     Unrecognized,
-// }
+use std::fmt;
 
-// impl Instruction {
-//     pub fn decode(instruction: u16) -> Self {
-//         let nnn = instruction & 0x0FFF; // 12-bit address, lower 12 bits of instruction
-//         let kk = (instruction & 0x00FF) as u8; // 8-bit value, lower 8 bits of instruction
-//         // let uu = ((instruction & 0xFF00) >> 8) as u8; // 8-bit value, upper 8 bits of instruction
-//         let n = (instruction & 0x000F) as u8; // 4-bit value, lowest 4 bits of instruction
-//         let x = ((instruction & 0x0F00) >> 8) as u8; // 4-bit value, lower 4 bits of upper byte
-//         let y = ((instruction & 0x00F0) >> 4) as u8; // 4-bit value, upper 4 bits of lower byte
+use crate::platform::Platform;
 
-//         // println!("parsed instruction: nnn = {nnn}, uu = {uu}, kk = {kk}, n = {n}, x = {x}, y = {y}");
+/// A decoded instruction, with its operands already extracted. `x` and `y` are register
+/// numbers, `kk` and `n` are immediate values and `nnn` is an address. Instructions of
+/// platform extensions only decode on their platform.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Instruction {
+    /// `CLS` - Clear the display
+    Cls,
+    /// `0230` - Clear the 64x64 display of hi-res CHIP-8
+    HiresCls,
+    /// `02A0` - Cycle the CHIP-8X background colour
+    CycleBackground,
+    /// `0010` - Leave MegaChip mode
+    MegaOff,
+    /// `0011` - Enter MegaChip mode, with a 256x192 colour display
+    MegaOn,
+    /// `01kk nnnn` - Load the 24-bit address `kk nnnn` into `I`. The low 16 bits follow the
+    /// opcode.
+    LdILong(u8),
+    /// `02kk` - Load `kk` MegaChip palette colours from `I`, 4 bytes of ARGB each, starting
+    /// at colour 1
+    LoadPalette(u8),
+    /// `03kk` - Set the MegaChip sprite width, 0 meaning 256
+    SpriteWidth(u8),
+    /// `04kk` - Set the MegaChip sprite height, 0 meaning 256
+    SpriteHeight(u8),
+    /// `05kk` - Set the opacity of MegaChip sprites
+    Alpha(u8),
+    /// `060n` - Play the digitized sound at `I`, once if `n` is 1 and looping if it's 0
+    PlaySample(u8),
+    /// `0700` - Stop the digitized sound
+    StopSample,
+    /// `080n` - Set how MegaChip sprites blend with the screen
+    Blend(u8),
+    /// `09kk` - Set the palette colour that counts as a collision when drawn over
+    CollisionColor(u8),
+    /// `RET` - Return from a subroutine
+    Ret,
+    /// `JP addr` - Jump to `addr`
+    Jp(u16),
+    /// `CALL addr` - Call subroutine at `addr`
+    Call(u16),
+    /// `SE Vx, byte` - Skip next instruction if `Vx == byte`
+    SeByte(u8, u8),
+    /// `SNE Vx, byte` - Skip next instruction if `Vx != byte`
+    SneByte(u8, u8),
+    /// `SE Vx, Vy` - Skip next instruction if `Vx == Vy`
+    SeReg(u8, u8),
+    /// `LD Vx, byte`
+    LdByte(u8, u8),
+    /// `ADD Vx, byte`, without carry
+    AddByte(u8, u8),
+    /// `LD Vx, Vy`
+    LdReg(u8, u8),
+    /// `OR Vx, Vy`
+    Or(u8, u8),
+    /// `AND Vx, Vy`
+    And(u8, u8),
+    /// `XOR Vx, Vy`
+    Xor(u8, u8),
+    /// `ADD Vx, Vy` - VF is set to the carry
+    AddReg(u8, u8),
+    /// `SUB Vx, Vy` - VF is set to NOT borrow
+    Sub(u8, u8),
+    /// `SHR Vx {, Vy}` - VF is set to the bit shifted out
+    Shr(u8, u8),
+    /// `SUBN Vx, Vy` - VF is set to NOT borrow
+    Subn(u8, u8),
+    /// `SHL Vx {, Vy}` - VF is set to the bit shifted out
+    Shl(u8, u8),
+    /// `SNE Vx, Vy` - Skip next instruction if `Vx != Vy`
+    SneReg(u8, u8),
+    /// `LD I, addr`
+    LdI(u16),
+    /// `RND Vx, byte` - Set `Vx` to a random byte AND `byte`
+    Rnd(u8, u8),
+    /// `DRW Vx, Vy, n` - Draw an `n` byte sprite from `I` at `(Vx, Vy)`
+    Drw(u8, u8, u8),
+    /// `Bxyn` - Set the CHIP-8X foreground colour of the zones given by `Vx` and `Vx+1` to
+    /// `Vy`. Zones are 4 pixel rows high if `n` is 0, and `n` single rows otherwise.
+    Color(u8, u8, u8),
+    /// `SKP Vx` - Skip next instruction if key `Vx` is pressed
+    Skp(u8),
+    /// `SKNP Vx` - Skip next instruction if key `Vx` isn't pressed
+    Sknp(u8),
+    /// `SKP2 Vx` - Skip next instruction if key `Vx` is pressed on the second CHIP-8X keypad
+    Skp2(u8),
+    /// `SKNP2 Vx` - Skip next instruction if key `Vx` isn't pressed on the second keypad
+    Sknp2(u8),
+    /// `LD Vx, DT`
+    LdVxDt(u8),
+    /// `LD Vx, K` - Wait for a key press and store it in `Vx`
+    LdKey(u8),
+    /// `LD DT, Vx`
+    LdDt(u8),
+    /// `LD ST, Vx`
+    LdSt(u8),
+    /// `ADD I, Vx`
+    AddI(u8),
+    /// `LD F, Vx` - Point `I` at the font sprite for digit `Vx`
+    LdFont(u8),
+    /// `LD HF, Vx` - Point `I` at the SUPER-CHIP large font sprite for digit `Vx`
+    LdLargeFont(u8),
+    /// `LD B, Vx` - Store the decimal digits of `Vx` at `I`
+    LdBcd(u8),
+    /// `LD [I], Vx` - Store `V0` to `Vx` at `I`
+    Store(u8),
+    /// `LD Vx, [I]` - Load `V0` to `Vx` from `I`
+    Load(u8),
+    /// `OUT Vx` - Write `Vx` to the CHIP-8X I/O port
+    Out(u8),
+    /// `IN Vx` - Wait for a byte on the CHIP-8X I/O port and store it in `Vx`
+    In(u8),
+    /// Not a CHIP-8 instruction
+    Invalid,
+}
 
-//         match instruction & 0xF000 {
-//             0x00 => {
-//                 match kk {
-//                     0xE0 => Instruction::CLS,
-//                     0xEE => Instruction::RET,
-//                     _ => Instruction::Unrecognized,
-//                 }
-//             },
-//             0x01 => Instruction::JP(nnn),
-//             0x02 => Instruction::CALL(nnn),
-//             0x03 => Instruction::SE((x, kk)),
-//             0x04 => Instruction::SNE((x, kk)),
-//             0x05 => Instruction::
-//             _ => Instruction::Unrecognized,
-//         }
-//     }
-// }
+impl Instruction {
+    pub fn decode(instruction: u16, platform: Platform) -> Self {
+        let nnn = instruction & 0x0FFF; // 12-bit address, lower 12 bits of instruction
+        let kk = (instruction & 0x00FF) as u8; // 8-bit value, lower 8 bits of instruction
+        let n = (instruction & 0x000F) as u8; // 4-bit value, lowest 4 bits of instruction
+        let x = ((instruction & 0x0F00) >> 8) as u8; // 4-bit value, lower 4 bits of upper byte
+        let y = ((instruction & 0x00F0) >> 4) as u8; // 4-bit value, upper 4 bits of lower byte
+
+        match (instruction & 0xF000) >> 12 {
+            0x00 if platform == Platform::MegaChip && x != 0 => match (x, kk) {
+                (0x1, _) => Instruction::LdILong(kk),
+                (0x2, _) => Instruction::LoadPalette(kk),
+                (0x3, _) => Instruction::SpriteWidth(kk),
+                (0x4, _) => Instruction::SpriteHeight(kk),
+                (0x5, _) => Instruction::Alpha(kk),
+                (0x6, 0x00 | 0x01) => Instruction::PlaySample(n),
+                (0x7, 0x00) => Instruction::StopSample,
+                (0x8, 0x00..=0x05) => Instruction::Blend(n),
+                (0x9, _) => Instruction::CollisionColor(kk),
+                _ => Instruction::Invalid,
+            },
+            0x00 => match kk {
+                0xE0 => Instruction::Cls,
+                0x10 if x == 0 && platform == Platform::MegaChip => Instruction::MegaOff,
+                0x11 if x == 0 && platform == Platform::MegaChip => Instruction::MegaOn,
+                0x30 if x == 2 && platform == Platform::Hires => Instruction::HiresCls,
+                0xA0 if x == 2 && platform == Platform::Chip8x => Instruction::CycleBackground,
+                0xEE => Instruction::Ret,
+                _ => Instruction::Invalid,
+            },
+            0x01 => Instruction::Jp(nnn),
+            0x02 => Instruction::Call(nnn),
+            0x03 => Instruction::SeByte(x, kk),
+            0x04 => Instruction::SneByte(x, kk),
+            0x05 => Instruction::SeReg(x, y),
+            0x06 => Instruction::LdByte(x, kk),
+            0x07 => Instruction::AddByte(x, kk),
+            0x08 => match n {
+                0x00 => Instruction::LdReg(x, y),
+                0x01 => Instruction::Or(x, y),
+                0x02 => Instruction::And(x, y),
+                0x03 => Instruction::Xor(x, y),
+                0x04 => Instruction::AddReg(x, y),
+                0x05 => Instruction::Sub(x, y),
+                0x06 => Instruction::Shr(x, y),
+                0x07 => Instruction::Subn(x, y),
+                0x0E => Instruction::Shl(x, y),
+                _ => Instruction::Invalid,
+            },
+            0x09 => Instruction::SneReg(x, y),
+            0x0A => Instruction::LdI(nnn),
+            0x0B if platform == Platform::Chip8x => Instruction::Color(x, y, n),
+            0x0C => Instruction::Rnd(x, kk),
+            0x0D => Instruction::Drw(x, y, n),
+            0x0E => match kk {
+                0x9E => Instruction::Skp(x),
+                0xA1 => Instruction::Sknp(x),
+                0xF2 if platform == Platform::Chip8x => Instruction::Skp2(x),
+                0xF5 if platform == Platform::Chip8x => Instruction::Sknp2(x),
+                _ => Instruction::Invalid,
+            },
+            0x0F => match kk {
+                0x07 => Instruction::LdVxDt(x),
+                0x0A => Instruction::LdKey(x),
+                0x15 => Instruction::LdDt(x),
+                0x18 => Instruction::LdSt(x),
+                0x1E => Instruction::AddI(x),
+                0x29 => Instruction::LdFont(x),
+                0x30 => Instruction::LdLargeFont(x),
+                0x33 => Instruction::LdBcd(x),
+                0x55 => Instruction::Store(x),
+                0x65 => Instruction::Load(x),
+                0xF8 if platform == Platform::Chip8x => Instruction::Out(x),
+                0xFB if platform == Platform::Chip8x => Instruction::In(x),
+                _ => Instruction::Invalid,
+            },
+            _ => Instruction::Invalid,
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Instruction::Cls => write!(f, "CLS"),
+            Instruction::HiresCls => write!(f, "CLS (hires)"),
+            Instruction::CycleBackground => write!(f, "BGCYCLE"),
+            Instruction::MegaOff => write!(f, "MEGAOFF"),
+            Instruction::MegaOn => write!(f, "MEGAON"),
+            Instruction::LdILong(kk) => write!(f, "LDHI I, 0x{kk:02X}...."),
+            Instruction::LoadPalette(kk) => write!(f, "LDPAL {kk}"),
+            Instruction::SpriteWidth(kk) => write!(f, "SPRW {kk}"),
+            Instruction::SpriteHeight(kk) => write!(f, "SPRH {kk}"),
+            Instruction::Alpha(kk) => write!(f, "ALPHA 0x{kk:02X}"),
+            Instruction::PlaySample(n) => write!(f, "DIGISND {n}"),
+            Instruction::StopSample => write!(f, "STOPSND"),
+            Instruction::Blend(n) => write!(f, "BMODE {n}"),
+            Instruction::CollisionColor(kk) => write!(f, "CCOL {kk}"),
+            Instruction::Ret => write!(f, "RET"),
+            Instruction::Jp(nnn) => write!(f, "JP {nnn:04X}"),
+            Instruction::Call(nnn) => write!(f, "CALL {nnn:04X}"),
+            Instruction::SeByte(x, kk) => write!(f, "SE V{x:X}, {kk:02X}"),
+            Instruction::SneByte(x, kk) => write!(f, "SNE V{x:X}, {kk:02X}"),
+            Instruction::SeReg(x, y) => write!(f, "SE V{x:X}, V{y:X}"),
+            Instruction::LdByte(x, kk) => write!(f, "LD V{x:X}, 0x{kk:02X}"),
+            Instruction::AddByte(x, kk) => write!(f, "ADD V{x:X}, 0x{kk:02X}"),
+            Instruction::LdReg(x, y) => write!(f, "LD V{x:X}, V{y:X}"),
+            Instruction::Or(x, y) => write!(f, "OR V{x:X}, V{y:X}"),
+            Instruction::And(x, y) => write!(f, "AND V{x:X}, V{y:X}"),
+            Instruction::Xor(x, y) => write!(f, "XOR V{x:X}, V{y:X}"),
+            Instruction::AddReg(x, y) => write!(f, "ADD V{x:X}, V{y:X}"),
+            Instruction::Sub(x, y) => write!(f, "SUB V{x:X}, V{y:X}"),
+            Instruction::Shr(x, y) => write!(f, "SHR V{x:X} {{, V{y:X}}}"),
+            Instruction::Subn(x, y) => write!(f, "SUBN V{x:X}, V{y:X}"),
+            Instruction::Shl(x, y) => write!(f, "SHL V{x:X} {{, V{y:X}}}"),
+            Instruction::SneReg(x, y) => write!(f, "SNE V{x:X}, V{y:X}"),
+            Instruction::LdI(nnn) => write!(f, "LD I, 0x{nnn:04X}"),
+            Instruction::Rnd(x, kk) => write!(f, "RND V{x:X}, {kk:02X}"),
+            Instruction::Drw(x, y, n) => write!(f, "DRW V{x:X}, V{y:X}, {n}"),
+            Instruction::Color(x, y, n) => write!(f, "COL V{x:X}, V{y:X}, {n}"),
+            Instruction::Skp(x) => write!(f, "SKP V{x:X}"),
+            Instruction::Sknp(x) => write!(f, "SKNP V{x:X}"),
+            Instruction::Skp2(x) => write!(f, "SKP2 V{x:X}"),
+            Instruction::Sknp2(x) => write!(f, "SKNP2 V{x:X}"),
+            Instruction::LdVxDt(x) => write!(f, "LD V{x:X}, DT"),
+            Instruction::LdKey(x) => write!(f, "LD V{x:X}, K"),
+            Instruction::LdDt(x) => write!(f, "LD DT, V{x:X}"),
+            Instruction::LdSt(x) => write!(f, "LD ST, V{x:X}"),
+            Instruction::AddI(x) => write!(f, "ADD I, V{x:X}"),
+            Instruction::LdFont(x) => write!(f, "LD F, V{x:X}"),
+            Instruction::LdLargeFont(x) => write!(f, "LD HF, V{x:X}"),
+            Instruction::LdBcd(x) => write!(f, "LD B, V{x:X}"),
+            Instruction::Store(x) => write!(f, "LD [I], V{x:X}"),
+            Instruction::Load(x) => write!(f, "LD V{x:X}, [I]"),
+            Instruction::Out(x) => write!(f, "OUT V{x:X}"),
+            Instruction::In(x) => write!(f, "IN V{x:X}"),
+            Instruction::Invalid => write!(f, "invalid"),
+        }
+    }
+}
@@ -1,15 +1,49 @@
+use std::collections::VecDeque;
+
+/// Key events kept for Fx0A. Older events are dropped once this many are queued.
+const MAX_EVENTS: usize = 16;
+
+/// A key changing state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct KeyEvent {
+    pub key: u8,
+    pub pressed: bool,
+}
+
 #[derive(Debug)]
 pub(crate) struct Keyboard {
     keys: [bool; 16],
+    /// The second CHIP-8X keypad, only checked by ExF2 and ExF5
+    keys2: [bool; 16],
+    /// Presses and releases not yet seen by Fx0A, oldest first
+    events: VecDeque<KeyEvent>,
+    /// Keys checked by the program since the last `take_polled`, bit n for key n
+    polled: u16,
 }
 
 impl Keyboard {
     pub fn new() -> Self {
-        Self { keys: [false; 16] }
+        Self {
+            keys: [false; 16],
+            keys2: [false; 16],
+            events: VecDeque::with_capacity(MAX_EVENTS),
+            polled: 0,
+        }
     }
 
+    /// Set whether a key is held, queueing an event if it changed.
     pub fn set_key_state(&mut self, key: usize, state: bool) {
+        if self.keys[key] == state {
+            return;
+        }
         self.keys[key] = state;
+        if self.events.len() == MAX_EVENTS {
+            self.events.pop_front();
+        }
+        self.events.push_back(KeyEvent {
+            key: key as u8,
+            pressed: state,
+        });
     }
 
     /// checks if a given key is pressed
@@ -17,13 +51,37 @@ impl Keyboard {
         self.keys[key as usize]
     }
 
-    /// get first key that is pressed, otherwise return None
-    pub fn get_pressed(&self) -> Option<u8> {
-        for (i, key) in self.keys.iter().enumerate() {
-            if *key == true {
-                return Some(i as u8);
-            }
-        }
-        None
+    pub fn set_keypad2_state(&mut self, key: usize, state: bool) {
+        self.keys2[key] = state;
+    }
+
+    pub fn is_keypad2_pressed(&self, key: u8) -> bool {
+        self.keys2[key as usize & 0xF]
+    }
+
+    /// Check a key for the program, remembering that it was polled.
+    pub fn poll(&mut self, key: u8) -> bool {
+        self.polled |= 1 << (key & 0xF);
+        self.is_pressed(key)
+    }
+
+    /// Mark every key as polled, for Fx0A which accepts any of them.
+    pub fn poll_all(&mut self) {
+        self.polled = u16::MAX;
+    }
+
+    /// Get the keys polled since the last call and start over.
+    pub fn take_polled(&mut self) -> u16 {
+        std::mem::take(&mut self.polled)
+    }
+
+    /// Take the oldest queued key event.
+    pub fn next_event(&mut self) -> Option<KeyEvent> {
+        self.events.pop_front()
+    }
+
+    /// Forget queued events, so only keys pressed from now on are seen.
+    pub fn clear_events(&mut self) {
+        self.events.clear();
     }
 }
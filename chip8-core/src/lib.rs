@@ -1,7 +1,17 @@
 pub mod chip8;
+pub mod color;
+pub mod env;
+pub mod font;
+mod expr;
+pub mod hooks;
+pub mod megachip;
+pub mod platform;
+pub mod profiler;
+pub mod rom;
+pub mod scheduler;
 mod memory;
 mod cpu;
 mod display;
 mod bus;
 mod keyboard;
-// mod instructions;
\ No newline at end of file
+mod instructions;
\ No newline at end of file
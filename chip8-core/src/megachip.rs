@@ -0,0 +1,199 @@
+//! The MegaChip-8 extension: a 256x192 screen of palette colours and digitized sound.
+
+/// Width of the screen in MegaChip mode.
+pub const WIDTH: usize = 256;
+/// Height of the screen in MegaChip mode.
+pub const HEIGHT: usize = 192;
+
+/// How a MegaChip sprite's colours are combined with the screen, set by `080n`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BlendMode {
+    /// Sprites replace the screen, with the opacity set by `05kk`
+    #[default]
+    Normal,
+    /// Sprites are drawn at 25% opacity
+    Percent25,
+    /// Sprites are drawn at 50% opacity
+    Percent50,
+    /// Sprites are drawn at 75% opacity
+    Percent75,
+    /// Sprite colours are added to the screen
+    Add,
+    /// Sprite colours are multiplied with the screen
+    Multiply,
+}
+
+impl BlendMode {
+    /// The mode for the `n` of `080n`.
+    pub fn from_code(n: u8) -> Option<Self> {
+        match n {
+            0 => Some(BlendMode::Normal),
+            1 => Some(BlendMode::Percent25),
+            2 => Some(BlendMode::Percent50),
+            3 => Some(BlendMode::Percent75),
+            4 => Some(BlendMode::Add),
+            5 => Some(BlendMode::Multiply),
+            _ => None,
+        }
+    }
+
+    /// Combine a sprite pixel with the screen pixel under it, both ARGB.
+    fn blend(self, alpha: u8, src: u32, dst: u32) -> u32 {
+        let channels = |color: u32| [(color >> 16) as u8, (color >> 8) as u8, color as u8];
+        let (src, dst) = (channels(src), channels(dst));
+        let mix = |opacity: u32| {
+            move |s: u8, d: u8| ((s as u32 * opacity + d as u32 * (255 - opacity)) / 255) as u8
+        };
+        let combine: &dyn Fn(u8, u8) -> u8 = match self {
+            BlendMode::Normal => &mix(alpha as u32),
+            BlendMode::Percent25 => &mix(64),
+            BlendMode::Percent50 => &mix(128),
+            BlendMode::Percent75 => &mix(191),
+            BlendMode::Add => &|s: u8, d: u8| s.saturating_add(d),
+            BlendMode::Multiply => &|s: u8, d: u8| (s as u32 * d as u32 / 255) as u8,
+        };
+        let [r, g, b] = [0, 1, 2].map(|i| combine(src[i], dst[i]));
+        0xFF00_0000 | (r as u32) << 16 | (g as u32) << 8 | b as u32
+    }
+}
+
+/// The screen in MegaChip mode. Sprites are drawn to a back buffer, which `CLS` shows and
+/// then clears.
+#[derive(Debug, Clone)]
+pub(crate) struct MegaScreen {
+    /// Palette index of each pixel in the back buffer, for collisions
+    indices: Box<[u8]>,
+    /// ARGB colour of each pixel in the back buffer
+    buffer: Box<[u32]>,
+    /// ARGB pixels being shown
+    visible: Box<[u32]>,
+    /// 1 for each shown pixel that isn't black, for frontends that only draw monochrome
+    lit: Box<[u8]>,
+    /// ARGB colours, loaded by `02kk`. Colour 0 is transparent in sprites.
+    pub palette: [u32; 256],
+    pub sprite_width: usize,
+    pub sprite_height: usize,
+    pub alpha: u8,
+    pub blend: BlendMode,
+    /// Drawing over a pixel of this palette index sets VF
+    pub collision_color: u8,
+}
+
+impl MegaScreen {
+    pub fn new() -> Self {
+        Self {
+            indices: vec![0; WIDTH * HEIGHT].into_boxed_slice(),
+            buffer: vec![0xFF00_0000; WIDTH * HEIGHT].into_boxed_slice(),
+            visible: vec![0xFF00_0000; WIDTH * HEIGHT].into_boxed_slice(),
+            lit: vec![0; WIDTH * HEIGHT].into_boxed_slice(),
+            palette: [0xFF00_0000; 256],
+            sprite_width: 0,
+            sprite_height: 0,
+            alpha: 0xFF,
+            blend: BlendMode::Normal,
+            collision_color: 0,
+        }
+    }
+
+    /// ARGB pixels being shown.
+    pub fn visible(&self) -> &[u32] {
+        &self.visible
+    }
+
+    pub fn lit(&self) -> &[u8] {
+        &self.lit
+    }
+
+    /// Show the back buffer and start the next frame with a clear one.
+    pub fn present(&mut self) {
+        self.visible.copy_from_slice(&self.buffer);
+        for (lit, pixel) in self.lit.iter_mut().zip(self.visible.iter()) {
+            *lit = (pixel & 0x00FF_FFFF != 0) as u8;
+        }
+        self.buffer.fill(0xFF00_0000);
+        self.indices.fill(0);
+    }
+
+    /// Draw a sprite of `sprite_width` by `sprite_height` palette indices to the back buffer,
+    /// clipped at the edges. Returns true if it was drawn over the collision colour.
+    pub fn draw(&mut self, x: usize, y: usize, sprite: &[u8]) -> bool {
+        let mut collided = false;
+        if self.sprite_width == 0 {
+            return collided;
+        }
+        for (row, line) in sprite.chunks(self.sprite_width).enumerate() {
+            let py = y + row;
+            if py >= HEIGHT {
+                break;
+            }
+            for (column, index) in line.iter().enumerate() {
+                let px = x + column;
+                if px >= WIDTH {
+                    break;
+                }
+                if *index == 0 {
+                    continue;
+                }
+                let pixel = py * WIDTH + px;
+                let under = self.indices[pixel];
+                collided |= under != 0 && under == self.collision_color;
+                self.indices[pixel] = *index;
+                let color = self.palette[*index as usize];
+                self.buffer[pixel] = self.blend.blend(self.alpha, color, self.buffer[pixel]);
+            }
+        }
+        collided
+    }
+}
+
+/// A digitized sound played by `060n`: 8-bit unsigned samples at `rate` Hz.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sample {
+    pub rate: u16,
+    pub data: Vec<u8>,
+    pub looping: bool,
+    /// Playback position in samples
+    position: f64,
+}
+
+impl Sample {
+    /// Read a sound from memory: a 2 byte sample rate, 3 byte length and an unused byte,
+    /// followed by the samples. Returns None if it runs past the end of `memory`.
+    pub(crate) fn parse(memory: &[u8], looping: bool) -> Option<Self> {
+        let header = memory.get(..6)?;
+        let rate = u16::from_be_bytes([header[0], header[1]]);
+        let length = u32::from_be_bytes([0, header[2], header[3], header[4]]) as usize;
+        let data = memory.get(6..6 + length)?.to_vec();
+        Some(Self {
+            rate,
+            data,
+            looping,
+            position: 0.0,
+        })
+    }
+
+    /// Resample the sound into `out` at `output_rate` Hz, continuing from where the last call
+    /// stopped. Returns false once a sound that doesn't loop has finished; the rest of `out`
+    /// is filled with silence.
+    pub fn fill(&mut self, out: &mut [i16], output_rate: u32) -> bool {
+        let step = self.rate as f64 / output_rate as f64;
+        let mut playing = !self.data.is_empty();
+        for sample in out.iter_mut() {
+            if playing && self.position as usize >= self.data.len() {
+                if self.looping {
+                    self.position = 0.0;
+                } else {
+                    playing = false;
+                }
+            }
+            *sample = if playing {
+                let value = self.data[self.position as usize];
+                self.position += step;
+                (value as i16 - 0x80) << 8
+            } else {
+                0
+            };
+        }
+        playing
+    }
+}
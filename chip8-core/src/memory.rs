@@ -1,39 +1,41 @@
+use crate::{
+    font::{FontSet, LARGE_FONT, SMALL_FONT_SIZE},
+    instructions::Instruction,
+};
+
 const MEMORY_SIZE: usize = 4096; // 4 KiB
+/// Instructions can only be fetched from the first 64 KiB, since PC is 16 bits
+const MAX_DECODED: usize = 0x10000;
 
 #[derive(Debug)]
 pub(crate) struct Memory {
     memory: Box<[u8]>,
+    /// Opcode and decoded instruction starting at each address, filled in as instructions
+    /// execute and cleared when either of their bytes is written
+    decoded: Box<[Option<(u16, Instruction)>]>,
 }
 
 impl Memory {
     pub fn new() -> Self {
+        Self::with_size(MEMORY_SIZE)
+    }
+
+    pub fn with_size(size: usize) -> Self {
         Self {
-            memory: vec![0; MEMORY_SIZE].into_boxed_slice(),
+            memory: vec![0; size].into_boxed_slice(),
+            decoded: vec![None; size.min(MAX_DECODED)].into_boxed_slice(),
         }
     }
 
-    /// Load standard font into memory at 0x00..0x80
-    pub fn load_font(&mut self) {
-        let font: [u8; 80] = [
-            0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
-            0x20, 0x60, 0x20, 0x20, 0x70, // 1
-            0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
-            0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
-            0x90, 0x90, 0xF0, 0x10, 0x10, // 4
-            0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
-            0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
-            0xF0, 0x10, 0x20, 0x40, 0x40, // 7
-            0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
-            0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
-            0xF0, 0x90, 0xF0, 0x90, 0x90, // A
-            0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
-            0xF0, 0x80, 0x80, 0x80, 0xF0, // C
-            0xE0, 0x90, 0x90, 0x90, 0xE0, // D
-            0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
-            0xF0, 0x80, 0xF0, 0x80, 0x80, // F
-        ];
-
-        self.write_slice(0, &font);
+    /// Load a font set into memory at `address`, the small font followed by the large one.
+    pub fn load_font(&mut self, font: FontSet, address: usize) {
+        self.write_slice(address, font.small());
+        self.write_slice(address + SMALL_FONT_SIZE, &LARGE_FONT);
+    }
+
+    /// Get the entire contents of memory.
+    pub fn get(&self) -> &[u8] {
+        &self.memory
     }
 
     /// Read byte from given address.
@@ -49,12 +51,14 @@ impl Memory {
     /// Write byte at given address.
     pub fn write_byte(&mut self, address: usize, value: u8) {
         self.memory[address] = value;
+        self.invalidate(address, 1);
     }
 
     /// Write slice into memory at given address.
     pub fn write_slice(&mut self, address: usize, value: &[u8]) {
         let size = value.len();
         self.memory[address..address + size].copy_from_slice(value);
+        self.invalidate(address, size);
     }
 
     /// Set `length` bytes to `value` at `address`.
@@ -62,13 +66,41 @@ impl Memory {
         for i in 0..length {
             self.memory[address + i] = value;
         }
+        self.invalidate(address, length);
+    }
+
+    /// Get the cached decoding of the instruction at `address`.
+    pub fn decoded(&self, address: u16) -> Option<(u16, Instruction)> {
+        self.decoded.get(address as usize).copied().flatten()
+    }
+
+    /// Cache the decoding of the instruction at `address`.
+    pub fn cache_decoded(&mut self, address: u16, decoded: (u16, Instruction)) {
+        self.decoded[address as usize] = Some(decoded);
+    }
+
+    /// Forget decoded instructions that overlap `length` bytes written at `address`,
+    /// including one starting on the byte before.
+    fn invalidate(&mut self, address: usize, length: usize) {
+        if length == 0 {
+            return;
+        }
+        let end = (address + length).min(self.decoded.len());
+        if let Some(decoded) = self.decoded.get_mut(address.saturating_sub(1)..end) {
+            decoded.fill(None);
+        }
+    }
+
+    /// Forget every decoded instruction, for when instructions decode differently.
+    pub fn clear_decoded(&mut self) {
+        self.decoded.fill(None);
     }
 }
 
 impl Default for Memory {
     fn default() -> Self {
         let mut memory = Memory::new();
-        memory.load_font();
+        memory.load_font(FontSet::default(), 0);
         memory
     }
 }
@@ -86,4 +118,17 @@ mod tests {
 
         assert_eq!(mem.read_slice(0, 12), &[1, 0, 1, 2, 3, 4, 5, 0, 9, 9, 9, 9]);
     }
+
+    #[test]
+    fn writes_invalidate_decoded() {
+        let mut mem = Memory::new();
+        for address in [0x1FF, 0x200, 0x201, 0x202] {
+            mem.cache_decoded(address, (0x00E0, Instruction::Cls));
+        }
+        mem.write_byte(0x201, 0x12);
+        assert_eq!(mem.decoded(0x1FF), Some((0x00E0, Instruction::Cls)));
+        assert_eq!(mem.decoded(0x200), None);
+        assert_eq!(mem.decoded(0x201), None);
+        assert_eq!(mem.decoded(0x202), Some((0x00E0, Instruction::Cls)));
+    }
 }
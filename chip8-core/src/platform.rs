@@ -0,0 +1,92 @@
+//! Machines CHIP-8 ran on, which differ in where programs are loaded and in the display size.
+
+use std::{fmt, str::FromStr};
+
+/// A CHIP-8 platform. Changing it changes the memory layout, so the loaded ROM is reloaded.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Platform {
+    /// The COSMAC VIP and most later interpreters, with programs at 0x200 and a 64x32 display
+    #[default]
+    Chip8,
+    /// The ETI-660, which loads programs at 0x600
+    Eti660,
+    /// Hi-res CHIP-8 for the COSMAC VIP, with a 64x64 display. Programs start with `JP 0x260`
+    /// into the hi-res extension of the interpreter, which is redirected to 0x2C0 where the
+    /// program itself continues, and clear the screen with `0x0230`.
+    Hires,
+    /// CHIP-8X for the COSMAC VIP with the VP-590 colour board and VP-595 sound board, with
+    /// programs at 0x300, colour zones, a second keypad and an I/O port
+    Chip8x,
+    /// MegaChip-8, which adds a 256x192 colour mode, digitized sound and 16 MiB of memory
+    MegaChip,
+}
+
+impl Platform {
+    pub const ALL: [Platform; 5] = [
+        Platform::Chip8,
+        Platform::Eti660,
+        Platform::Hires,
+        Platform::Chip8x,
+        Platform::MegaChip,
+    ];
+
+    /// Name used on the command line and in [`FromStr`].
+    pub fn name(self) -> &'static str {
+        match self {
+            Platform::Chip8 => "chip8",
+            Platform::Eti660 => "eti660",
+            Platform::Hires => "hires",
+            Platform::Chip8x => "chip8x",
+            Platform::MegaChip => "megachip",
+        }
+    }
+
+    /// Address programs are loaded at and start executing from.
+    pub fn program_start(self) -> u16 {
+        match self {
+            Platform::Chip8 | Platform::Hires | Platform::MegaChip => 0x200,
+            Platform::Eti660 => 0x600,
+            Platform::Chip8x => 0x300,
+        }
+    }
+
+    /// Display height in pixels at reset. Every platform's display is 64 pixels wide, until
+    /// a MegaChip program enters MegaChip mode.
+    pub fn display_height(self) -> usize {
+        match self {
+            Platform::Chip8 | Platform::Eti660 | Platform::Chip8x | Platform::MegaChip => 32,
+            Platform::Hires => 64,
+        }
+    }
+
+    /// Size of memory in bytes.
+    pub fn memory_size(self) -> usize {
+        match self {
+            Platform::MegaChip => 0x100_0000,
+            _ => 0x1000,
+        }
+    }
+}
+
+impl fmt::Display for Platform {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Platform {
+    type Err = std::io::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Platform::ALL
+            .into_iter()
+            .find(|platform| platform.name().eq_ignore_ascii_case(s))
+            .ok_or_else(|| {
+                let names: Vec<&str> = Platform::ALL.iter().map(|p| p.name()).collect();
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!("unknown platform {s}, expected one of {}", names.join(", ")),
+                )
+            })
+    }
+}
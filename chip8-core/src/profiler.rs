@@ -0,0 +1,277 @@
+//! Execution profiling: how often each address and kind of instruction runs, which calls it
+//! ran under, and how long the program spends waiting.
+//!
+//! Profiling is started with [`crate::chip8::Chip8::set_profiling`] and the results read with
+//! [`crate::chip8::Chip8::profile`].
+
+use std::{
+    collections::HashMap,
+    fmt,
+    io::{self, Write},
+};
+
+use crate::instructions::Instruction;
+
+/// A `LD Vx, DT` executed again within this many instructions is a busy loop waiting for
+/// the delay timer.
+const TIMER_LOOP_LENGTH: u64 = 4;
+
+/// Function entry recorded for calls made before profiling started or a save state loaded.
+const UNKNOWN_FUNCTION: u16 = u16::MAX;
+
+/// Kinds of instructions counted separately by the profiler.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum InstructionClass {
+    /// `JP`, `CALL` and `RET`
+    Flow,
+    /// Conditional skips on registers
+    Skip,
+    /// Loads and arithmetic on V registers, including `RND`
+    Register,
+    /// `I` and loads and stores through it
+    Memory,
+    /// Drawing and other display instructions
+    Display,
+    /// Reading and setting the delay timer
+    Timer,
+    /// The sound timer and digitized sound
+    Sound,
+    /// Keys and the CHIP-8X I/O port
+    Input,
+}
+
+impl InstructionClass {
+    pub const ALL: [InstructionClass; 8] = [
+        InstructionClass::Flow,
+        InstructionClass::Skip,
+        InstructionClass::Register,
+        InstructionClass::Memory,
+        InstructionClass::Display,
+        InstructionClass::Timer,
+        InstructionClass::Sound,
+        InstructionClass::Input,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            InstructionClass::Flow => "flow",
+            InstructionClass::Skip => "skip",
+            InstructionClass::Register => "register",
+            InstructionClass::Memory => "memory",
+            InstructionClass::Display => "display",
+            InstructionClass::Timer => "timer",
+            InstructionClass::Sound => "sound",
+            InstructionClass::Input => "input",
+        }
+    }
+
+    fn of(instruction: Instruction) -> Option<Self> {
+        use Instruction::*;
+        Some(match instruction {
+            Jp(_) | Call(_) | Ret => InstructionClass::Flow,
+            SeByte(..) | SneByte(..) | SeReg(..) | SneReg(..) => InstructionClass::Skip,
+            LdByte(..) | AddByte(..) | LdReg(..) | Or(..) | And(..) | Xor(..) | AddReg(..)
+            | Sub(..) | Shr(..) | Subn(..) | Shl(..) | Rnd(..) => InstructionClass::Register,
+            LdI(_) | LdILong(_) | AddI(_) | LdFont(_) | LdLargeFont(_) | LdBcd(_) | Store(_)
+            | Load(_) => InstructionClass::Memory,
+            Cls | HiresCls | Drw(..) | CycleBackground | Color(..) | MegaOff | MegaOn
+            | LoadPalette(_) | SpriteWidth(_) | SpriteHeight(_) | Alpha(_) | Blend(_)
+            | CollisionColor(_) => InstructionClass::Display,
+            LdVxDt(_) | LdDt(_) => InstructionClass::Timer,
+            LdSt(_) | PlaySample(_) | StopSample => InstructionClass::Sound,
+            Skp(_) | Sknp(_) | LdKey(_) | Skp2(_) | Sknp2(_) | Out(_) | In(_) => {
+                InstructionClass::Input
+            }
+            Invalid => return None,
+        })
+    }
+}
+
+impl fmt::Display for InstructionClass {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Execution counts collected while profiling. Waiting is measured in frames, 60 to the
+/// second, counted whenever the timers tick.
+#[derive(Debug, Clone, Default)]
+pub struct Profile {
+    /// Executions of the instruction at each address, grown as higher addresses execute
+    counts: Vec<u64>,
+    classes: [u64; InstructionClass::ALL.len()],
+    total: u64,
+    frames: u64,
+    key_wait_frames: u64,
+    timer_wait_frames: u64,
+    /// Entry address of each function on the call stack, outermost first
+    functions: Vec<u16>,
+    /// Instructions executed under each call stack, indexed by `stack_index`
+    stacks: Vec<(Vec<u16>, u64)>,
+    stack_index: HashMap<Vec<u16>, usize>,
+    /// Entry in `stacks` for the current call stack
+    current_stack: Option<usize>,
+    /// The program is blocked on Fx0A
+    waiting_for_key: bool,
+    /// Address of the last `LD Vx, DT` and the instruction count when it executed
+    timer_read: Option<(u16, u64)>,
+    in_timer_loop: bool,
+}
+
+impl Profile {
+    /// Executions of the instruction at `address`.
+    pub fn count(&self, address: u16) -> u64 {
+        self.counts.get(address as usize).copied().unwrap_or(0)
+    }
+
+    /// Executions at each address, starting from 0. Addresses past the end never executed.
+    pub fn counts(&self) -> &[u64] {
+        &self.counts
+    }
+
+    /// The highest execution count of any address.
+    pub fn max_count(&self) -> u64 {
+        self.counts.iter().copied().max().unwrap_or(0)
+    }
+
+    pub fn class_count(&self, class: InstructionClass) -> u64 {
+        self.classes[class as usize]
+    }
+
+    /// Instructions executed while profiling.
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    /// Frames run while profiling.
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// Frames the program spent blocked on Fx0A waiting for a key.
+    pub fn key_wait_frames(&self) -> u64 {
+        self.key_wait_frames
+    }
+
+    /// Frames the program spent in tight loops reading the delay timer.
+    pub fn timer_wait_frames(&self) -> u64 {
+        self.timer_wait_frames
+    }
+
+    /// Write one line per executed address: address, opcode, instruction and count. `opcode`
+    /// looks up the opcode and disassembly at an address.
+    pub fn write_csv<W: Write>(
+        &self,
+        w: &mut W,
+        opcode: impl Fn(u16) -> Option<(u16, String)>,
+    ) -> io::Result<()> {
+        writeln!(w, "address,opcode,instruction,count")?;
+        for (address, count) in self.counts.iter().enumerate() {
+            if *count == 0 {
+                continue;
+            }
+            let address = address as u16;
+            let (opcode, text) = opcode(address).unwrap_or_default();
+            writeln!(w, "0x{address:03X},{opcode:04X},\"{text}\",{count}")?;
+        }
+        Ok(())
+    }
+
+    /// Write the instructions executed under each call stack as folded stacks, one line per
+    /// stack, for flamegraph tools. Functions are named after their entry address, under a
+    /// root frame named `root`.
+    pub fn write_folded<W: Write>(&self, w: &mut W, root: &str) -> io::Result<()> {
+        for (functions, count) in &self.stacks {
+            if *count == 0 {
+                continue;
+            }
+            write!(w, "{root}")?;
+            for function in functions {
+                if *function == UNKNOWN_FUNCTION {
+                    write!(w, ";unknown")?;
+                } else {
+                    write!(w, ";sub_{function:03X}")?;
+                }
+            }
+            writeln!(w, " {count}")?;
+        }
+        Ok(())
+    }
+
+    /// Count the instruction that just executed at `pc`. `depth` is the call stack depth
+    /// afterwards and `waiting` whether the program is now blocked.
+    pub(crate) fn record(
+        &mut self,
+        pc: u16,
+        instruction: Instruction,
+        depth: usize,
+        waiting: bool,
+    ) {
+        let waited = std::mem::replace(&mut self.waiting_for_key, false);
+        if waiting {
+            self.waiting_for_key = matches!(instruction, Instruction::LdKey(_));
+            if waited {
+                // a blocked instruction executes again and again, which is counted as time
+                return;
+            }
+        }
+        let Some(class) = InstructionClass::of(instruction) else {
+            return;
+        };
+        if self.counts.len() <= pc as usize {
+            self.counts.resize(pc as usize + 1, 0);
+        }
+        self.counts[pc as usize] += 1;
+        self.classes[class as usize] += 1;
+        self.total += 1;
+
+        if let Instruction::LdVxDt(_) = instruction {
+            if let Some((address, at)) = self.timer_read {
+                self.in_timer_loop = address == pc && self.total - at <= TIMER_LOOP_LENGTH;
+            }
+            self.timer_read = Some((pc, self.total));
+        } else if let Some((_, at)) = self.timer_read {
+            if self.total - at > TIMER_LOOP_LENGTH {
+                self.in_timer_loop = false;
+            }
+        }
+
+        // the instruction counts towards the function it's in, before a call or return
+        let index = match self.current_stack {
+            Some(index) => index,
+            None => {
+                let index = *self
+                    .stack_index
+                    .entry(self.functions.clone())
+                    .or_insert_with(|| {
+                        self.stacks.push((self.functions.clone(), 0));
+                        self.stacks.len() - 1
+                    });
+                self.current_stack = Some(index);
+                index
+            }
+        };
+        self.stacks[index].1 += 1;
+
+        if let Instruction::Call(nnn) = instruction {
+            if depth > self.functions.len() {
+                self.functions.push(nnn);
+                self.current_stack = None;
+            }
+        }
+        if depth != self.functions.len() {
+            self.functions.resize(depth, UNKNOWN_FUNCTION);
+            self.current_stack = None;
+        }
+    }
+
+    /// Count a frame, when the timers tick.
+    pub(crate) fn tick(&mut self) {
+        self.frames += 1;
+        if self.waiting_for_key {
+            self.key_wait_frames += 1;
+        } else if self.in_timer_loop {
+            self.timer_wait_frames += 1;
+        }
+    }
+}
@@ -0,0 +1,125 @@
+//! Reading ROM images from files, including ROMs packed in `.zip` and `.gz` archives.
+
+use std::{
+    io::{Error, ErrorKind, Read, Seek},
+    path::Path,
+};
+
+/// Extensions recognised as ROMs when choosing a file from a zip archive.
+pub const ROM_EXTENSIONS: &[&str] = &["ch8", "c8", "rom", "bin"];
+
+/// Archives are decompressed up to this size, far more than any ROM, so a corrupt or
+/// malicious archive can't exhaust memory.
+const MAX_DECOMPRESSED: u64 = 16 * 1024 * 1024;
+
+/// Read a ROM file. Files ending in `.gz` are decompressed and files ending in `.zip` are
+/// searched for a ROM with [`read_zip`].
+pub fn read<P: AsRef<Path>>(path: P) -> std::io::Result<Vec<u8>> {
+    let path = path.as_ref();
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase());
+    let file = std::fs::File::open(path)?;
+    match extension.as_deref() {
+        Some("gz") => read_gzip(file),
+        Some("zip") => read_zip(file),
+        _ => read_limited(file),
+    }
+}
+
+/// Decompress a gzipped ROM.
+pub fn read_gzip<R: Read>(reader: R) -> std::io::Result<Vec<u8>> {
+    read_limited(flate2::read::GzDecoder::new(reader))
+}
+
+/// Extract the ROM from a zip archive. The archive must contain exactly one file with one of
+/// the [`ROM_EXTENSIONS`], or else exactly one file.
+pub fn read_zip<R: Read + Seek>(reader: R) -> std::io::Result<Vec<u8>> {
+    let mut archive = zip::ZipArchive::new(reader).map_err(invalid)?;
+    let files: Vec<String> = archive
+        .file_names()
+        .filter(|name| !name.ends_with('/'))
+        .map(str::to_string)
+        .collect();
+    let roms: Vec<&String> = files.iter().filter(|name| is_rom_name(name)).collect();
+    let name = match (roms.as_slice(), files.as_slice()) {
+        ([rom], _) => rom.to_string(),
+        ([], [file]) => file.clone(),
+        ([], []) => {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "archive doesn't contain any files",
+            ))
+        }
+        ([], files) => {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("archive doesn't contain a rom: {}", files.join(", ")),
+            ))
+        }
+        (roms, _) => {
+            let names: Vec<&str> = roms.iter().map(|name| name.as_str()).collect();
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("archive contains several roms: {}", names.join(", ")),
+            ));
+        }
+    };
+    let rom = read_limited(archive.by_name(&name).map_err(invalid)?)?;
+    Ok(rom)
+}
+
+fn is_rom_name(name: &str) -> bool {
+    Path::new(name)
+        .extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| ROM_EXTENSIONS.contains(&e.to_ascii_lowercase().as_str()))
+}
+
+fn read_limited<R: Read>(reader: R) -> std::io::Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    reader.take(MAX_DECOMPRESSED + 1).read_to_end(&mut buf)?;
+    if buf.len() as u64 > MAX_DECOMPRESSED {
+        return Err(Error::new(ErrorKind::InvalidData, "rom file is too large"));
+    }
+    Ok(buf)
+}
+
+fn invalid(e: zip::result::ZipError) -> Error {
+    Error::new(ErrorKind::InvalidData, e)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Write};
+
+    use super::*;
+
+    fn zip(files: &[(&str, &[u8])]) -> Cursor<Vec<u8>> {
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, data) in files {
+            writer
+                .start_file(*name, zip::write::SimpleFileOptions::default())
+                .unwrap();
+            writer.write_all(data).unwrap();
+        }
+        let mut archive = writer.finish().unwrap();
+        archive.set_position(0);
+        archive
+    }
+
+    #[test]
+    fn archives() {
+        let mut gz = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gz.write_all(&[0x12, 0x00]).unwrap();
+        assert_eq!(read_gzip(&gz.finish().unwrap()[..]).unwrap(), [0x12, 0x00]);
+
+        let archive = zip(&[("README.txt", b"hello"), ("games/PONG.ch8", &[0x6A, 0x02])]);
+        assert_eq!(read_zip(archive).unwrap(), [0x6A, 0x02]);
+        assert_eq!(read_zip(zip(&[("PONG", &[0x6A])])).unwrap(), [0x6A]);
+        assert!(read_zip(zip(&[("a.ch8", &[1]), ("b.ch8", &[2])])).is_err());
+        assert!(read_zip(zip(&[("a.txt", &[1]), ("b.txt", &[2])])).is_err());
+        assert!(read_zip(Cursor::new(b"not a zip")).is_err());
+    }
+}
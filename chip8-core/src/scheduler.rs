@@ -0,0 +1,112 @@
+//! Converts real time into emulated frames, so emulation speed doesn't depend on how often the
+//! frontend renders.
+//!
+//! Each frame the frontend passes the real time that has elapsed to [`Scheduler::advance`] and
+//! runs the number of 60 Hz frames it returns. For each of those frames it executes
+//! [`Scheduler::frame_cycles`] instructions and then ticks the timers once, so the timers run
+//! at exactly 60 Hz of emulated time and the CPU at the configured speed.
+
+use std::time::Duration;
+
+/// Rate of the delay and sound timers, and of emulated frames.
+const FRAME_RATE: f64 = 60.0;
+/// Elapsed time is capped to this much per call, so a long stall doesn't cause a burst of
+/// catch-up frames.
+const MAX_ELAPSED: Duration = Duration::from_millis(250);
+
+#[derive(Debug, Clone)]
+pub struct Scheduler {
+    /// Emulated seconds per real second
+    rate: f64,
+    /// Emulated time not yet run, in seconds
+    time: f64,
+    /// Fraction of an instruction carried over between frames
+    cycles: f64,
+}
+
+impl Scheduler {
+    pub fn new() -> Self {
+        Self {
+            rate: 1.0,
+            time: 0.0,
+            cycles: 0.0,
+        }
+    }
+
+    /// Set how fast emulated time passes relative to real time, e.g. 4.0 to fast-forward or
+    /// 0.25 for slow motion.
+    pub fn set_rate(&mut self, rate: f64) {
+        self.rate = rate.max(0.0);
+    }
+
+    pub fn rate(&self) -> f64 {
+        self.rate
+    }
+
+    /// Add elapsed real time and return the number of frames now due.
+    pub fn advance(&mut self, elapsed: Duration) -> u32 {
+        self.time += elapsed.min(MAX_ELAPSED).as_secs_f64() * self.rate;
+        let frames = (self.time * FRAME_RATE).floor();
+        self.time -= frames / FRAME_RATE;
+        frames as u32
+    }
+
+    /// Number of instructions to execute in the next frame at `speed` Hz. Fractions are carried
+    /// over, so speeds that aren't a multiple of 60 average out exactly.
+    pub fn frame_cycles(&mut self, speed: u32) -> u32 {
+        self.cycles += speed as f64 / FRAME_RATE;
+        let cycles = self.cycles.floor();
+        self.cycles -= cycles;
+        cycles as u32
+    }
+
+    /// Discard any time and cycles that haven't been run yet, e.g. after unpausing.
+    pub fn reset(&mut self) {
+        self.time = 0.0;
+        self.cycles = 0.0;
+    }
+}
+
+impl Default for Scheduler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn independent_of_render_rate() {
+        for fps in [30, 60, 144] {
+            let mut scheduler = Scheduler::new();
+            let mut frames = 0;
+            let mut cycles = 0;
+            for _ in 0..fps {
+                for _ in 0..scheduler.advance(Duration::from_secs_f64(1.0 / fps as f64)) {
+                    frames += 1;
+                    cycles += scheduler.frame_cycles(500);
+                }
+            }
+            // one second of real time, give or take rounding of the last frame
+            assert!((59..=60).contains(&frames), "{fps} fps ran {frames} frames");
+            assert!(
+                (491..=500).contains(&cycles),
+                "{fps} fps ran {cycles} cycles"
+            );
+        }
+    }
+
+    #[test]
+    fn rate() {
+        let mut scheduler = Scheduler::new();
+        scheduler.set_rate(4.0);
+        assert_eq!(scheduler.advance(Duration::from_millis(100)), 24);
+        scheduler.set_rate(0.5);
+        assert_eq!(scheduler.advance(Duration::from_millis(100)), 3);
+        // capped to a quarter of a second
+        scheduler.set_rate(1.0);
+        assert_eq!(scheduler.advance(Duration::from_secs(10)), 15);
+    }
+}
@@ -0,0 +1,126 @@
+#ifndef CHIP8_H
+#define CHIP8_H
+
+/* Generated by cbindgen from chip8-ffi/src/lib.rs, do not edit by hand. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// Width of the framebuffer in pixels.
+#define CHIP8_DISPLAY_WIDTH 64
+
+// Height of the framebuffer in pixels.
+#define CHIP8_DISPLAY_HEIGHT 32
+
+// Number of keys on the hex keypad.
+#define CHIP8_KEY_COUNT 16
+
+// Opaque emulator handle.
+typedef struct Chip8 Chip8;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Create a new emulator with no ROM loaded. Release it with `chip8_free`.
+struct Chip8 *chip8_new(void);
+
+// Destroy an emulator created by `chip8_new`. Passing null is a no-op.
+//
+// # Safety
+//
+// `chip8` must be null or a handle returned by `chip8_new` that has not been freed yet.
+void chip8_free(struct Chip8 *chip8);
+
+// Load a ROM image, resetting the machine first if a ROM was already loaded.
+// Returns false if the ROM doesn't fit in memory.
+//
+// # Safety
+//
+// `chip8` must be a valid handle and `data` must be valid for reads of `len` bytes.
+bool chip8_load_rom_bytes(struct Chip8 *chip8, const uint8_t *data, size_t len);
+
+// Reset the machine to its power-on state, unloading the ROM.
+//
+// # Safety
+//
+// `chip8` must be a valid handle.
+void chip8_reset(struct Chip8 *chip8);
+
+// Set emulation clock speed in Hz, used by `chip8_run_frame`.
+//
+// # Safety
+//
+// `chip8` must be a valid handle.
+void chip8_set_speed(struct Chip8 *chip8, uint32_t speed);
+
+// Execute a single instruction. Returns false if the program has faulted, e.g. on an invalid
+// instruction, after which it stays stopped until it's reset.
+//
+// # Safety
+//
+// `chip8` must be a valid handle.
+bool chip8_step(struct Chip8 *chip8);
+
+// Execute one 60 Hz frame's worth of instructions. Returns false if the program has faulted.
+//
+// # Safety
+//
+// `chip8` must be a valid handle.
+bool chip8_run_frame(struct Chip8 *chip8);
+
+// Press or release a key on the hex keypad. Returns false if `key` is out of range.
+//
+// # Safety
+//
+// `chip8` must be a valid handle.
+bool chip8_set_key(struct Chip8 *chip8, uint8_t key, bool pressed);
+
+// Get the framebuffer, `CHIP8_DISPLAY_WIDTH * CHIP8_DISPLAY_HEIGHT` bytes in row-major order
+// with one byte per pixel (0 = off, 1 = on). The pointer stays valid until the next call that
+// mutates the emulator.
+//
+// # Safety
+//
+// `chip8` must be a valid handle.
+const uint8_t *chip8_framebuffer(const struct Chip8 *chip8);
+
+// Get a counter that changes whenever any pixel of the framebuffer does, to skip redrawing
+// unchanged frames.
+//
+// # Safety
+//
+// `chip8` must be a valid handle.
+uint64_t chip8_framebuffer_generation(const struct Chip8 *chip8);
+
+// Returns true while the sound timer is running.
+//
+// # Safety
+//
+// `chip8` must be a valid handle.
+bool chip8_sound_active(const struct Chip8 *chip8);
+
+// Serialize the machine state into `buf`. Returns the size of the state in bytes, which is
+// only written if `len` is large enough, so call with a null `buf` first to query the size.
+// Returns 0 if the state could not be saved.
+//
+// # Safety
+//
+// `chip8` must be a valid handle and `buf` must be null or valid for writes of `len` bytes.
+size_t chip8_save_state(const struct Chip8 *chip8, uint8_t *buf, size_t len);
+
+// Restore state produced by `chip8_save_state`. Returns false if the data is not a valid state.
+//
+// # Safety
+//
+// `chip8` must be a valid handle and `data` must be valid for reads of `len` bytes.
+bool chip8_load_state(struct Chip8 *chip8, const uint8_t *data, size_t len);
+
+#ifdef __cplusplus
+} // extern "C"
+#endif // __cplusplus
+
+#endif /* CHIP8_H */
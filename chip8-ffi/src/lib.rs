@@ -0,0 +1,184 @@
+//! Stable C ABI over [`chip8_core`] for embedding the emulator in other languages.
+//!
+//! Every function takes an opaque `Chip8` handle created by [`chip8_new`] and released with
+//! [`chip8_free`]. The C header in `include/chip8.h` is generated from this file by `build.rs`.
+
+use std::panic::{catch_unwind, AssertUnwindSafe};
+
+/// Width of the framebuffer in pixels.
+pub const CHIP8_DISPLAY_WIDTH: usize = 64;
+/// Height of the framebuffer in pixels.
+pub const CHIP8_DISPLAY_HEIGHT: usize = 32;
+/// Number of keys on the hex keypad.
+pub const CHIP8_KEY_COUNT: u8 = 16;
+
+/// Opaque emulator handle.
+pub struct Chip8(chip8_core::chip8::Chip8);
+
+/// Create a new emulator with no ROM loaded. Release it with `chip8_free`.
+#[no_mangle]
+pub extern "C" fn chip8_new() -> *mut Chip8 {
+    let mut chip8 = chip8_core::chip8::Chip8::default();
+    chip8.set_trace(false);
+    Box::into_raw(Box::new(Chip8(chip8)))
+}
+
+/// Destroy an emulator created by `chip8_new`. Passing null is a no-op.
+///
+/// # Safety
+///
+/// `chip8` must be null or a handle returned by `chip8_new` that has not been freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_free(chip8: *mut Chip8) {
+    if !chip8.is_null() {
+        drop(Box::from_raw(chip8));
+    }
+}
+
+/// Load a ROM image, resetting the machine first if a ROM was already loaded.
+/// Returns false if the ROM doesn't fit in memory.
+///
+/// # Safety
+///
+/// `chip8` must be a valid handle and `data` must be valid for reads of `len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_load_rom_bytes(
+    chip8: *mut Chip8,
+    data: *const u8,
+    len: usize,
+) -> bool {
+    if data.is_null() {
+        return false;
+    }
+    let rom = std::slice::from_raw_parts(data, len);
+    (*chip8).0.load_rom_bytes(rom).is_ok()
+}
+
+/// Reset the machine to its power-on state, unloading the ROM.
+///
+/// # Safety
+///
+/// `chip8` must be a valid handle.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_reset(chip8: *mut Chip8) {
+    (*chip8).0.reset();
+}
+
+/// Set emulation clock speed in Hz, used by `chip8_run_frame`.
+///
+/// # Safety
+///
+/// `chip8` must be a valid handle.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_set_speed(chip8: *mut Chip8, speed: u32) {
+    (*chip8).0.set_speed(speed);
+}
+
+/// Execute a single instruction. Returns false if the program has faulted, e.g. on an invalid
+/// instruction, after which it stays stopped until it's reset.
+///
+/// # Safety
+///
+/// `chip8` must be a valid handle.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_step(chip8: *mut Chip8) -> bool {
+    let chip8 = &mut (*chip8).0;
+    catch_unwind(AssertUnwindSafe(|| {
+        chip8.step();
+        chip8.fault().is_none()
+    }))
+    .unwrap_or(false)
+}
+
+/// Execute one 60 Hz frame's worth of instructions. Returns false if the program has faulted.
+///
+/// # Safety
+///
+/// `chip8` must be a valid handle.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_run_frame(chip8: *mut Chip8) -> bool {
+    let chip8 = &mut (*chip8).0;
+    catch_unwind(AssertUnwindSafe(|| chip8.run_frame().stop))
+        .is_ok_and(|stop| !matches!(stop, Some(chip8_core::chip8::Stop::Fault(_))))
+}
+
+/// Press or release a key on the hex keypad. Returns false if `key` is out of range.
+///
+/// # Safety
+///
+/// `chip8` must be a valid handle.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_set_key(chip8: *mut Chip8, key: u8, pressed: bool) -> bool {
+    if key >= CHIP8_KEY_COUNT {
+        return false;
+    }
+    (*chip8).0.set_key_state(key as usize, pressed);
+    true
+}
+
+/// Get the framebuffer, `CHIP8_DISPLAY_WIDTH * CHIP8_DISPLAY_HEIGHT` bytes in row-major order
+/// with one byte per pixel (0 = off, 1 = on). The pointer stays valid until the next call that
+/// mutates the emulator.
+///
+/// # Safety
+///
+/// `chip8` must be a valid handle.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_framebuffer(chip8: *const Chip8) -> *const u8 {
+    (*chip8).0.get_display().as_ptr()
+}
+
+/// Get a counter that changes whenever any pixel of the framebuffer does, to skip redrawing
+/// unchanged frames.
+///
+/// # Safety
+///
+/// `chip8` must be a valid handle.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_framebuffer_generation(chip8: *const Chip8) -> u64 {
+    (*chip8).0.display_generation()
+}
+
+/// Returns true while the sound timer is running.
+///
+/// # Safety
+///
+/// `chip8` must be a valid handle.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_sound_active(chip8: *const Chip8) -> bool {
+    (*chip8).0.is_sound_active()
+}
+
+/// Serialize the machine state into `buf`. Returns the size of the state in bytes, which is
+/// only written if `len` is large enough, so call with a null `buf` first to query the size.
+/// Returns 0 if the state could not be saved.
+///
+/// # Safety
+///
+/// `chip8` must be a valid handle and `buf` must be null or valid for writes of `len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_save_state(chip8: *const Chip8, buf: *mut u8, len: usize) -> usize {
+    match (*chip8).0.save_state() {
+        Ok(state) => {
+            if !buf.is_null() && state.len() <= len {
+                std::ptr::copy_nonoverlapping(state.as_ptr(), buf, state.len());
+            }
+            state.len()
+        }
+        Err(_) => 0,
+    }
+}
+
+/// Restore state produced by `chip8_save_state`. Returns false if the data is not a valid state.
+///
+/// # Safety
+///
+/// `chip8` must be a valid handle and `data` must be valid for reads of `len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_load_state(chip8: *mut Chip8, data: *const u8, len: usize) -> bool {
+    if data.is_null() {
+        return false;
+    }
+    let state = std::slice::from_raw_parts(data, len);
+    (*chip8).0.load_state(state).is_ok()
+}
@@ -0,0 +1,58 @@
+/*
+ * Smoke test for the chip8-ffi C ABI.
+ *
+ * From the repository root:
+ *
+ *     cargo build -p chip8-ffi
+ *     cc chip8-ffi/tests/c/smoke.c -Ichip8-ffi/include -Ltarget/debug -lchip8_ffi -o target/smoke
+ *     LD_LIBRARY_PATH=target/debug ./target/smoke
+ */
+
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+#include "chip8.h"
+
+#define CHECK(cond)                                                   \
+    do {                                                              \
+        if (!(cond)) {                                                \
+            fprintf(stderr, "%s:%d: check failed: %s\n", __FILE__,    \
+                    __LINE__, #cond);                                 \
+            return 1;                                                 \
+        }                                                             \
+    } while (0)
+
+/* CLS; LD I, 0x000; DRW V0, V0, 5; JP 0x206 */
+static const uint8_t ROM[] = {0x00, 0xE0, 0xA0, 0x00, 0xD0, 0x05, 0x12, 0x06};
+
+int main(void) {
+    Chip8 *chip8 = chip8_new();
+    CHECK(chip8 != NULL);
+
+    CHECK(chip8_load_rom_bytes(chip8, ROM, sizeof(ROM)));
+    CHECK(chip8_set_key(chip8, 0xF, true));
+    CHECK(!chip8_set_key(chip8, CHIP8_KEY_COUNT, true));
+    CHECK(chip8_run_frame(chip8));
+
+    /* the top row of the "0" glyph is 0xF0 */
+    const uint8_t *fb = chip8_framebuffer(chip8);
+    CHECK(fb[0] == 1 && fb[3] == 1 && fb[4] == 0);
+    CHECK(fb[CHIP8_DISPLAY_WIDTH * 4] == 1);
+
+    size_t size = chip8_save_state(chip8, NULL, 0);
+    CHECK(size > 0);
+    uint8_t *state = malloc(size);
+    CHECK(chip8_save_state(chip8, state, size) == size);
+
+    chip8_reset(chip8);
+    CHECK(chip8_framebuffer(chip8)[0] == 0);
+    CHECK(chip8_load_state(chip8, state, size));
+    CHECK(chip8_framebuffer(chip8)[0] == 1);
+    CHECK(!chip8_load_state(chip8, state, 3));
+
+    free(state);
+    chip8_free(chip8);
+    printf("ok\n");
+    return 0;
+}
@@ -0,0 +1,655 @@
+//! GDB remote serial protocol stub for debugging a running [`Chip8`].
+//!
+//! Connect with `target remote localhost:<port>`. The stub describes the machine with a custom
+//! target description, so GDB shows the registers `v0`-`vf`, `i`, `pc`, `sp`, `dt` and `st`.
+//! Memory reads and writes, software breakpoints, write/read/access watchpoints, single-step,
+//! continue and Ctrl-C are supported.
+//!
+//! Like the JSON-RPC server, clients are served on a background thread and packets are queued
+//! until the emulator thread calls [`GdbStub::poll`]. The frontend executes instructions
+//! through [`GdbStub::step`], which stops at breakpoints and watchpoints while a debugger is
+//! attached. Watchpoints are implemented with the machine's read and write hooks, and only
+//! take the events of their own hooks, so a script's hooks keep working alongside them.
+
+use std::{
+    collections::HashSet,
+    io::{BufRead, BufReader, Write},
+    net::{TcpListener, ToSocketAddrs},
+    sync::mpsc::{channel, Receiver, Sender},
+};
+
+use chip8_core::{
+    chip8::{Chip8, Fault, Register},
+    hooks::{HookEvent, HookId},
+};
+
+/// Registers in the order of the target description and the `g` packet.
+const REGISTERS: [(Register, usize); 21] = [
+    (Register::V(0x0), 1),
+    (Register::V(0x1), 1),
+    (Register::V(0x2), 1),
+    (Register::V(0x3), 1),
+    (Register::V(0x4), 1),
+    (Register::V(0x5), 1),
+    (Register::V(0x6), 1),
+    (Register::V(0x7), 1),
+    (Register::V(0x8), 1),
+    (Register::V(0x9), 1),
+    (Register::V(0xA), 1),
+    (Register::V(0xB), 1),
+    (Register::V(0xC), 1),
+    (Register::V(0xD), 1),
+    (Register::V(0xE), 1),
+    (Register::V(0xF), 1),
+    (Register::I, 2),
+    (Register::Pc, 2),
+    (Register::Sp, 1),
+    (Register::Dt, 1),
+    (Register::St, 1),
+];
+
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.chip8.core">
+    <reg name="v0" bitsize="8" type="uint8"/>
+    <reg name="v1" bitsize="8" type="uint8"/>
+    <reg name="v2" bitsize="8" type="uint8"/>
+    <reg name="v3" bitsize="8" type="uint8"/>
+    <reg name="v4" bitsize="8" type="uint8"/>
+    <reg name="v5" bitsize="8" type="uint8"/>
+    <reg name="v6" bitsize="8" type="uint8"/>
+    <reg name="v7" bitsize="8" type="uint8"/>
+    <reg name="v8" bitsize="8" type="uint8"/>
+    <reg name="v9" bitsize="8" type="uint8"/>
+    <reg name="va" bitsize="8" type="uint8"/>
+    <reg name="vb" bitsize="8" type="uint8"/>
+    <reg name="vc" bitsize="8" type="uint8"/>
+    <reg name="vd" bitsize="8" type="uint8"/>
+    <reg name="ve" bitsize="8" type="uint8"/>
+    <reg name="vf" bitsize="8" type="uint8"/>
+    <reg name="i" bitsize="16" type="data_ptr"/>
+    <reg name="pc" bitsize="16" type="code_ptr"/>
+    <reg name="sp" bitsize="8" type="uint8"/>
+    <reg name="dt" bitsize="8" type="uint8"/>
+    <reg name="st" bitsize="8" type="uint8"/>
+  </feature>
+</target>
+"#;
+
+/// Stop reply for breakpoints and single steps.
+const SIGTRAP: &str = "S05";
+/// Stop reply for Ctrl-C.
+const SIGINT: &str = "S02";
+/// Stop reply for invalid instructions.
+const SIGILL: &str = "S04";
+/// Stop reply for other faults.
+const SIGSEGV: &str = "S0b";
+
+enum Message {
+    Connected(Box<dyn Write + Send>),
+    Packet(String),
+    /// A packet arrived with a bad checksum and should be retransmitted
+    Corrupt,
+    /// Ctrl-C
+    Interrupt,
+    Disconnected,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum WatchKind {
+    Write,
+    Read,
+    Access,
+}
+
+impl WatchKind {
+    /// Name used in stop replies.
+    fn name(self) -> &'static str {
+        match self {
+            WatchKind::Write => "watch",
+            WatchKind::Read => "rwatch",
+            WatchKind::Access => "awatch",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Watchpoint {
+    kind: WatchKind,
+    /// Inclusive address range
+    start: u16,
+    end: u16,
+}
+
+pub struct GdbStub {
+    messages: Receiver<Message>,
+    client: Option<Box<dyn Write + Send>>,
+    breakpoints: HashSet<u16>,
+    /// Watchpoints and the hooks added for them
+    watchpoints: Vec<(Watchpoint, Vec<HookId>)>,
+    /// Whether the debugger has the machine stopped
+    halted: bool,
+    /// Execute the next instruction even if it has a breakpoint, so continuing from a
+    /// breakpoint doesn't stop again straight away
+    resuming: bool,
+}
+
+impl GdbStub {
+    /// Listen for a debugger on a TCP address such as `127.0.0.1:1234`. One debugger is served
+    /// at a time.
+    pub fn listen<A: ToSocketAddrs>(address: A) -> std::io::Result<GdbStub> {
+        let listener = TcpListener::bind(address)?;
+        let (tx, rx) = channel();
+        std::thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let Ok(writer) = stream.try_clone() else {
+                    continue;
+                };
+                if tx.send(Message::Connected(Box::new(writer))).is_err() {
+                    return;
+                }
+                serve(BufReader::new(stream), &tx);
+                if tx.send(Message::Disconnected).is_err() {
+                    return;
+                }
+            }
+        });
+        Ok(Self::new(rx))
+    }
+
+    fn new(messages: Receiver<Message>) -> Self {
+        Self {
+            messages,
+            client: None,
+            breakpoints: HashSet::new(),
+            watchpoints: Vec::new(),
+            halted: false,
+            resuming: false,
+        }
+    }
+
+    /// Returns true if a debugger is connected.
+    pub fn is_attached(&self) -> bool {
+        self.client.is_some()
+    }
+
+    /// Returns true if a debugger has the machine stopped.
+    pub fn is_halted(&self) -> bool {
+        self.client.is_some() && self.halted
+    }
+
+    /// Handle every queued packet.
+    pub fn poll(&mut self, chip8: &mut Chip8) {
+        while let Ok(message) = self.messages.try_recv() {
+            match message {
+                Message::Connected(client) => {
+                    // the debugger expects the target to be stopped when it attaches
+                    self.client = Some(client);
+                    self.halted = true;
+                }
+                Message::Disconnected => {
+                    self.detach(chip8);
+                    self.client = None;
+                }
+                Message::Corrupt => self.write(b"-"),
+                Message::Interrupt => {
+                    if !self.halted {
+                        self.stop(SIGINT);
+                    }
+                }
+                Message::Packet(packet) => {
+                    self.write(b"+");
+                    if let Some(reply) = self.handle(chip8, &packet) {
+                        self.send(&reply);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Execute one instruction, unless the debugger has the machine stopped or it reaches a
+    /// breakpoint. Returns false if nothing was executed, in which case the frontend should stop
+    /// stepping for this frame. Timers are left to the frontend, see
+    /// [`Chip8::step_instruction`].
+    pub fn step(&mut self, chip8: &mut Chip8) -> bool {
+        if self.client.is_none() {
+            chip8.step_instruction();
+            return true;
+        }
+        if self.halted {
+            return false;
+        }
+        let pc = chip8.get_register(Register::Pc);
+        if !self.resuming && self.breakpoints.contains(&pc) {
+            self.stop(SIGTRAP);
+            return false;
+        }
+        self.resuming = false;
+        chip8.step_instruction();
+        if let Some(fault) = chip8.fault() {
+            self.stop(fault_signal(fault));
+        } else if let Some(reply) = self.watch_hit(chip8) {
+            self.stop(&reply);
+        }
+        true
+    }
+
+    /// Build the reply to a single packet, or `None` if the reply is sent when the machine
+    /// next stops.
+    fn handle(&mut self, chip8: &mut Chip8, packet: &str) -> Option<String> {
+        // the command is the first character, which needn't be a single byte
+        let split = packet.char_indices().nth(1).map_or(packet.len(), |(i, _)| i);
+        let (command, args) = packet.split_at(split);
+        let reply = match command {
+            "?" => chip8.fault().map_or(SIGTRAP, fault_signal).to_string(),
+            "q" => query(args),
+            "H" | "T" => "OK".to_string(),
+            "g" => {
+                let mut bytes = Vec::new();
+                for (register, size) in REGISTERS {
+                    let value = chip8.get_register(register);
+                    bytes.extend_from_slice(&value.to_le_bytes()[..size]);
+                }
+                to_hex(&bytes)
+            }
+            "G" => match from_hex(args) {
+                Some(bytes) if bytes.len() == register_bytes() => {
+                    let mut bytes = &bytes[..];
+                    for (register, size) in REGISTERS {
+                        chip8.set_register(register, le_value(&bytes[..size]));
+                        bytes = &bytes[size..];
+                    }
+                    "OK".to_string()
+                }
+                _ => error(),
+            },
+            "p" => match parse_hex(args).and_then(|n| REGISTERS.get(n as usize)) {
+                Some((register, size)) => {
+                    to_hex(&chip8.get_register(*register).to_le_bytes()[..*size])
+                }
+                None => error(),
+            },
+            "P" => {
+                let register = args.split_once('=').and_then(|(n, value)| {
+                    let (register, size) = REGISTERS.get(parse_hex(n)? as usize)?;
+                    let value = from_hex(value).filter(|v| v.len() == *size)?;
+                    Some((*register, le_value(&value)))
+                });
+                match register {
+                    Some((register, value)) => {
+                        chip8.set_register(register, value);
+                        "OK".to_string()
+                    }
+                    None => error(),
+                }
+            }
+            "m" => {
+                let memory = parse_range(args)
+                    .and_then(|(address, length)| chip8.read_memory(address, length));
+                match memory {
+                    Some(bytes) => to_hex(bytes),
+                    None => error(),
+                }
+            }
+            "M" => {
+                let written = args.split_once(':').and_then(|(range, data)| {
+                    let (address, length) = parse_range(range)?;
+                    let data = from_hex(data).filter(|data| data.len() == length)?;
+                    Some(chip8.write_memory(address, &data))
+                });
+                if written == Some(true) {
+                    "OK".to_string()
+                } else {
+                    error()
+                }
+            }
+            "c" => {
+                if let Some(address) = parse_hex(args) {
+                    chip8.set_register(Register::Pc, address as u16);
+                }
+                self.halted = false;
+                self.resuming = true;
+                return None;
+            }
+            "s" => {
+                if let Some(address) = parse_hex(args) {
+                    chip8.set_register(Register::Pc, address as u16);
+                }
+                if chip8.is_loaded() {
+                    chip8.step_instruction();
+                }
+                match chip8.fault() {
+                    Some(fault) => fault_signal(fault).to_string(),
+                    None => self.watch_hit(chip8).unwrap_or_else(|| SIGTRAP.to_string()),
+                }
+            }
+            "Z" | "z" => self.set_point(chip8, command == "Z", args),
+            "D" => {
+                self.detach(chip8);
+                "OK".to_string()
+            }
+            "k" => {
+                self.detach(chip8);
+                return None;
+            }
+            // unsupported packets get an empty reply
+            _ => String::new(),
+        };
+        Some(reply)
+    }
+
+    /// Insert or remove a breakpoint or watchpoint, from a `Z`/`z` packet like `2,300,1`.
+    fn set_point(&mut self, chip8: &mut Chip8, insert: bool, args: &str) -> String {
+        let mut parts = args.split(',');
+        let (Some(kind), Some(address), Some(length)) = (
+            parts.next(),
+            parts.next().and_then(parse_hex),
+            parts.next().and_then(parse_hex),
+        ) else {
+            return error();
+        };
+        let address = address as u16;
+        let kind = match kind {
+            // hardware breakpoints are no different from software ones here
+            "0" | "1" => {
+                if insert {
+                    self.breakpoints.insert(address);
+                } else {
+                    self.breakpoints.remove(&address);
+                }
+                return "OK".to_string();
+            }
+            "2" => WatchKind::Write,
+            "3" => WatchKind::Read,
+            "4" => WatchKind::Access,
+            _ => return String::new(),
+        };
+        let watchpoint = Watchpoint {
+            kind,
+            start: address,
+            end: address.wrapping_add((length as u16).max(1) - 1),
+        };
+        if insert {
+            let hooks = add_watch_hooks(chip8, watchpoint);
+            self.watchpoints.push((watchpoint, hooks));
+        } else if let Some(index) = self.watchpoints.iter().position(|(w, _)| *w == watchpoint) {
+            let (_, hooks) = self.watchpoints.remove(index);
+            remove_watch_hooks(chip8, hooks);
+        }
+        "OK".to_string()
+    }
+
+    /// Check the hook events from the last instruction against the watchpoints, returning a
+    /// stop reply for the first hit.
+    fn watch_hit(&mut self, chip8: &mut Chip8) -> Option<String> {
+        let hooks: Vec<HookId> = self
+            .watchpoints
+            .iter()
+            .flat_map(|(_, hooks)| hooks.iter().copied())
+            .collect();
+        if !chip8.has_hook_events(&hooks) {
+            return None;
+        }
+        chip8
+            .take_hook_events(&hooks)
+            .into_iter()
+            .find_map(|(id, event)| {
+                let address = match event {
+                    HookEvent::Write { address, .. } | HookEvent::Read { address, .. } => address,
+                    HookEvent::Pc(_) => return None,
+                };
+                self.watchpoints
+                    .iter()
+                    .find(|(_, hooks)| hooks.contains(&id))
+                    .map(|(w, _)| format!("T05{}:{address:x};", w.kind.name()))
+            })
+    }
+
+    /// Remove every breakpoint and watchpoint and let the machine run freely.
+    fn detach(&mut self, chip8: &mut Chip8) {
+        self.breakpoints.clear();
+        for (_, hooks) in self.watchpoints.drain(..) {
+            remove_watch_hooks(chip8, hooks);
+        }
+        self.halted = false;
+        self.resuming = false;
+    }
+
+    fn stop(&mut self, reply: &str) {
+        self.halted = true;
+        self.send(reply);
+    }
+
+    /// Send a packet with its checksum.
+    fn send(&mut self, data: &str) {
+        let checksum = data.bytes().fold(0u8, |sum, b| sum.wrapping_add(b));
+        self.write(format!("${data}#{checksum:02x}").as_bytes());
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        if let Some(client) = &mut self.client {
+            if client
+                .write_all(bytes)
+                .and_then(|_| client.flush())
+                .is_err()
+            {
+                // the reader thread notices the disconnect and detaches
+                self.halted = false;
+            }
+        }
+    }
+}
+
+/// Read packets from one connection until it closes, forwarding each to the emulator thread.
+fn serve<R: BufRead>(reader: R, messages: &Sender<Message>) {
+    let mut bytes = reader.bytes().map_while(Result::ok);
+    while let Some(byte) = bytes.next() {
+        let message = match byte {
+            0x03 => Message::Interrupt,
+            b'$' => {
+                let data: Vec<u8> = bytes.by_ref().take_while(|b| *b != b'#').collect();
+                let checksum: Vec<u8> = bytes.by_ref().take(2).collect();
+                let expected = data.iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+                let valid = std::str::from_utf8(&checksum)
+                    .ok()
+                    .and_then(|c| u8::from_str_radix(c, 16).ok())
+                    == Some(expected);
+                match String::from_utf8(data) {
+                    Ok(packet) if valid => Message::Packet(packet),
+                    _ => Message::Corrupt,
+                }
+            }
+            // acknowledgements and anything outside a packet
+            _ => continue,
+        };
+        if messages.send(message).is_err() {
+            return;
+        }
+    }
+}
+
+/// Reply to a `q` packet.
+fn query(args: &str) -> String {
+    if args.starts_with("Supported") {
+        "PacketSize=1000;qXfer:features:read+".to_string()
+    } else if let Some(args) = args.strip_prefix("Xfer:features:read:target.xml:") {
+        match parse_range(args) {
+            Some((offset, length)) => {
+                let xml = TARGET_XML.get(offset.min(TARGET_XML.len())..).unwrap_or("");
+                if xml.len() > length {
+                    format!("m{}", &xml[..length])
+                } else {
+                    format!("l{xml}")
+                }
+            }
+            None => error(),
+        }
+    } else {
+        match args {
+            "Attached" => "1".to_string(),
+            "C" => "QC1".to_string(),
+            "fThreadInfo" => "m1".to_string(),
+            "sThreadInfo" => "l".to_string(),
+            _ => String::new(),
+        }
+    }
+}
+
+fn fault_signal(fault: Fault) -> &'static str {
+    match fault {
+        Fault::InvalidInstruction { .. } => SIGILL,
+        _ => SIGSEGV,
+    }
+}
+
+fn add_watch_hooks(chip8: &mut Chip8, watchpoint: Watchpoint) -> Vec<HookId> {
+    let Watchpoint { kind, start, end } = watchpoint;
+    let mut hooks = Vec::new();
+    if kind != WatchKind::Read {
+        hooks.push(chip8.add_write_hook(start, end));
+    }
+    if kind != WatchKind::Write {
+        hooks.push(chip8.add_read_hook(start, end));
+    }
+    hooks
+}
+
+fn remove_watch_hooks(chip8: &mut Chip8, hooks: Vec<HookId>) {
+    for id in hooks {
+        chip8.remove_hook(id);
+    }
+}
+
+fn register_bytes() -> usize {
+    REGISTERS.iter().map(|(_, size)| size).sum()
+}
+
+fn le_value(bytes: &[u8]) -> u16 {
+    bytes
+        .iter()
+        .rev()
+        .fold(0, |value, b| (value << 8) | *b as u16)
+}
+
+fn error() -> String {
+    "E01".to_string()
+}
+
+/// Parse `address,length` in hex.
+fn parse_range(args: &str) -> Option<(usize, usize)> {
+    let (address, length) = args.split_once(',')?;
+    Some((parse_hex(address)? as usize, parse_hex(length)? as usize))
+}
+
+fn parse_hex(hex: &str) -> Option<u32> {
+    u32::from_str_radix(hex, 16).ok()
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // LD V3, 0x2A; LD I, 0x300; LD [I], V3; JP 0x206
+    const ROM: [u8; 8] = [0x63, 0x2A, 0xA3, 0x00, 0xF3, 0x55, 0x12, 0x06];
+
+    fn attached() -> (Sender<Message>, GdbStub, Chip8) {
+        let (tx, rx) = channel();
+        let mut stub = GdbStub::new(rx);
+        let mut chip8 = Chip8::default();
+        chip8.set_trace(false);
+        chip8.load_rom_bytes(&ROM).unwrap();
+        tx.send(Message::Connected(Box::new(std::io::sink())))
+            .unwrap();
+        stub.poll(&mut chip8);
+        (tx, stub, chip8)
+    }
+
+    fn reply(stub: &mut GdbStub, chip8: &mut Chip8, packet: &str) -> Option<String> {
+        stub.handle(chip8, packet)
+    }
+
+    #[test]
+    fn registers_and_memory() {
+        let (_tx, mut stub, mut chip8) = attached();
+        assert!(stub.is_halted());
+        assert!(!stub.step(&mut chip8));
+
+        assert_eq!(reply(&mut stub, &mut chip8, "s").unwrap(), SIGTRAP);
+        let registers = reply(&mut stub, &mut chip8, "g").unwrap();
+        assert_eq!(registers.len(), register_bytes() * 2);
+        assert_eq!(&registers[6..8], "2a");
+        // pc = 0x202, little endian
+        assert_eq!(reply(&mut stub, &mut chip8, "p11").unwrap(), "0202");
+        reply(&mut stub, &mut chip8, "P10=0004");
+        assert_eq!(chip8.get_register(Register::I), 0x400);
+
+        assert_eq!(reply(&mut stub, &mut chip8, "M300,2:beef").unwrap(), "OK");
+        assert_eq!(reply(&mut stub, &mut chip8, "m300,3").unwrap(), "beef00");
+        assert_eq!(reply(&mut stub, &mut chip8, "mfff,2").unwrap(), "E01");
+
+        let xml = reply(&mut stub, &mut chip8, "qXfer:features:read:target.xml:0,20");
+        assert_eq!(xml.unwrap(), format!("m{}", &TARGET_XML[..0x20]));
+        assert_eq!(reply(&mut stub, &mut chip8, "vMustReplyEmpty").unwrap(), "");
+        assert_eq!(reply(&mut stub, &mut chip8, "é0").unwrap(), "");
+        assert_eq!(reply(&mut stub, &mut chip8, "é").unwrap(), "");
+    }
+
+    #[test]
+    fn breakpoints_and_watchpoints() {
+        let (tx, mut stub, mut chip8) = attached();
+        // a hook of the frontend's own, such as a script's, on the watched address
+        let own = chip8.add_write_hook(0x300, 0x303);
+        reply(&mut stub, &mut chip8, "Z0,204,2");
+        reply(&mut stub, &mut chip8, "Z2,300,1");
+        assert_eq!(reply(&mut stub, &mut chip8, "c"), None);
+
+        while stub.step(&mut chip8) {}
+        assert_eq!(chip8.get_register(Register::Pc), 0x204);
+
+        reply(&mut stub, &mut chip8, "c");
+        assert!(stub.step(&mut chip8));
+        assert!(stub.is_halted());
+        assert_eq!(chip8.read_memory(0x303, 1), Some(&[0x2A][..]));
+        assert!(chip8.has_hook_events(&[own]));
+
+        reply(&mut stub, &mut chip8, "z2,300,1");
+        assert!(chip8.has_hook_events(&[own]));
+        reply(&mut stub, &mut chip8, "c");
+        for _ in 0..10 {
+            assert!(stub.step(&mut chip8));
+        }
+        tx.send(Message::Interrupt).unwrap();
+        stub.poll(&mut chip8);
+        assert!(stub.is_halted());
+
+        tx.send(Message::Disconnected).unwrap();
+        stub.poll(&mut chip8);
+        assert!(!stub.is_attached());
+        assert!(stub.step(&mut chip8));
+    }
+
+    #[test]
+    fn serve_packets() {
+        let (tx, rx) = channel();
+        serve(&b"+$g#67$m0,1#00\x03"[..], &tx);
+        let messages: Vec<Message> = rx.try_iter().collect();
+        assert!(matches!(&messages[0], Message::Packet(packet) if packet == "g"));
+        assert!(matches!(messages[1], Message::Corrupt));
+        assert!(matches!(messages[2], Message::Interrupt));
+    }
+}
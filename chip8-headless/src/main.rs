@@ -0,0 +1,273 @@
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use chip8_capture::{Format, Palette, Recorder};
+use chip8_carts::Cartridge;
+use chip8_core::{
+    chip8::{Chip8, Quirks, Stop},
+    font::FontSet,
+    platform::Platform,
+};
+use chip8_gdb::GdbStub;
+use chip8_rpc::Server;
+use clap::Parser;
+
+/// Run a ROM without a window, optionally under remote control.
+#[derive(Debug, Parser)]
+struct Cli {
+    rom_path: Option<PathBuf>,
+    /// Listen for JSON-RPC connections on this TCP port on localhost
+    #[arg(long)]
+    rpc_port: Option<u16>,
+    /// Listen for JSON-RPC connections on this Unix socket
+    #[cfg(unix)]
+    #[arg(long)]
+    rpc_socket: Option<PathBuf>,
+    /// Listen for a GDB remote debugger on this TCP port on localhost
+    #[arg(long)]
+    gdb: Option<u16>,
+    /// Start paused
+    #[arg(long)]
+    paused: bool,
+    /// Exit after running this many frames
+    #[arg(long)]
+    frames: Option<u64>,
+    /// Save the display as a PNG after the last frame
+    #[arg(long, requires = "frames")]
+    screenshot: Option<PathBuf>,
+    /// Record every frame to this .gif or .y4m file
+    #[arg(long, requires = "frames")]
+    record: Option<PathBuf>,
+    /// Count executions per address and save them as CSV after the last frame
+    #[arg(long, requires = "frames")]
+    profile: Option<PathBuf>,
+    /// Save the profile as folded stacks for flamegraph tools after the last frame
+    #[arg(long, requires = "frames")]
+    profile_folded: Option<PathBuf>,
+    /// Size of each CHIP-8 pixel in screenshots and recordings
+    #[arg(long, default_value_t = 8)]
+    capture_scale: usize,
+    /// CPU speed in Hz
+    #[arg(long, default_value_t = 500)]
+    speed: u32,
+    /// Print CPU state and instructions as they execute
+    #[arg(long)]
+    trace: bool,
+    /// Make Fx0A wait for a key to be released, like the COSMAC VIP
+    #[arg(long)]
+    key_release: bool,
+    /// Number of nested calls allowed, between 1 and 16
+    #[arg(long, default_value_t = 16)]
+    stack_depth: usize,
+    /// Keep the call stack in memory at 0xEA0, like the COSMAC VIP
+    #[arg(long)]
+    memory_stack: bool,
+    /// Built-in font: vip, dream6800, eti660, schip or octo
+    #[arg(long, default_value_t = FontSet::Schip)]
+    font: FontSet,
+    /// Address of the font, such as 0x050
+    #[arg(long, value_parser = parse_address, default_value = "0x000")]
+    font_address: u16,
+    /// Machine to emulate: chip8, eti660 (programs at 0x600), hires (64x64 display), chip8x or
+    /// megachip
+    #[arg(long, default_value_t = Platform::Chip8)]
+    platform: Platform,
+}
+
+const FRAME_TIME: Duration = Duration::from_micros(1_000_000 / 60);
+
+fn main() {
+    let cli = Cli::parse();
+    let mut chip8 = Chip8::default();
+    chip8.set_trace(cli.trace);
+    chip8.set_quirks(Quirks {
+        key_release: cli.key_release,
+    });
+    chip8.set_stack_depth(cli.stack_depth);
+    chip8.set_memory_stack(cli.memory_stack);
+    if let Err(e) = chip8.set_platform(cli.platform) {
+        eprintln!("{e}");
+        std::process::exit(1);
+    }
+    chip8.set_font(cli.font);
+    if let Err(e) = chip8.set_font_address(cli.font_address) {
+        eprintln!("{e}");
+        std::process::exit(1);
+    }
+    chip8.set_speed(cli.speed);
+    if let Some(path) = &cli.rom_path {
+        let result = if chip8_carts::is_cartridge(path) {
+            Cartridge::load(path).and_then(|cartridge| cartridge.apply(&mut chip8))
+        } else {
+            chip8.load_rom(path)
+        };
+        if let Err(e) = result {
+            eprintln!("failed to load {}: {e}", path.display());
+            std::process::exit(1);
+        }
+    }
+
+    chip8.set_profiling(cli.profile.is_some() || cli.profile_folded.is_some());
+
+    let mut servers = Vec::new();
+    if let Some(port) = cli.rpc_port {
+        match Server::listen_tcp(("127.0.0.1", port)) {
+            Ok(server) => servers.push(server),
+            Err(e) => {
+                eprintln!("failed to listen on port {port}: {e}");
+                std::process::exit(1);
+            }
+        }
+    }
+    #[cfg(unix)]
+    if let Some(path) = &cli.rpc_socket {
+        match Server::listen_unix(path) {
+            Ok(server) => servers.push(server),
+            Err(e) => {
+                eprintln!("failed to listen on {}: {e}", path.display());
+                std::process::exit(1);
+            }
+        }
+    }
+
+    let mut gdb = cli
+        .gdb
+        .map(|port| match GdbStub::listen(("127.0.0.1", port)) {
+            Ok(gdb) => gdb,
+            Err(e) => {
+                eprintln!("failed to listen for gdb on port {port}: {e}");
+                std::process::exit(1);
+            }
+        });
+
+    let (width, height) = chip8.get_display_size();
+    let format = Format {
+        width,
+        height,
+        scale: cli.capture_scale,
+        palette: Palette::default(),
+    };
+    let mut recorder = cli.record.as_deref().map(|path| {
+        let recorder = Recorder::create(path, format).unwrap_or_else(|e| capture_failed(path, e));
+        (recorder, path)
+    });
+
+    let mut paused = cli.paused;
+    let mut frames = 0u64;
+    let mut next_frame = Instant::now();
+    while cli.frames.is_none_or(|max| frames < max) {
+        for server in servers.iter_mut() {
+            server.poll(&mut chip8, &mut paused);
+        }
+        if let Some(gdb) = &mut gdb {
+            gdb.poll(&mut chip8);
+        }
+        if !paused && chip8.is_loaded() {
+            match &mut gdb {
+                // step one instruction at a time so breakpoints are honoured
+                Some(gdb) if gdb.is_attached() => {
+                    for _ in 0..(chip8.speed() / 60).max(1) {
+                        if !gdb.step(&mut chip8) {
+                            break;
+                        }
+                    }
+                    if !gdb.is_halted() {
+                        chip8.tick_timers();
+                    }
+                }
+                _ => {
+                    if let Some(Stop::Fault(fault)) = chip8.run_frame().stop {
+                        eprintln!("program stopped: {fault}");
+                        if servers.is_empty() && gdb.is_none() {
+                            break;
+                        }
+                        paused = true;
+                    }
+                }
+            }
+            frames += 1;
+            // recordings keep the size they started with, so frames of another size are
+            // left out, e.g. while a MegaChip program is in MegaChip mode
+            if let Some((recorder, path)) = recorder
+                .as_mut()
+                .filter(|_| chip8.get_display_size() == (format.width, format.height))
+            {
+                let result = match chip8.get_display_rgb() {
+                    Some(pixels) => recorder.record_frame_rgb(&pixels),
+                    None => recorder.record_frame(chip8.get_display()),
+                };
+                if let Err(e) = result {
+                    capture_failed(path, e);
+                }
+            }
+        }
+
+        next_frame += FRAME_TIME;
+        let now = Instant::now();
+        if next_frame > now {
+            std::thread::sleep(next_frame - now);
+        } else {
+            next_frame = now;
+        }
+    }
+
+    if let Some((recorder, path)) = recorder {
+        if let Err(e) = recorder.finish() {
+            capture_failed(path, e);
+        }
+    }
+    if let Some(path) = &cli.screenshot {
+        let (width, height) = chip8.get_display_size();
+        let format = Format {
+            width,
+            height,
+            ..format
+        };
+        let result = match chip8.get_display_rgb() {
+            Some(pixels) => chip8_capture::save_png_rgb(path, &format, &pixels),
+            None => chip8_capture::save_png(path, &format, chip8.get_display()),
+        };
+        if let Err(e) = result {
+            capture_failed(path, e);
+        }
+    }
+    if let Some(path) = &cli.profile {
+        write_profile(path, |w| {
+            let profile = chip8.profile().unwrap();
+            profile.write_csv(w, |address| chip8.disassemble(address))
+        });
+    }
+    if let Some(path) = &cli.profile_folded {
+        let name = chip8.get_rom_name().unwrap_or_else(|| "chip8".to_string());
+        write_profile(path, |w| chip8.profile().unwrap().write_folded(w, &name));
+    }
+}
+
+fn write_profile(path: &Path, write: impl FnOnce(&mut BufWriter<File>) -> std::io::Result<()>) {
+    let result = File::create(path).and_then(|file| {
+        let mut w = BufWriter::new(file);
+        write(&mut w)?;
+        w.flush()
+    });
+    if let Err(e) = result {
+        eprintln!("failed to save profile to {}: {e}", path.display());
+        std::process::exit(1);
+    }
+}
+
+fn capture_failed(path: &Path, e: std::io::Error) -> ! {
+    eprintln!("failed to save {}: {e}", path.display());
+    std::process::exit(1);
+}
+
+/// Parse an address given in decimal or, with a `0x` prefix, in hex.
+fn parse_address(s: &str) -> Result<u16, std::num::ParseIntError> {
+    match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => s.parse(),
+    }
+}
@@ -0,0 +1,353 @@
+//! libretro core wrapping [`Chip8`] so it can run inside any libretro frontend.
+
+use std::{
+    ffi::CStr,
+    os::raw::{c_char, c_uint, c_void},
+    sync::Mutex,
+};
+
+use chip8_core::chip8::Chip8;
+use libretro::*;
+
+mod libretro;
+
+const WIDTH: usize = 64;
+const HEIGHT: usize = 32;
+const FPS: f64 = 60.0;
+const SAMPLE_RATE: f64 = 44100.0;
+const SAMPLES_PER_FRAME: usize = (SAMPLE_RATE / FPS) as usize;
+const TONE_HZ: usize = 440;
+const TONE_VOLUME: i16 = 0x1000;
+const FOREGROUND: u32 = 0x00FF_FFFF;
+const BACKGROUND: u32 = 0x0000_0000;
+
+// QWERTY     CHIP8
+// 1 2 3 4    1 2 3 C
+// Q W E R    4 5 6 D
+// A S D F    7 8 9 E
+// Z X C V    A 0 B F
+
+/// libretro keycodes for each chip8 key, matching the desktop frontend layout.
+const KEY_MAP: [u8; 16] = [
+    b'x', b'1', b'2', b'3', b'q', b'w', b'e', b'a', b's', b'd', b'z', b'c', b'4', b'r', b'f', b'v',
+];
+
+/// Joypad buttons and the chip8 key each one presses.
+const JOYPAD_MAP: [(c_uint, usize); 8] = [
+    (RETRO_DEVICE_ID_JOYPAD_UP, 0x2),
+    (RETRO_DEVICE_ID_JOYPAD_DOWN, 0x8),
+    (RETRO_DEVICE_ID_JOYPAD_LEFT, 0x4),
+    (RETRO_DEVICE_ID_JOYPAD_RIGHT, 0x6),
+    (RETRO_DEVICE_ID_JOYPAD_A, 0x5),
+    (RETRO_DEVICE_ID_JOYPAD_B, 0x0),
+    (RETRO_DEVICE_ID_JOYPAD_X, 0x1),
+    (RETRO_DEVICE_ID_JOYPAD_Y, 0xC),
+];
+
+struct Callbacks {
+    environment: Option<RetroEnvironmentFn>,
+    video_refresh: Option<RetroVideoRefreshFn>,
+    audio_sample_batch: Option<RetroAudioSampleBatchFn>,
+    input_poll: Option<RetroInputPollFn>,
+    input_state: Option<RetroInputStateFn>,
+}
+
+struct Core {
+    chip8: Chip8,
+    framebuffer: Box<[u32]>,
+    /// Display generation the framebuffer was last converted from
+    rendered: Option<u64>,
+    audio: Box<[i16]>,
+    /// Position within the square wave, in samples
+    phase: usize,
+}
+
+impl Core {
+    fn new() -> Self {
+        let mut chip8 = Chip8::default();
+        chip8.set_trace(false);
+        Self {
+            chip8,
+            framebuffer: vec![BACKGROUND; WIDTH * HEIGHT].into_boxed_slice(),
+            rendered: None,
+            audio: vec![0; SAMPLES_PER_FRAME * 2].into_boxed_slice(),
+            phase: 0,
+        }
+    }
+
+    fn update_keys(&mut self, input_state: RetroInputStateFn) {
+        let mut keys = [false; 16];
+        for (i, code) in KEY_MAP.iter().enumerate() {
+            keys[i] |= unsafe { input_state(0, RETRO_DEVICE_KEYBOARD, 0, *code as c_uint) } != 0;
+        }
+        for (id, key) in JOYPAD_MAP {
+            keys[key] |= unsafe { input_state(0, RETRO_DEVICE_JOYPAD, 0, id) } != 0;
+        }
+        for (i, state) in keys.into_iter().enumerate() {
+            self.chip8.set_key_state(i, state);
+        }
+    }
+
+    fn run_frame(&mut self) {
+        if self.chip8.is_loaded() {
+            self.chip8.run_frame();
+        }
+    }
+
+    fn render(&mut self) {
+        let generation = self.chip8.display_generation();
+        if self.rendered == Some(generation) {
+            return;
+        }
+        self.rendered = Some(generation);
+        for (out, pixel) in self.framebuffer.iter_mut().zip(self.chip8.get_display()) {
+            *out = if *pixel == 0 { BACKGROUND } else { FOREGROUND };
+        }
+    }
+
+    fn render_audio(&mut self) {
+        let period = SAMPLE_RATE as usize / TONE_HZ;
+        let active = self.chip8.is_sound_active();
+        for frame in self.audio.chunks_exact_mut(2) {
+            let sample = if !active {
+                0
+            } else if self.phase < period / 2 {
+                TONE_VOLUME
+            } else {
+                -TONE_VOLUME
+            };
+            frame[0] = sample;
+            frame[1] = sample;
+            self.phase = (self.phase + 1) % period;
+        }
+    }
+}
+
+static CALLBACKS: Mutex<Callbacks> = Mutex::new(Callbacks {
+    environment: None,
+    video_refresh: None,
+    audio_sample_batch: None,
+    input_poll: None,
+    input_state: None,
+});
+static CORE: Mutex<Option<Core>> = Mutex::new(None);
+
+fn with_core<T: Default>(f: impl FnOnce(&mut Core) -> T) -> T {
+    match CORE.lock().unwrap().as_mut() {
+        Some(core) => f(core),
+        None => T::default(),
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_api_version() -> c_uint {
+    RETRO_API_VERSION
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_environment(cb: RetroEnvironmentFn) {
+    CALLBACKS.lock().unwrap().environment = Some(cb);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_video_refresh(cb: RetroVideoRefreshFn) {
+    CALLBACKS.lock().unwrap().video_refresh = Some(cb);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample(_cb: RetroAudioSampleFn) {
+    // audio is always submitted in batches
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample_batch(cb: RetroAudioSampleBatchFn) {
+    CALLBACKS.lock().unwrap().audio_sample_batch = Some(cb);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_poll(cb: RetroInputPollFn) {
+    CALLBACKS.lock().unwrap().input_poll = Some(cb);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_state(cb: RetroInputStateFn) {
+    CALLBACKS.lock().unwrap().input_state = Some(cb);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_init() {
+    *CORE.lock().unwrap() = Some(Core::new());
+}
+
+#[no_mangle]
+pub extern "C" fn retro_deinit() {
+    *CORE.lock().unwrap() = None;
+}
+
+/// # Safety
+///
+/// `info` must point to a valid `retro_system_info`.
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_info(info: *mut RetroSystemInfo) {
+    *info = RetroSystemInfo {
+        library_name: c"chip8".as_ptr(),
+        library_version: concat!(env!("CARGO_PKG_VERSION"), "\0").as_ptr() as *const c_char,
+        valid_extensions: c"ch8|c8|gz|zip".as_ptr(),
+        need_fullpath: true,
+        // the core reads gzip and zip archives itself
+        block_extract: true,
+    };
+}
+
+/// # Safety
+///
+/// `info` must point to a valid `retro_system_av_info`.
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_av_info(info: *mut RetroSystemAvInfo) {
+    *info = RetroSystemAvInfo {
+        geometry: RetroGameGeometry {
+            base_width: WIDTH as c_uint,
+            base_height: HEIGHT as c_uint,
+            max_width: WIDTH as c_uint,
+            max_height: HEIGHT as c_uint,
+            aspect_ratio: WIDTH as f32 / HEIGHT as f32,
+        },
+        timing: RetroSystemTiming {
+            fps: FPS,
+            sample_rate: SAMPLE_RATE,
+        },
+    };
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_controller_port_device(_port: c_uint, _device: c_uint) {}
+
+#[no_mangle]
+pub extern "C" fn retro_reset() {
+    with_core(|core| {
+        core.chip8
+            .reload_rom()
+            .unwrap_or_else(|e| eprintln!("failed to reset: {e}"));
+    });
+}
+
+#[no_mangle]
+pub extern "C" fn retro_run() {
+    let callbacks = CALLBACKS.lock().unwrap();
+    if let Some(input_poll) = callbacks.input_poll {
+        unsafe { input_poll() };
+    }
+    with_core(|core| {
+        if let Some(input_state) = callbacks.input_state {
+            core.update_keys(input_state);
+        }
+        core.run_frame();
+        core.render();
+        core.render_audio();
+
+        if let Some(video_refresh) = callbacks.video_refresh {
+            unsafe {
+                video_refresh(
+                    core.framebuffer.as_ptr() as *const c_void,
+                    WIDTH as c_uint,
+                    HEIGHT as c_uint,
+                    WIDTH * std::mem::size_of::<u32>(),
+                )
+            };
+        }
+        if let Some(audio_sample_batch) = callbacks.audio_sample_batch {
+            unsafe { audio_sample_batch(core.audio.as_ptr(), SAMPLES_PER_FRAME) };
+        }
+    });
+}
+
+#[no_mangle]
+pub extern "C" fn retro_serialize_size() -> usize {
+    with_core(|core| core.chip8.save_state().map(|s| s.len()).unwrap_or(0))
+}
+
+/// # Safety
+///
+/// `data` must be valid for writes of `size` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn retro_serialize(data: *mut c_void, size: usize) -> bool {
+    with_core(|core| match core.chip8.save_state() {
+        Ok(state) if state.len() <= size => {
+            std::ptr::copy_nonoverlapping(state.as_ptr(), data as *mut u8, state.len());
+            true
+        }
+        _ => false,
+    })
+}
+
+/// # Safety
+///
+/// `data` must be valid for reads of `size` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn retro_unserialize(data: *const c_void, size: usize) -> bool {
+    let state = std::slice::from_raw_parts(data as *const u8, size);
+    with_core(|core| core.chip8.load_state(state).is_ok())
+}
+
+#[no_mangle]
+pub extern "C" fn retro_cheat_reset() {}
+
+#[no_mangle]
+pub extern "C" fn retro_cheat_set(_index: c_uint, _enabled: bool, _code: *const c_char) {}
+
+/// # Safety
+///
+/// `game` must be null or point to a valid `retro_game_info`.
+#[no_mangle]
+pub unsafe extern "C" fn retro_load_game(game: *const RetroGameInfo) -> bool {
+    if game.is_null() || (*game).path.is_null() {
+        return false;
+    }
+    if let Some(environment) = CALLBACKS.lock().unwrap().environment {
+        let mut format = RETRO_PIXEL_FORMAT_XRGB8888;
+        if !environment(
+            RETRO_ENVIRONMENT_SET_PIXEL_FORMAT,
+            &mut format as *mut c_uint as *mut c_void,
+        ) {
+            eprintln!("frontend does not support XRGB8888");
+            return false;
+        }
+    }
+    let path = CStr::from_ptr((*game).path).to_string_lossy().into_owned();
+    with_core(|core| match core.chip8.load_rom(path) {
+        Ok(()) => true,
+        Err(e) => {
+            eprintln!("failed to load rom: {e}");
+            false
+        }
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn retro_load_game_special(
+    _game_type: c_uint,
+    _info: *const RetroGameInfo,
+    _num_info: usize,
+) -> bool {
+    false
+}
+
+#[no_mangle]
+pub extern "C" fn retro_unload_game() {
+    with_core(|core| core.chip8.reset());
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_region() -> c_uint {
+    RETRO_REGION_NTSC
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_memory_data(_id: c_uint) -> *mut c_void {
+    std::ptr::null_mut()
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_memory_size(_id: c_uint) -> usize {
+    0
+}
@@ -0,0 +1,140 @@
+//! Python bindings for [`chip8_core`], built as the `chip8` extension module with maturin.
+
+use numpy::{ndarray::Array2, IntoPyArray, PyArray2};
+use pyo3::{
+    exceptions::{PyIndexError, PyRuntimeError, PyValueError},
+    prelude::*,
+    types::PyBytes,
+};
+
+/// A CHIP-8 machine.
+#[pyclass(name = "Chip8")]
+struct PyChip8 {
+    chip8: chip8_core::chip8::Chip8,
+}
+
+#[pymethods]
+impl PyChip8 {
+    #[new]
+    fn new() -> Self {
+        let mut chip8 = chip8_core::chip8::Chip8::default();
+        chip8.set_trace(false);
+        Self { chip8 }
+    }
+
+    /// Load a ROM image, resetting the machine first if a ROM was already loaded.
+    fn load_rom(&mut self, rom: &[u8]) -> PyResult<()> {
+        self.chip8
+            .load_rom_bytes(rom)
+            .map_err(|e| PyValueError::new_err(e.to_string()))
+    }
+
+    /// Reset the machine to its power-on state, unloading the ROM.
+    fn reset(&mut self) {
+        self.chip8.reset();
+    }
+
+    /// Execute a single instruction. Raises `RuntimeError` if the program faults.
+    fn step(&mut self) -> PyResult<()> {
+        self.chip8.step();
+        self.check_fault()
+    }
+
+    /// Execute one 60 Hz frame's worth of instructions and return how many were executed.
+    /// Raises `RuntimeError` if the program faults.
+    fn run_frame(&mut self) -> PyResult<u32> {
+        let summary = self.chip8.run_frame();
+        self.check_fault()?;
+        Ok(summary.cycles)
+    }
+
+    /// Press or release a key (0x0 - 0xF) on the hex keypad.
+    fn set_key(&mut self, key: usize, pressed: bool) -> PyResult<()> {
+        if key >= 16 {
+            return Err(PyValueError::new_err(format!("invalid key: {key}")));
+        }
+        self.chip8.set_key_state(key, pressed);
+        Ok(())
+    }
+
+    /// Get the framebuffer as a `(height, width)` uint8 array of 0s and 1s.
+    fn framebuffer<'py>(&self, py: Python<'py>) -> Bound<'py, PyArray2<u8>> {
+        let (width, height) = self.chip8.get_display_size();
+        Array2::from_shape_vec((height, width), self.chip8.get_display().to_vec())
+            .unwrap()
+            .into_pyarray(py)
+    }
+
+    /// Serialize the full machine state.
+    fn save_state<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyBytes>> {
+        let state = self
+            .chip8
+            .save_state()
+            .map_err(|e| PyRuntimeError::new_err(e.to_string()))?;
+        Ok(PyBytes::new(py, &state))
+    }
+
+    /// Restore state produced by `save_state`.
+    fn load_state(&mut self, state: &[u8]) -> PyResult<()> {
+        self.chip8
+            .load_state(state)
+            .map_err(|e| PyValueError::new_err(e.to_string()))
+    }
+
+    /// Read a byte of memory.
+    fn peek(&self, address: usize) -> PyResult<u8> {
+        self.chip8
+            .read_memory(address, 1)
+            .map(|bytes| bytes[0])
+            .ok_or_else(|| PyIndexError::new_err(format!("address out of range: {address:#X}")))
+    }
+
+    /// Write a byte of memory.
+    fn poke(&mut self, address: usize, value: u8) -> PyResult<()> {
+        if self.chip8.write_memory(address, &[value]) {
+            Ok(())
+        } else {
+            Err(PyIndexError::new_err(format!(
+                "address out of range: {address:#X}"
+            )))
+        }
+    }
+
+    /// Emulation clock speed in Hz, used by `run_frame`.
+    #[getter]
+    fn speed(&self) -> u32 {
+        self.chip8.speed()
+    }
+
+    #[setter]
+    fn set_speed(&mut self, speed: u32) {
+        self.chip8.set_speed(speed);
+    }
+
+    /// True while the sound timer is running.
+    #[getter]
+    fn sound_active(&self) -> bool {
+        self.chip8.is_sound_active()
+    }
+
+    #[getter]
+    fn loaded(&self) -> bool {
+        self.chip8.is_loaded()
+    }
+}
+
+impl PyChip8 {
+    fn check_fault(&self) -> PyResult<()> {
+        match self.chip8.fault() {
+            Some(fault) => Err(PyRuntimeError::new_err(fault.to_string())),
+            None => Ok(()),
+        }
+    }
+}
+
+#[pymodule]
+#[pyo3(name = "chip8")]
+fn chip8_module(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PyChip8>()?;
+    Ok(())
+}
@@ -0,0 +1,42 @@
+# Run with `maturin develop && pytest` from the chip8-py directory.
+
+import pytest
+
+import chip8
+
+# CLS; LD I, 0x000; DRW V0, V0, 5; JP 0x206
+ROM = bytes([0x00, 0xE0, 0xA0, 0x00, 0xD0, 0x05, 0x12, 0x06])
+
+
+def test_run_frame_draws_to_framebuffer():
+    c = chip8.Chip8()
+    c.load_rom(ROM)
+    c.run_frame()
+    fb = c.framebuffer()
+    assert fb.shape == (32, 64)
+    assert fb[0, :5].tolist() == [1, 1, 1, 1, 0]
+
+
+def test_save_and_load_state():
+    c = chip8.Chip8()
+    c.load_rom(ROM)
+    c.poke(0x300, 0xAB)
+    state = c.save_state()
+    c.reset()
+    assert c.peek(0x300) == 0
+    c.load_state(state)
+    assert c.peek(0x300) == 0xAB
+
+
+def test_errors():
+    c = chip8.Chip8()
+    with pytest.raises(ValueError):
+        c.load_rom(bytes(4096))
+    with pytest.raises(ValueError):
+        c.set_key(16, True)
+    with pytest.raises(IndexError):
+        c.peek(0x1000)
+    with pytest.raises(RuntimeError):
+        # 0x0000 isn't an instruction
+        c.load_rom(bytes(2))
+        c.run_frame()
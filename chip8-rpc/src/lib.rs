@@ -0,0 +1,524 @@
+//! Remote control of a running [`Chip8`] over a local socket using JSON-RPC 2.0.
+//!
+//! The server accepts connections on a background thread. Each connection sends one JSON-RPC
+//! request per line and receives one response per line. Requests are queued until the
+//! emulator thread calls [`Server::poll`], so the machine is only ever touched by its owner.
+//!
+//! | method          | params                         | result                               |
+//! |-----------------|--------------------------------|--------------------------------------|
+//! | `status`        |                                | `{paused, loaded, rom, fault}`       |
+//! | `load_rom`      | `{path}` or `{data: hex}`      |                                      |
+//! | `reset`         |                                |                                      |
+//! | `pause`         |                                |                                      |
+//! | `resume`        |                                |                                      |
+//! | `step`          | `{count?}`                     | number of instructions executed      |
+//! | `run_frame`     | `{count?}`                     | frame summary, see below             |
+//! | `read_memory`   | `{address, length}`            | array of bytes                       |
+//! | `write_memory`  | `{address, data: [bytes]}`     |                                      |
+//! | `get_registers` |                                | `{V0..VF, I, PC, SP, DT, ST}`        |
+//! | `set_register`  | `{name, value}`                |                                      |
+//! | `press_key`     | `{key}`                        |                                      |
+//! | `release_key`   | `{key}`                        |                                      |
+//! | `framebuffer`   |                                | `{width, height, pixels: [bytes]}`   |
+//! | `save_state`    |                                | hex string                           |
+//! | `load_state`    | `{state: hex}`                 |                                      |
+//!
+//! `step` stops early at a fault. `run_frame` stops early at a breakpoint or fault and returns
+//! `{frames, cycles, display_changed, sound, waiting_for_key, stop}`, where `stop` is null,
+//! `{breakpoint: pc}` or `{fault: message}`. Both run on the emulator thread, so `count` is
+//! capped at [`MAX_STEPS`] instructions or [`MAX_FRAMES`] frames per request.
+
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::{TcpListener, ToSocketAddrs},
+    sync::mpsc::{channel, Receiver, Sender},
+};
+
+use chip8_core::chip8::{Chip8, FrameSummary, Register, Stop};
+use serde_json::{json, Map, Value};
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const SERVER_ERROR: i64 = -32000;
+
+/// Most instructions a single `step` request executes.
+pub const MAX_STEPS: u64 = 100_000;
+/// Most frames a single `run_frame` request runs, 10 seconds of emulated time.
+pub const MAX_FRAMES: u64 = 600;
+
+const REGISTER_NAMES: [&str; 21] = [
+    "V0", "V1", "V2", "V3", "V4", "V5", "V6", "V7", "V8", "V9", "VA", "VB", "VC", "VD", "VE", "VF",
+    "I", "PC", "SP", "DT", "ST",
+];
+
+struct Request {
+    message: Value,
+    reply: Sender<Value>,
+}
+
+#[derive(Debug)]
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+
+    fn params(message: impl Into<String>) -> Self {
+        Self::new(INVALID_PARAMS, message)
+    }
+}
+
+pub struct Server {
+    requests: Receiver<Request>,
+    /// Keys held by remote clients, combined with the frontend's own input
+    held: [bool; 16],
+}
+
+impl Server {
+    /// Listen for connections on a TCP address such as `127.0.0.1:6502`.
+    pub fn listen_tcp<A: ToSocketAddrs>(address: A) -> std::io::Result<Server> {
+        let listener = TcpListener::bind(address)?;
+        let (tx, rx) = channel();
+        std::thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let tx = tx.clone();
+                std::thread::spawn(move || {
+                    if let Ok(reader) = stream.try_clone() {
+                        serve(BufReader::new(reader), stream, tx);
+                    }
+                });
+            }
+        });
+        Ok(Self::new(rx))
+    }
+
+    /// Listen for connections on a Unix domain socket, replacing any stale socket file.
+    #[cfg(unix)]
+    pub fn listen_unix<P: AsRef<std::path::Path>>(path: P) -> std::io::Result<Server> {
+        use std::os::unix::net::UnixListener;
+
+        let _ = std::fs::remove_file(path.as_ref());
+        let listener = UnixListener::bind(path)?;
+        let (tx, rx) = channel();
+        std::thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let tx = tx.clone();
+                std::thread::spawn(move || {
+                    if let Ok(reader) = stream.try_clone() {
+                        serve(BufReader::new(reader), stream, tx);
+                    }
+                });
+            }
+        });
+        Ok(Self::new(rx))
+    }
+
+    fn new(requests: Receiver<Request>) -> Self {
+        Self {
+            requests,
+            held: [false; 16],
+        }
+    }
+
+    /// Handle every queued request. `paused` is the frontend's pause flag, which clients can
+    /// change with `pause` and `resume`.
+    pub fn poll(&mut self, chip8: &mut Chip8, paused: &mut bool) {
+        while let Ok(request) = self.requests.try_recv() {
+            let response = self.handle(chip8, paused, &request.message);
+            let _ = request.reply.send(response);
+        }
+    }
+
+    /// Keys held by remote clients, to be combined with the frontend's own input.
+    pub fn held_keys(&self) -> [bool; 16] {
+        self.held
+    }
+
+    /// Build the response to a single request, or `Value::Null` for notifications.
+    fn handle(&mut self, chip8: &mut Chip8, paused: &mut bool, message: &Value) -> Value {
+        let id = message.get("id").cloned();
+        let result = match message.get("method").and_then(Value::as_str) {
+            Some(method) => {
+                let params = message.get("params").cloned().unwrap_or(Value::Null);
+                self.call(chip8, paused, method, &params)
+            }
+            None => Err(RpcError::new(INVALID_REQUEST, "missing method")),
+        };
+        match (id, result) {
+            (None, _) => Value::Null,
+            (Some(id), Ok(result)) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            (Some(id), Err(e)) => error_response(id, e),
+        }
+    }
+
+    fn call(
+        &mut self,
+        chip8: &mut Chip8,
+        paused: &mut bool,
+        method: &str,
+        params: &Value,
+    ) -> Result<Value, RpcError> {
+        match method {
+            "status" => Ok(json!({
+                "paused": *paused,
+                "loaded": chip8.is_loaded(),
+                "rom": chip8.get_rom_name(),
+                "fault": chip8.fault().map(|fault| fault.to_string()),
+            })),
+            "load_rom" => {
+                let result = if let Some(path) = params.get("path").and_then(Value::as_str) {
+                    chip8.load_rom(path)
+                } else if let Some(data) = params.get("data").and_then(Value::as_str) {
+                    chip8.load_rom_bytes(&from_hex(data)?)
+                } else {
+                    return Err(RpcError::params("expected `path` or `data`"));
+                };
+                result.map_err(|e| RpcError::new(SERVER_ERROR, e.to_string()))?;
+                Ok(Value::Null)
+            }
+            "reset" => {
+                chip8.reset();
+                Ok(Value::Null)
+            }
+            "pause" => {
+                *paused = true;
+                Ok(Value::Null)
+            }
+            "resume" => {
+                *paused = false;
+                Ok(Value::Null)
+            }
+            "step" => {
+                let count = optional_u64(params, "count", 1)?.min(MAX_STEPS);
+                let mut executed = 0;
+                if chip8.is_loaded() {
+                    for _ in 0..count {
+                        chip8.step();
+                        if chip8.fault().is_some() {
+                            break;
+                        }
+                        executed += 1;
+                    }
+                }
+                Ok(json!(executed))
+            }
+            "run_frame" => {
+                let count = optional_u64(params, "count", 1)?.min(MAX_FRAMES);
+                let mut frames = 0;
+                let mut cycles = 0;
+                let mut display_changed = false;
+                let mut last = FrameSummary::default();
+                if chip8.is_loaded() {
+                    for _ in 0..count {
+                        last = chip8.run_frame();
+                        frames += 1;
+                        cycles += last.cycles as u64;
+                        display_changed |= last.display_changed;
+                        if last.stop.is_some() {
+                            break;
+                        }
+                    }
+                }
+                let stop = match last.stop {
+                    None => Value::Null,
+                    Some(Stop::Breakpoint(pc)) => json!({ "breakpoint": pc }),
+                    Some(Stop::Fault(fault)) => json!({ "fault": fault.to_string() }),
+                };
+                Ok(json!({
+                    "frames": frames,
+                    "cycles": cycles,
+                    "display_changed": display_changed,
+                    "sound": last.sound,
+                    "waiting_for_key": last.waiting_for_key,
+                    "stop": stop,
+                }))
+            }
+            "read_memory" => {
+                let address = required_u64(params, "address")? as usize;
+                let length = required_u64(params, "length")? as usize;
+                chip8
+                    .read_memory(address, length)
+                    .map(|bytes| json!(bytes))
+                    .ok_or_else(|| RpcError::params("address out of range"))
+            }
+            "write_memory" => {
+                let address = required_u64(params, "address")? as usize;
+                let data = params
+                    .get("data")
+                    .and_then(Value::as_array)
+                    .ok_or_else(|| RpcError::params("missing `data`"))?
+                    .iter()
+                    .map(|b| b.as_u64().and_then(|b| u8::try_from(b).ok()))
+                    .collect::<Option<Vec<u8>>>()
+                    .ok_or_else(|| RpcError::params("`data` must be an array of bytes"))?;
+                if chip8.write_memory(address, &data) {
+                    Ok(Value::Null)
+                } else {
+                    Err(RpcError::params("address out of range"))
+                }
+            }
+            "get_registers" => {
+                let mut registers = Map::new();
+                for name in REGISTER_NAMES {
+                    let value = chip8.get_register(name.parse().unwrap());
+                    registers.insert(name.to_string(), json!(value));
+                }
+                Ok(Value::Object(registers))
+            }
+            "set_register" => {
+                let register = params
+                    .get("name")
+                    .and_then(Value::as_str)
+                    .ok_or_else(|| RpcError::params("missing `name`"))?
+                    .parse::<Register>()
+                    .map_err(RpcError::params)?;
+                let value = required_u64(params, "value")?;
+                chip8.set_register(register, value as u16);
+                Ok(Value::Null)
+            }
+            "press_key" | "release_key" => {
+                let key = required_u64(params, "key")? as usize;
+                if key >= 16 {
+                    return Err(RpcError::params(format!("invalid key: {key}")));
+                }
+                let pressed = method == "press_key";
+                self.held[key] = pressed;
+                chip8.set_key_state(key, pressed);
+                Ok(Value::Null)
+            }
+            "framebuffer" => {
+                let (width, height) = chip8.get_display_size();
+                Ok(json!({
+                    "width": width,
+                    "height": height,
+                    "pixels": chip8.get_display(),
+                }))
+            }
+            "save_state" => chip8
+                .save_state()
+                .map(|state| json!(to_hex(&state)))
+                .map_err(|e| RpcError::new(SERVER_ERROR, e.to_string())),
+            "load_state" => {
+                let state = params
+                    .get("state")
+                    .and_then(Value::as_str)
+                    .ok_or_else(|| RpcError::params("missing `state`"))?;
+                chip8
+                    .load_state(&from_hex(state)?)
+                    .map_err(|e| RpcError::params(e.to_string()))?;
+                Ok(Value::Null)
+            }
+            _ => Err(RpcError::new(
+                METHOD_NOT_FOUND,
+                format!("unknown method `{method}`"),
+            )),
+        }
+    }
+}
+
+/// Read requests from one connection until it closes, forwarding each to the emulator thread.
+fn serve<R: BufRead, W: Write>(reader: R, mut writer: W, requests: Sender<Request>) {
+    for line in reader.lines() {
+        let Ok(line) = line else {
+            return;
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = match serde_json::from_str::<Value>(&line) {
+            Ok(message) => {
+                let (reply, response) = channel();
+                if requests.send(Request { message, reply }).is_err() {
+                    return;
+                }
+                match response.recv() {
+                    Ok(response) => response,
+                    Err(_) => return,
+                }
+            }
+            Err(e) => error_response(Value::Null, RpcError::new(PARSE_ERROR, e.to_string())),
+        };
+        if !response.is_null() && writeln!(writer, "{response}").is_err() {
+            return;
+        }
+    }
+}
+
+fn error_response(id: Value, error: RpcError) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": { "code": error.code, "message": error.message },
+    })
+}
+
+fn required_u64(params: &Value, name: &str) -> Result<u64, RpcError> {
+    params
+        .get(name)
+        .and_then(Value::as_u64)
+        .ok_or_else(|| RpcError::params(format!("missing integer `{name}`")))
+}
+
+fn optional_u64(params: &Value, name: &str, default: u64) -> Result<u64, RpcError> {
+    match params.get(name) {
+        None | Some(Value::Null) => Ok(default),
+        Some(_) => required_u64(params, name),
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn from_hex(hex: &str) -> Result<Vec<u8>, RpcError> {
+    if !hex.len().is_multiple_of(2) {
+        return Err(RpcError::params("hex string has odd length"));
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2).unwrap_or(""), 16))
+        .collect::<Result<_, _>>()
+        .map_err(|_| RpcError::params("invalid hex string"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(server: &mut Server, chip8: &mut Chip8, paused: &mut bool, text: &str) -> Value {
+        server.handle(chip8, paused, &serde_json::from_str(text).unwrap())
+    }
+
+    #[test]
+    fn methods() {
+        let (_tx, rx) = channel();
+        let mut server = Server::new(rx);
+        let mut chip8 = Chip8::default();
+        chip8.set_trace(false);
+        let mut paused = false;
+        let mut call = |text: &str| request(&mut server, &mut chip8, &mut paused, text);
+
+        // LD V3, 0x2A; JP 0x202
+        let load =
+            call(r#"{"jsonrpc":"2.0","id":1,"method":"load_rom","params":{"data":"632a1202"}}"#);
+        assert_eq!(load["result"], Value::Null);
+        call(r#"{"jsonrpc":"2.0","id":2,"method":"step","params":{"count":2}}"#);
+        let registers = call(r#"{"jsonrpc":"2.0","id":3,"method":"get_registers"}"#);
+        assert_eq!(registers["result"]["V3"], 0x2A);
+        assert_eq!(registers["result"]["PC"], 0x202);
+
+        call(
+            r#"{"jsonrpc":"2.0","id":4,"method":"write_memory","params":{"address":768,"data":[1,2]}}"#,
+        );
+        let memory = call(
+            r#"{"jsonrpc":"2.0","id":5,"method":"read_memory","params":{"address":768,"length":3}}"#,
+        );
+        assert_eq!(memory["result"], json!([1, 2, 0]));
+
+        let state = call(r#"{"jsonrpc":"2.0","id":6,"method":"save_state"}"#)["result"].clone();
+        call(
+            r#"{"jsonrpc":"2.0","id":7,"method":"set_register","params":{"name":"v3","value":1}}"#,
+        );
+        let load_state =
+            json!({"jsonrpc":"2.0","id":8,"method":"load_state","params":{"state":state}});
+        call(&load_state.to_string());
+        let v3 =
+            call(r#"{"jsonrpc":"2.0","id":9,"method":"get_registers"}"#)["result"]["V3"].clone();
+        assert_eq!(v3, 0x2A);
+
+        assert_eq!(call(r#"{"jsonrpc":"2.0","method":"pause"}"#), Value::Null);
+        let status = call(r#"{"jsonrpc":"2.0","id":10,"method":"status"}"#);
+        assert_eq!(status["result"]["paused"], true);
+
+        call(r#"{"jsonrpc":"2.0","id":11,"method":"press_key","params":{"key":5}}"#);
+        assert!(server.held_keys()[5]);
+    }
+
+    #[test]
+    fn errors() {
+        let (_tx, rx) = channel();
+        let mut server = Server::new(rx);
+        let mut chip8 = Chip8::default();
+        let mut paused = false;
+        let mut call = |text: &str| {
+            request(&mut server, &mut chip8, &mut paused, text)["error"]["code"].clone()
+        };
+
+        assert_eq!(
+            call(r#"{"jsonrpc":"2.0","id":1,"method":"fly"}"#),
+            METHOD_NOT_FOUND
+        );
+        assert_eq!(call(r#"{"jsonrpc":"2.0","id":1}"#), INVALID_REQUEST);
+        assert_eq!(
+            call(r#"{"jsonrpc":"2.0","id":1,"method":"press_key","params":{"key":16}}"#),
+            INVALID_PARAMS
+        );
+        assert_eq!(
+            call(
+                r#"{"jsonrpc":"2.0","id":1,"method":"read_memory","params":{"address":4095,"length":2}}"#
+            ),
+            INVALID_PARAMS
+        );
+        assert_eq!(
+            call(r#"{"jsonrpc":"2.0","id":1,"method":"load_rom","params":{"data":"abc"}}"#),
+            INVALID_PARAMS
+        );
+    }
+
+    #[test]
+    fn step_count() {
+        let (_tx, rx) = channel();
+        let mut server = Server::new(rx);
+        let mut chip8 = Chip8::default();
+        chip8.set_trace(false);
+        let mut paused = false;
+        let mut call =
+            |text: &str| request(&mut server, &mut chip8, &mut paused, text)["result"].clone();
+
+        let step = r#"{"jsonrpc":"2.0","id":1,"method":"step","params":{"count":1000000000000}}"#;
+        assert_eq!(call(step), 0);
+        // JP 0x200
+        call(r#"{"jsonrpc":"2.0","id":2,"method":"load_rom","params":{"data":"1200"}}"#);
+        assert_eq!(call(step), MAX_STEPS);
+        let run_frame =
+            r#"{"jsonrpc":"2.0","id":3,"method":"run_frame","params":{"count":1000000000000}}"#;
+        assert_eq!(call(run_frame)["frames"], MAX_FRAMES);
+        // LD V3, 0x2A; an invalid instruction
+        call(r#"{"jsonrpc":"2.0","id":4,"method":"load_rom","params":{"data":"632affff"}}"#);
+        assert_eq!(call(step), 1);
+        assert_eq!(call(step), 0);
+    }
+
+    #[test]
+    fn serve_lines() {
+        let (tx, rx) = channel();
+        let mut server = Server::new(rx);
+        let input = b"{\"jsonrpc\":\"2.0\",\"id\":1,\"method\":\"status\"}\nnot json\n".to_vec();
+        let handle = std::thread::spawn(move || {
+            let mut output = Vec::new();
+            serve(&input[..], &mut output, tx);
+            output
+        });
+        let mut chip8 = Chip8::default();
+        let mut paused = false;
+        while !handle.is_finished() {
+            server.poll(&mut chip8, &mut paused);
+        }
+        let output = String::from_utf8(handle.join().unwrap()).unwrap();
+        let lines: Vec<Value> = output
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        assert_eq!(lines[0]["result"]["loaded"], false);
+        assert_eq!(lines[1]["error"]["code"], PARSE_ERROR);
+    }
+}
@@ -0,0 +1,353 @@
+//! Rhai scripting for automating and extending a running [`Chip8`].
+//!
+//! A script's top level runs once when it is attached, and registers callbacks that run later:
+//!
+//! ```rhai
+//! // infinite lives
+//! on_write(0x2F4, |address, value| poke(address, 3));
+//! on_frame(|frame| if frame % 60 == 0 { print(`score: ${peek(0x2F0)}`) });
+//! on_pc(0x2A6, |pc| press(5));
+//! ```
+//!
+//! Functions available to scripts:
+//!
+//! - `peek(address)`, `poke(address, value)` read and write memory
+//! - `reg(name)`, `set_reg(name, value)` read and write registers (`V0`-`VF`, `I`, `PC`, `SP`, `DT`, `ST`)
+//! - `press(key)`, `release(key)` hold and release keys on the hex keypad
+//! - `screenshot(path)` save the display as a PBM image
+//! - `frame()` number of frames since the script was attached
+//! - `on_frame(fn(frame))`, `on_pc(address, fn(pc))`, `on_write(address, fn(address, value))`,
+//!   `on_write(start, end, fn(address, value))` register callbacks. Callbacks run between
+//!   instructions, so an `on_pc` callback runs just after the instruction at its address.
+
+use std::{cell::RefCell, fmt::Write as _, path::Path, rc::Rc};
+
+use chip8_core::{
+    chip8::{Chip8, Register},
+    hooks::{HookEvent, HookId},
+};
+use rhai::{Dynamic, Engine, EvalAltResult, FnPtr, AST, INT};
+
+pub type ScriptResult<T> = Result<T, Box<EvalAltResult>>;
+
+struct State {
+    /// The machine while a script call is running, otherwise a placeholder
+    chip8: Chip8,
+    /// Keys held by the script, combined with the frontend's own input
+    held: [bool; 16],
+    frame: u64,
+    frame_hooks: Vec<FnPtr>,
+    /// Callbacks for the machine's pc and write hooks
+    hooks: Vec<(HookId, FnPtr)>,
+}
+
+pub struct Script {
+    engine: Engine,
+    ast: AST,
+    state: Rc<RefCell<State>>,
+}
+
+impl Script {
+    /// Compile a script from source.
+    pub fn new(source: &str) -> ScriptResult<Script> {
+        let state = Rc::new(RefCell::new(State {
+            chip8: Chip8::default(),
+            held: [false; 16],
+            frame: 0,
+            frame_hooks: Vec::new(),
+            hooks: Vec::new(),
+        }));
+        let engine = create_engine(&state);
+        let ast = engine.compile(source)?;
+        Ok(Self { engine, ast, state })
+    }
+
+    /// Compile a script from a file.
+    pub fn load<P: AsRef<Path>>(path: P) -> ScriptResult<Script> {
+        let source = std::fs::read_to_string(path.as_ref())
+            .map_err(|e| format!("can't read {}: {e}", path.as_ref().display()))?;
+        Self::new(&source)
+    }
+
+    /// Run the script's top level against `chip8`, registering its callbacks.
+    pub fn attach(&mut self, chip8: &mut Chip8) -> ScriptResult<()> {
+        self.lend(chip8, |engine, ast| engine.run_ast(ast))
+    }
+
+    /// Remove the script's hooks from `chip8` and release any keys it is holding.
+    pub fn detach(&mut self, chip8: &mut Chip8) {
+        let mut state = self.state.borrow_mut();
+        for (id, _) in state.hooks.drain(..) {
+            chip8.remove_hook(id);
+        }
+        state.frame_hooks.clear();
+        for (key, held) in state.held.iter_mut().enumerate() {
+            if *held {
+                chip8.set_key_state(key, false);
+                *held = false;
+            }
+        }
+    }
+
+    /// Run callbacks for hook events queued by the last instructions. Call after each step;
+    /// pc callbacks therefore see the machine after the hooked instruction has executed.
+    pub fn after_step(&mut self, chip8: &mut Chip8) -> ScriptResult<()> {
+        let calls = {
+            let state = self.state.borrow();
+            let ids: Vec<HookId> = state.hooks.iter().map(|(id, _)| *id).collect();
+            if !chip8.has_hook_events(&ids) {
+                return Ok(());
+            }
+            chip8
+                .take_hook_events(&ids)
+                .into_iter()
+                .filter_map(|(id, event)| {
+                    let (_, f) = state.hooks.iter().find(|(hook, _)| *hook == id)?;
+                    let args = match event {
+                        HookEvent::Pc(pc) => vec![Dynamic::from(pc as INT)],
+                        HookEvent::Write { address, value } => {
+                            vec![Dynamic::from(address as INT), Dynamic::from(value as INT)]
+                        }
+                        HookEvent::Read { .. } => return None,
+                    };
+                    Some((f.clone(), args))
+                })
+                .collect()
+        };
+        self.call_all(chip8, calls)
+    }
+
+    /// Run frame callbacks. Call once per 60 Hz frame.
+    pub fn end_frame(&mut self, chip8: &mut Chip8) -> ScriptResult<()> {
+        let calls = {
+            let mut state = self.state.borrow_mut();
+            state.frame += 1;
+            let frame = Dynamic::from(state.frame as INT);
+            state
+                .frame_hooks
+                .iter()
+                .map(|f| (f.clone(), vec![frame.clone()]))
+                .collect()
+        };
+        self.call_all(chip8, calls)
+    }
+
+    /// Keys the script is holding down, to be combined with the frontend's own input.
+    pub fn held_keys(&self) -> [bool; 16] {
+        self.state.borrow().held
+    }
+
+    fn call_all(
+        &mut self,
+        chip8: &mut Chip8,
+        calls: Vec<(FnPtr, Vec<Dynamic>)>,
+    ) -> ScriptResult<()> {
+        if calls.is_empty() {
+            return Ok(());
+        }
+        self.lend(chip8, |engine, ast| {
+            for (f, args) in calls {
+                let _: Dynamic = f.call(engine, ast, args)?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Swap `chip8` into the shared state for the duration of `f`, so script functions can
+    /// reach it.
+    fn lend<T>(&mut self, chip8: &mut Chip8, f: impl FnOnce(&Engine, &AST) -> T) -> T {
+        std::mem::swap(chip8, &mut self.state.borrow_mut().chip8);
+        let result = f(&self.engine, &self.ast);
+        std::mem::swap(chip8, &mut self.state.borrow_mut().chip8);
+        result
+    }
+}
+
+fn create_engine(state: &Rc<RefCell<State>>) -> Engine {
+    let mut engine = Engine::new();
+
+    let s = state.clone();
+    engine.register_fn("peek", move |address: INT| -> ScriptResult<INT> {
+        let state = s.borrow();
+        usize::try_from(address)
+            .ok()
+            .and_then(|address| state.chip8.read_memory(address, 1))
+            .map(|bytes| bytes[0] as INT)
+            .ok_or_else(|| format!("address out of range: {address:#X}").into())
+    });
+    let s = state.clone();
+    engine.register_fn(
+        "poke",
+        move |address: INT, value: INT| -> ScriptResult<()> {
+            let ok = usize::try_from(address)
+                .is_ok_and(|address| s.borrow_mut().chip8.write_memory(address, &[value as u8]));
+            if ok {
+                Ok(())
+            } else {
+                Err(format!("address out of range: {address:#X}").into())
+            }
+        },
+    );
+
+    let s = state.clone();
+    engine.register_fn("reg", move |name: &str| -> ScriptResult<INT> {
+        let register = name.parse::<Register>()?;
+        Ok(s.borrow().chip8.get_register(register) as INT)
+    });
+    let s = state.clone();
+    engine.register_fn(
+        "set_reg",
+        move |name: &str, value: INT| -> ScriptResult<()> {
+            let register = name.parse::<Register>()?;
+            s.borrow_mut().chip8.set_register(register, value as u16);
+            Ok(())
+        },
+    );
+
+    let s = state.clone();
+    engine.register_fn("press", move |key: INT| set_key(&s, key, true));
+    let s = state.clone();
+    engine.register_fn("release", move |key: INT| set_key(&s, key, false));
+
+    let s = state.clone();
+    engine.register_fn("screenshot", move |path: &str| -> ScriptResult<()> {
+        let pbm = to_pbm(&s.borrow().chip8);
+        std::fs::write(path, pbm).map_err(|e| format!("can't write {path}: {e}").into())
+    });
+    let s = state.clone();
+    engine.register_fn("frame", move || s.borrow().frame as INT);
+
+    let s = state.clone();
+    engine.register_fn("on_frame", move |f: FnPtr| {
+        s.borrow_mut().frame_hooks.push(f);
+    });
+    let s = state.clone();
+    engine.register_fn("on_pc", move |pc: INT, f: FnPtr| -> ScriptResult<()> {
+        let pc = hook_address(pc)?;
+        let mut state = s.borrow_mut();
+        let id = state.chip8.add_pc_hook(pc);
+        state.hooks.push((id, f));
+        Ok(())
+    });
+    let s = state.clone();
+    engine.register_fn("on_write", move |address: INT, f: FnPtr| {
+        add_write_hook(&s, address, address, f)
+    });
+    let s = state.clone();
+    engine.register_fn("on_write", move |start: INT, end: INT, f: FnPtr| {
+        add_write_hook(&s, start, end, f)
+    });
+
+    engine
+}
+
+fn set_key(state: &Rc<RefCell<State>>, key: INT, pressed: bool) -> ScriptResult<()> {
+    if !(0..16).contains(&key) {
+        return Err(format!("invalid key: {key}").into());
+    }
+    let mut state = state.borrow_mut();
+    state.held[key as usize] = pressed;
+    state.chip8.set_key_state(key as usize, pressed);
+    Ok(())
+}
+
+/// Hooks can only watch the first 64 KiB.
+fn hook_address(address: INT) -> ScriptResult<u16> {
+    u16::try_from(address).map_err(|_| format!("can't hook address {address:#X}").into())
+}
+
+fn add_write_hook(state: &Rc<RefCell<State>>, start: INT, end: INT, f: FnPtr) -> ScriptResult<()> {
+    let (start, end) = (hook_address(start)?, hook_address(end)?);
+    let mut state = state.borrow_mut();
+    let id = state.chip8.add_write_hook(start, end);
+    state.hooks.push((id, f));
+    Ok(())
+}
+
+/// Render the display as a plain PBM image.
+fn to_pbm(chip8: &Chip8) -> String {
+    let (width, height) = chip8.get_display_size();
+    let mut pbm = format!("P1\n{width} {height}\n");
+    for row in chip8.get_display().chunks(width) {
+        for pixel in row {
+            let _ = write!(pbm, "{} ", pixel & 1);
+        }
+        pbm.push('\n');
+    }
+    pbm
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // loop: ADD V1, 1; LD I, 0x2FF; LD [I], V1; JP loop
+    const ROM: [u8; 8] = [0x71, 0x01, 0xA2, 0xFF, 0xF1, 0x55, 0x12, 0x00];
+
+    fn machine() -> Chip8 {
+        let mut chip8 = Chip8::default();
+        chip8.set_trace(false);
+        chip8.load_rom_bytes(&ROM).unwrap();
+        chip8
+    }
+
+    #[test]
+    fn hooks_and_memory_access() {
+        let mut chip8 = machine();
+        let mut script = Script::new(
+            r#"
+            poke(0x400, 9);
+            on_pc(0x206, |pc| set_reg("V2", reg("V2") + 1));
+            on_write(0x300, |address, value| poke(0x401, value * 2));
+            on_frame(|frame| { poke(0x402, frame); press(0xA); });
+            "#,
+        )
+        .unwrap();
+        // a hook of the frontend's own on the same address as the script's
+        let own = chip8.add_write_hook(0x300, 0x300);
+        script.attach(&mut chip8).unwrap();
+        assert_eq!(chip8.read_memory(0x400, 1), Some(&[9][..]));
+
+        for _ in 0..8 {
+            chip8.step();
+            script.after_step(&mut chip8).unwrap();
+        }
+        script.end_frame(&mut chip8).unwrap();
+        assert_eq!(chip8.get_register(Register::V(2)), 2);
+        assert_eq!(chip8.read_memory(0x401, 2), Some(&[4, 1][..]));
+        assert!(script.held_keys()[0xA]);
+        assert_eq!(chip8.take_hook_events(&[own]).len(), 2);
+
+        script.detach(&mut chip8);
+        assert!(!script.held_keys()[0xA]);
+        chip8.load_rom_bytes(&ROM).unwrap();
+        for _ in 0..8 {
+            chip8.step();
+        }
+        assert_eq!(chip8.take_hook_events(&[own]).len(), 2);
+    }
+
+    #[test]
+    fn errors() {
+        assert!(Script::new("let x = ;").is_err());
+        let mut chip8 = machine();
+        assert!(Script::new("peek(0x2000)")
+            .unwrap()
+            .attach(&mut chip8)
+            .is_err());
+        assert!(Script::new("press(16)")
+            .unwrap()
+            .attach(&mut chip8)
+            .is_err());
+        assert!(Script::new(r#"reg("VX")"#)
+            .unwrap()
+            .attach(&mut chip8)
+            .is_err());
+        for source in ["on_pc(-1, |pc| 0)", "on_write(0x10000, |a, v| 0)"] {
+            let mut script = Script::new(source).unwrap();
+            assert!(script.attach(&mut chip8).is_err());
+        }
+        // the machine is handed back even when the script fails
+        assert!(chip8.is_loaded());
+    }
+}
@@ -0,0 +1,215 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use chip8_core::rom::ROM_EXTENSIONS;
+
+/// Number of recently played ROMs to remember.
+const MAX_RECENT: usize = 10;
+
+/// A file listed in the browser.
+pub struct RomEntry {
+    pub path: PathBuf,
+    pub name: String,
+    /// File size in bytes
+    pub size: u64,
+    pub kind: &'static str,
+}
+
+/// Lists the ROMs in a directory and the recently played ones, any of which can be loaded by
+/// double-clicking.
+pub struct RomBrowser {
+    pub open: bool,
+    dir: PathBuf,
+    entries: Vec<RomEntry>,
+    /// Error from the last scan of `dir`
+    error: Option<String>,
+    /// Most recent first
+    recent: Vec<PathBuf>,
+    selected: Option<PathBuf>,
+}
+
+impl RomBrowser {
+    pub fn new(dir: PathBuf) -> Self {
+        let mut browser = Self {
+            open: false,
+            dir,
+            entries: Vec::new(),
+            error: None,
+            recent: load_recent(),
+            selected: None,
+        };
+        browser.refresh();
+        browser
+    }
+
+    /// Read the directory again.
+    pub fn refresh(&mut self) {
+        match scan(&self.dir) {
+            Ok(entries) => {
+                self.entries = entries;
+                self.error = None;
+            }
+            Err(e) => {
+                self.entries.clear();
+                self.error = Some(format!("can't read {}: {e}", self.dir.display()));
+            }
+        }
+    }
+
+    /// Move a ROM to the top of the recently played list.
+    pub fn add_recent(&mut self, path: &Path) {
+        let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        self.recent.retain(|recent| *recent != path);
+        self.recent.insert(0, path);
+        self.recent.truncate(MAX_RECENT);
+        if let Err(e) = save_recent(&self.recent) {
+            eprintln!("failed to save recent roms: {e}");
+        }
+    }
+
+    /// Show the browser window, returning the ROM to load if one was double-clicked.
+    pub fn show(&mut self, ctx: &egui::Context) -> Option<PathBuf> {
+        let mut open = self.open;
+        let mut chosen = None;
+        egui::Window::new("ROM Browser")
+            .open(&mut open)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label(self.dir.display().to_string());
+                    if ui.button("Refresh").clicked() {
+                        self.refresh();
+                    }
+                });
+                if let Some(error) = &self.error {
+                    ui.label(error);
+                }
+                egui::ScrollArea::vertical()
+                    .id_source("roms")
+                    .max_height(300.0)
+                    .show(ui, |ui| {
+                        egui::Grid::new("rom grid").striped(true).show(ui, |ui| {
+                            for entry in &self.entries {
+                                let selected = self.selected.as_ref() == Some(&entry.path);
+                                let response = ui.selectable_label(selected, &entry.name);
+                                if response.clicked() {
+                                    self.selected = Some(entry.path.clone());
+                                }
+                                if response.double_clicked() {
+                                    chosen = Some(entry.path.clone());
+                                }
+                                ui.label(format_size(entry.size));
+                                ui.label(entry.kind);
+                                ui.end_row();
+                            }
+                        });
+                    });
+                ui.separator();
+                ui.label("Recently played");
+                for path in &self.recent {
+                    let name = path.file_name().map_or_else(
+                        || path.display().to_string(),
+                        |name| name.to_string_lossy().into_owned(),
+                    );
+                    let selected = self.selected.as_ref() == Some(path);
+                    let response = ui
+                        .selectable_label(selected, name)
+                        .on_hover_text(path.display().to_string());
+                    if response.clicked() {
+                        self.selected = Some(path.clone());
+                    }
+                    if response.double_clicked() {
+                        chosen = Some(path.clone());
+                    }
+                }
+            });
+        self.open = open;
+        chosen
+    }
+}
+
+/// List the ROMs, cartridges and archives in `dir`, sorted by name.
+fn scan(dir: &Path) -> std::io::Result<Vec<RomEntry>> {
+    let mut entries = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        if !metadata.is_file() {
+            continue;
+        }
+        let path = entry.path();
+        let Some(kind) = rom_kind(&path) else {
+            continue;
+        };
+        entries.push(RomEntry {
+            name: entry.file_name().to_string_lossy().into_owned(),
+            path,
+            size: metadata.len(),
+            kind,
+        });
+    }
+    entries.sort_by_cached_key(|entry| entry.name.to_lowercase());
+    Ok(entries)
+}
+
+/// Describe a file that can be loaded, or None for other files. Files without an extension
+/// are included since many CHIP-8 ROMs don't have one.
+fn rom_kind(path: &Path) -> Option<&'static str> {
+    let Some(extension) = path.extension() else {
+        return Some("ROM");
+    };
+    let extension = extension.to_string_lossy().to_ascii_lowercase();
+    match extension.as_str() {
+        "gif" => Some("Octo cartridge"),
+        "zip" | "gz" => Some("archive"),
+        e if ROM_EXTENSIONS.contains(&e) => Some("ROM"),
+        _ => None,
+    }
+}
+
+fn format_size(size: u64) -> String {
+    if size < 1024 {
+        format!("{size} B")
+    } else {
+        format!("{:.1} KiB", size as f64 / 1024.0)
+    }
+}
+
+/// Where the recently played list is kept, in the user's configuration directory.
+fn recent_path() -> Option<PathBuf> {
+    let config = if cfg!(windows) {
+        PathBuf::from(std::env::var_os("APPDATA")?)
+    } else if let Some(config) = std::env::var_os("XDG_CONFIG_HOME") {
+        PathBuf::from(config)
+    } else {
+        PathBuf::from(std::env::var_os("HOME")?).join(".config")
+    };
+    Some(config.join("chip8-emu").join("recent"))
+}
+
+/// Read the recently played list, one path per line.
+fn load_recent() -> Vec<PathBuf> {
+    let Some(contents) = recent_path().and_then(|path| fs::read_to_string(path).ok()) else {
+        return Vec::new();
+    };
+    contents
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(PathBuf::from)
+        .take(MAX_RECENT)
+        .collect()
+}
+
+fn save_recent(recent: &[PathBuf]) -> std::io::Result<()> {
+    let path = recent_path().ok_or_else(|| std::io::Error::other("no config directory"))?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let mut contents = String::new();
+    for rom in recent {
+        contents.push_str(&rom.to_string_lossy());
+        contents.push('\n');
+    }
+    fs::write(path, contents)
+}
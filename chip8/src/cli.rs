@@ -5,4 +5,35 @@ use clap::Parser;
 #[derive(Debug, Parser)]
 pub struct Cli {
     pub rom_path: Option<PathBuf>,
-}
\ No newline at end of file
+    /// CPU speed in Hz
+    #[arg(long, default_value_t = 500)]
+    pub speed: u32,
+    /// Directory listed in the ROM browser
+    #[arg(long, default_value = "roms")]
+    pub rom_dir: PathBuf,
+    /// Palette file to use instead of the ROM's own or the default
+    #[arg(long)]
+    pub palette: Option<PathBuf>,
+    /// Rhai script to run alongside the ROM
+    #[arg(long)]
+    pub script: Option<PathBuf>,
+    /// Listen for JSON-RPC connections on this TCP port on localhost
+    #[arg(long)]
+    pub rpc_port: Option<u16>,
+    /// Listen for JSON-RPC connections on this Unix socket
+    #[cfg(unix)]
+    #[arg(long)]
+    pub rpc_socket: Option<PathBuf>,
+    /// Listen for a GDB remote debugger on this TCP port on localhost
+    #[arg(long)]
+    pub gdb: Option<u16>,
+    /// Start recording to this .gif or .y4m file
+    #[arg(long)]
+    pub record: Option<PathBuf>,
+    /// Directory screenshots and recordings started with F12 and F9 are saved to
+    #[arg(long, default_value = ".")]
+    pub capture_dir: PathBuf,
+    /// Size of each CHIP-8 pixel in screenshots and recordings
+    #[arg(long, default_value_t = 8)]
+    pub capture_scale: usize,
+}
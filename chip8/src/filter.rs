@@ -0,0 +1,144 @@
+use std::collections::VecDeque;
+
+/// How the framebuffer is smoothed over time before it's drawn.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FilterKind {
+    /// Draw the current frame as is
+    Off,
+    /// Pixels fade out over several frames after they're turned off, like a phosphor screen
+    Decay,
+    /// A pixel is lit if it was lit in any of the last few frames
+    Or,
+}
+
+impl FilterKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            FilterKind::Off => "Off",
+            FilterKind::Decay => "Phosphor decay",
+            FilterKind::Or => "OR of last frames",
+        }
+    }
+}
+
+/// Reduces the flicker caused by games erasing and redrawing sprites every frame.
+pub struct DisplayFilter {
+    pub kind: FilterKind,
+    /// Fraction of a pixel's brightness kept each frame in decay mode
+    pub decay: f32,
+    /// Number of frames combined in OR mode
+    pub frames: usize,
+    /// Brightness of each pixel from 0 to 1
+    intensity: Vec<f32>,
+    /// Most recent frames first
+    history: VecDeque<Vec<u8>>,
+    /// Counter that changes whenever a frame is added
+    generation: u64,
+}
+
+impl DisplayFilter {
+    pub fn new() -> Self {
+        Self {
+            kind: FilterKind::Off,
+            decay: 0.6,
+            frames: 3,
+            intensity: Vec::new(),
+            history: VecDeque::new(),
+            generation: 0,
+        }
+    }
+
+    /// Add an emulated frame, fading or combining it with the ones before. Call this once per
+    /// emulated frame, so the result doesn't depend on the render rate or pausing.
+    pub fn push(&mut self, pixels: &[u8]) {
+        if self.intensity.len() != pixels.len() {
+            self.intensity = vec![0.0; pixels.len()];
+            self.history.clear();
+        }
+        self.generation = self.generation.wrapping_add(1);
+
+        self.history.push_front(pixels.to_vec());
+        self.history.truncate(self.frames.max(1));
+
+        match self.kind {
+            FilterKind::Off => {
+                for (intensity, pixel) in self.intensity.iter_mut().zip(pixels) {
+                    *intensity = lit(*pixel);
+                }
+            }
+            FilterKind::Decay => {
+                for (intensity, pixel) in self.intensity.iter_mut().zip(pixels) {
+                    *intensity = lit(*pixel).max(*intensity * self.decay);
+                }
+            }
+            FilterKind::Or => {
+                for (i, intensity) in self.intensity.iter_mut().enumerate() {
+                    let any = self.history.iter().any(|frame| frame[i] != 0);
+                    *intensity = if any { 1.0 } else { 0.0 };
+                }
+            }
+        }
+    }
+
+    /// The brightness of each pixel to draw for the current display, as of the last frame
+    /// added. Unfiltered, or if no frame of this size has been added yet, it's just `pixels`.
+    pub fn intensity(&mut self, pixels: &[u8]) -> &[f32] {
+        if self.kind == FilterKind::Off || self.intensity.len() != pixels.len() {
+            self.intensity = pixels.iter().map(|pixel| lit(*pixel)).collect();
+            self.history.clear();
+        }
+        &self.intensity
+    }
+
+    /// Counter that changes whenever a frame is added, so frontends know when to redraw.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+}
+
+fn lit(pixel: u8) -> f32 {
+    if pixel != 0 {
+        1.0
+    } else {
+        0.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decay() {
+        let mut filter = DisplayFilter::new();
+        filter.kind = FilterKind::Decay;
+        filter.decay = 0.5;
+        filter.push(&[1, 0]);
+        filter.push(&[0, 0]);
+        assert_eq!(filter.intensity(&[0, 0]), [0.5, 0.0]);
+        // rendering again without a new frame, as when paused, doesn't fade any further
+        assert_eq!(filter.intensity(&[0, 0]), [0.5, 0.0]);
+        filter.push(&[0, 1]);
+        assert_eq!(filter.intensity(&[0, 1]), [0.25, 1.0]);
+
+        filter.kind = FilterKind::Off;
+        assert_eq!(filter.intensity(&[0, 1]), [0.0, 1.0]);
+    }
+
+    #[test]
+    fn or() {
+        let mut filter = DisplayFilter::new();
+        filter.kind = FilterKind::Or;
+        filter.frames = 2;
+        filter.push(&[1, 0]);
+        filter.push(&[0, 0]);
+        assert_eq!(filter.intensity(&[0, 0]), [1.0, 0.0]);
+        filter.push(&[0, 1]);
+        assert_eq!(filter.intensity(&[0, 1]), [0.0, 1.0]);
+
+        // a different size starts over from the current display
+        let generation = filter.generation();
+        assert_eq!(filter.intensity(&[1, 0, 0]), [1.0, 0.0, 0.0]);
+        assert_eq!(filter.generation(), generation);
+    }
+}
@@ -0,0 +1,132 @@
+use chip8_core::{chip8::Chip8, platform::Platform};
+
+/// The keys as they're laid out on the COSMAC VIP keypad.
+const LAYOUT: [[usize; 4]; 4] = [
+    [0x1, 0x2, 0x3, 0xC],
+    [0x4, 0x5, 0x6, 0xD],
+    [0x7, 0x8, 0x9, 0xE],
+    [0xA, 0x0, 0xB, 0xF],
+];
+
+/// How long a key stays outlined after the ROM checked it, in seconds.
+const POLL_HIGHLIGHT: f64 = 0.5;
+
+/// An on-screen hex keypad. Keys are held while a pointer or touch is down on them, light up
+/// while pressed by any source, and are outlined while the ROM is checking them.
+pub struct Keypad {
+    pub open: bool,
+    /// Keys held down on the keypad itself
+    held: [bool; 16],
+    /// Keys held down on the second CHIP-8X keypad, shown on that platform only
+    held2: [bool; 16],
+    /// When each key was last polled by the ROM
+    polled_at: [f64; 16],
+}
+
+impl Keypad {
+    pub fn new() -> Self {
+        Self {
+            open: false,
+            held: [false; 16],
+            held2: [false; 16],
+            polled_at: [f64::NEG_INFINITY; 16],
+        }
+    }
+
+    /// Keys held down on the keypad. Nothing is held while it's closed.
+    pub fn held_keys(&self) -> [bool; 16] {
+        if self.open {
+            self.held
+        } else {
+            [false; 16]
+        }
+    }
+
+    /// Keys held down on the second keypad.
+    pub fn held_keys2(&self) -> [bool; 16] {
+        if self.open {
+            self.held2
+        } else {
+            [false; 16]
+        }
+    }
+
+    /// Show the keypad window, taking the keys polled by the ROM since the last frame.
+    pub fn show(&mut self, ctx: &egui::Context, chip8: &mut Chip8) {
+        let now = ctx.input().time;
+        let polled = chip8.take_polled_keys();
+        for (key, polled_at) in self.polled_at.iter_mut().enumerate() {
+            if polled & (1 << key) != 0 {
+                *polled_at = now;
+            }
+        }
+        if !self.open {
+            return;
+        }
+
+        let mut open = self.open;
+        egui::Window::new("Keypad")
+            .open(&mut open)
+            .resizable(false)
+            .show(ctx, |ui| {
+                let polled = |key: usize| now - self.polled_at[key] < POLL_HIGHLIGHT;
+                key_grid(
+                    ui,
+                    "keypad grid",
+                    &mut self.held,
+                    |key| chip8.get_key_state(key),
+                    polled,
+                );
+                ui.label("outlined keys are being checked by the ROM");
+                if chip8.platform() == Platform::Chip8x {
+                    ui.separator();
+                    ui.label("Keypad 2");
+                    let held2 = self.held2;
+                    key_grid(
+                        ui,
+                        "keypad 2 grid",
+                        &mut self.held2,
+                        |key| held2[key],
+                        |_| false,
+                    );
+                }
+            });
+        self.open = open;
+        if !self.open {
+            self.held = [false; 16];
+            self.held2 = [false; 16];
+        }
+    }
+}
+
+/// Show a 4x4 grid of keys, recording which are held down. Pressed keys are filled and polled
+/// ones outlined.
+fn key_grid(
+    ui: &mut egui::Ui,
+    id: &str,
+    held: &mut [bool; 16],
+    pressed: impl Fn(usize) -> bool,
+    polled: impl Fn(usize) -> bool,
+) {
+    let visuals = ui.visuals().clone();
+    egui::Grid::new(id).spacing([4.0, 4.0]).show(ui, |ui| {
+        for row in LAYOUT {
+            for key in row {
+                let mut button = egui::Button::new(
+                    egui::RichText::new(format!("{key:X}"))
+                        .monospace()
+                        .size(20.0),
+                );
+                if pressed(key) {
+                    button = button.fill(visuals.selection.bg_fill);
+                }
+                if polled(key) {
+                    button = button.stroke(egui::Stroke::new(2.0, visuals.warn_fg_color));
+                }
+                let response = ui.add_sized([40.0, 40.0], button);
+                held[key] = response.is_pointer_button_down_on();
+            }
+            ui.end_row();
+        }
+    });
+}
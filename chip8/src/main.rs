@@ -1,12 +1,39 @@
-use chip8_core::chip8::Chip8;
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use chip8_capture::{Format, Recorder};
+use chip8_carts::{Cartridge, Options};
+use chip8_core::{
+    chip8::Chip8,
+    color::{self, DisplayColors},
+    font::FontSet,
+    platform::Platform,
+    scheduler::Scheduler,
+};
+use chip8_gdb::GdbStub;
+use chip8_rpc::Server;
+use chip8_script::{Script, ScriptResult};
 use clap::Parser;
+mod browser;
 mod cli;
+mod filter;
+mod keypad;
+mod palette;
+mod profiler;
 
-use egui_file::FileDialog;
+use browser::RomBrowser;
+use egui_file::{DialogType, FileDialog};
+use filter::{DisplayFilter, FilterKind};
+use keypad::Keypad;
 use macroquad::prelude::*;
+use palette::Palette;
+use profiler::{Export, Profiler};
 
 const WIDTH: usize = 64;
-const HEIGHT: usize = 32;
 
 // QWERTY     CHIP8
 // 1 2 3 4    1 2 3 C
@@ -14,6 +41,9 @@ const HEIGHT: usize = 32;
 // A S D F    7 8 9 E
 // Z X C V    A 0 B F
 
+/// Hold to run faster than real time.
+const FAST_FORWARD_KEY: KeyCode = KeyCode::Tab;
+
 const KEY_MAP: [KeyCode; 16] = [
     KeyCode::X,
     KeyCode::Key1,
@@ -35,50 +65,179 @@ const KEY_MAP: [KeyCode; 16] = [
 
 struct State {
     target_fps: f32,
-    cycles_per_frame: u32,
+    scheduler: Scheduler,
+    /// Speed multiplier while the fast-forward key is held
+    fast_forward: f32,
+    slow_motion: bool,
+    /// Speed multiplier in slow motion
+    slow_motion_rate: f32,
     paused: bool,
     game_scale: f32,
     game_centered: bool,
-    game_foreground_color: [f32; 3],
-    game_background_color: [f32; 3],
+    palette: Palette,
     window_color: [f32; 3],
+    filter: DisplayFilter,
     file_dialog: Option<FileDialog>,
+    script_dialog: Option<FileDialog>,
+    palette_dialog: Option<FileDialog>,
+    script: Option<Script>,
+    script_name: Option<String>,
+    servers: Vec<Server>,
+    gdb: Option<GdbStub>,
+    recorder: Option<Recorder>,
+    capture_dir: PathBuf,
+    capture_scale: usize,
+    /// Options of the loaded Octo cartridge, kept when saving it again
+    cartridge: Option<Options>,
+    browser: RomBrowser,
+    keypad: Keypad,
+    profiler: Profiler,
+    /// Files in the last drop on the window
+    dropped: Vec<PathBuf>,
 }
 
 #[macroquad::main("Chip8")]
 async fn main() {
     let cli = cli::Cli::parse();
     let mut chip8 = Chip8::default();
-    if let Some(path) = cli.rom_path {
-        chip8.load_rom(path).unwrap();
-    }
+    chip8.set_speed(cli.speed);
 
     let mut egui_state = State {
         target_fps: 60.0,
-        cycles_per_frame: 1,
+        scheduler: Scheduler::new(),
+        fast_forward: 4.0,
+        slow_motion: false,
+        slow_motion_rate: 0.25,
         paused: false,
         game_scale: 10.0,
         game_centered: true,
         window_color: [0.1; 3],
-        game_foreground_color: [1.0; 3],
-        game_background_color: [0.0; 3],
+        palette: Palette::default(),
+        filter: DisplayFilter::new(),
         file_dialog: None,
+        script_dialog: None,
+        palette_dialog: None,
+        script: None,
+        script_name: None,
+        servers: Vec::new(),
+        gdb: None,
+        recorder: None,
+        capture_dir: cli.capture_dir,
+        capture_scale: cli.capture_scale,
+        cartridge: None,
+        browser: RomBrowser::new(cli.rom_dir),
+        keypad: Keypad::new(),
+        profiler: Profiler::new(),
+        dropped: Vec::new(),
     };
+    if let Some(path) = &cli.rom_path {
+        open_rom(&mut egui_state, &mut chip8, path);
+    }
+    if let Some(path) = &cli.palette {
+        match Palette::load(path) {
+            Ok(palette) => egui_state.palette = palette,
+            Err(e) => eprintln!("failed to load palette: {e}"),
+        }
+    }
+    if let Some(port) = cli.rpc_port {
+        match Server::listen_tcp(("127.0.0.1", port)) {
+            Ok(server) => egui_state.servers.push(server),
+            Err(e) => {
+                eprintln!("failed to listen on port {port}: {e}");
+                std::process::exit(1);
+            }
+        }
+    }
+    #[cfg(unix)]
+    if let Some(path) = &cli.rpc_socket {
+        match Server::listen_unix(path) {
+            Ok(server) => egui_state.servers.push(server),
+            Err(e) => {
+                eprintln!("failed to listen on {}: {e}", path.display());
+                std::process::exit(1);
+            }
+        }
+    }
+    if let Some(port) = cli.gdb {
+        match GdbStub::listen(("127.0.0.1", port)) {
+            Ok(gdb) => egui_state.gdb = Some(gdb),
+            Err(e) => {
+                eprintln!("failed to listen for gdb on port {port}: {e}");
+                std::process::exit(1);
+            }
+        }
+    }
+    if let Some(path) = &cli.record {
+        start_recording(&mut egui_state, &chip8, path);
+    }
+    if let Some(path) = cli.script {
+        load_script(&mut egui_state, &mut chip8, &path);
+    }
 
-    let width = WIDTH as f32;
-    let height = HEIGHT as f32;
     let mut total_cycles = 0u64;
     let mut last_time = 0.0;
+    let (width, height) = chip8.get_display_size();
+    let mut image = Image::gen_image_color(width as u16, height as u16, BLACK);
+    let mut texture = Texture2D::from_image(&image);
+    texture.set_filter(FilterMode::Nearest);
+    // what the texture was last rendered from, so unchanged frames aren't uploaded again
+    let mut rendered = None;
 
     loop {
-        update_keys(&mut chip8);
+        for server in egui_state.servers.iter_mut() {
+            server.poll(&mut chip8, &mut egui_state.paused);
+        }
+        if let Some(gdb) = &mut egui_state.gdb {
+            gdb.poll(&mut chip8);
+        }
+        update_keys(&mut chip8, &egui_state);
+        for path in dropped_files(&mut egui_state.dropped) {
+            if open_file(&mut egui_state, &mut chip8, &path) {
+                total_cycles = 0;
+            }
+        }
 
-        // run emulator cycles
-        if !egui_state.paused && chip8.is_loaded() {
-            for _ in 0..egui_state.cycles_per_frame {
-                chip8.step();
+        // run as many 60 Hz frames as are due for the real time that has passed, independent
+        // of the render frame rate
+        let rate = if is_key_down(FAST_FORWARD_KEY) {
+            egui_state.fast_forward
+        } else if egui_state.slow_motion {
+            egui_state.slow_motion_rate
+        } else {
+            1.0
+        };
+        egui_state.scheduler.set_rate(rate as f64);
+        let frames = if !egui_state.paused && chip8.is_loaded() {
+            let elapsed = Duration::from_secs_f32(get_frame_time());
+            egui_state.scheduler.advance(elapsed)
+        } else {
+            egui_state.scheduler.reset();
+            0
+        };
+        'frames: for _ in 0..frames {
+            for _ in 0..egui_state.scheduler.frame_cycles(chip8.speed()) {
+                match &mut egui_state.gdb {
+                    Some(gdb) => {
+                        if !gdb.step(&mut chip8) {
+                            break 'frames;
+                        }
+                    }
+                    None => chip8.step_instruction(),
+                }
                 total_cycles = total_cycles.wrapping_add(1);
+                run_script(&mut egui_state, &mut chip8, Script::after_step);
             }
+            chip8.tick_timers();
+            egui_state.filter.push(chip8.get_display());
+            run_script(&mut egui_state, &mut chip8, Script::end_frame);
+            record_frame(&mut egui_state, &chip8);
+        }
+
+        if is_key_pressed(KeyCode::F12) {
+            take_screenshot(&egui_state, &chip8);
+        }
+        if is_key_pressed(KeyCode::F9) {
+            toggle_recording(&mut egui_state, &chip8);
         }
 
         let window_background_color = Color::new(
@@ -87,18 +246,10 @@ async fn main() {
             egui_state.window_color[2],
             1.0,
         );
-        let game_foreground_color = Color::new(
-            egui_state.game_foreground_color[0],
-            egui_state.game_foreground_color[1],
-            egui_state.game_foreground_color[2],
-            1.0,
-        );
-        let game_background_color = Color::new(
-            egui_state.game_background_color[0],
-            egui_state.game_background_color[1],
-            egui_state.game_background_color[2],
-            1.0,
-        );
+        let game_colors = egui_state
+            .palette
+            .colors
+            .map(|[r, g, b]| Color::new(r, g, b, 1.0));
 
         clear_background(window_background_color);
 
@@ -110,6 +261,15 @@ async fn main() {
                         dialog.open();
                         egui_state.file_dialog = Some(dialog);
                     }
+                    if ui.add(egui::Button::new("ROM Browser")).clicked() {
+                        egui_state.browser.open = !egui_state.browser.open;
+                    }
+                    if ui.add(egui::Button::new("Keypad")).clicked() {
+                        egui_state.keypad.open = !egui_state.keypad.open;
+                    }
+                    if ui.add(egui::Button::new("Profiler")).clicked() {
+                        egui_state.profiler.open = !egui_state.profiler.open;
+                    }
                     if ui.add(egui::Button::new("Reload ROM")).clicked() {
                         // chip8.reset()
                         chip8.reload_rom().unwrap_or_else(|_| eprintln!("can't reload rom if no rom is loaded"));
@@ -122,27 +282,157 @@ async fn main() {
                     if let Some(dialog) = &mut egui_state.file_dialog {
                         if dialog.show(ctx).selected() {
                             if let Some(file) = dialog.path() {
-                                chip8.load_rom(file).unwrap();
-                                total_cycles = 0;
+                                if open_rom(&mut egui_state, &mut chip8, &file) {
+                                    total_cycles = 0;
+                                }
                             }
                         }
                     }
                 });
-                ui.label(if chip8.is_loaded() {
-                    format!("{} loaded", chip8.get_rom_name().unwrap())
-                } else {
-                    "no rom loaded".to_string()
+                ui.horizontal(|ui| {
+                    if ui.add(egui::Button::new("Load Script")).clicked() {
+                        let mut dialog = FileDialog::open_file(None);
+                        dialog.open();
+                        egui_state.script_dialog = Some(dialog);
+                    }
+                    if ui
+                        .add_enabled(
+                            egui_state.script.is_some(),
+                            egui::Button::new("Stop Script"),
+                        )
+                        .clicked()
+                    {
+                        stop_script(&mut egui_state, &mut chip8);
+                    }
+                    let mut selected = None;
+                    if let Some(dialog) = &mut egui_state.script_dialog {
+                        if dialog.show(ctx).selected() {
+                            selected = dialog.path();
+                        }
+                    }
+                    if let Some(file) = selected {
+                        load_script(&mut egui_state, &mut chip8, &file);
+                    }
+                });
+                ui.horizontal(|ui| {
+                    if ui.add(egui::Button::new("Screenshot (F12)")).clicked() {
+                        take_screenshot(&egui_state, &chip8);
+                    }
+                    let record = if egui_state.recorder.is_some() {
+                        "Stop Recording (F9)"
+                    } else {
+                        "Record (F9)"
+                    };
+                    if ui.add(egui::Button::new(record)).clicked() {
+                        toggle_recording(&mut egui_state, &chip8);
+                    }
+                    if ui
+                        .add_enabled(chip8.is_loaded(), egui::Button::new("Save Cartridge"))
+                        .clicked()
+                    {
+                        save_cartridge(&egui_state, &chip8);
+                    }
+                });
+                if let Some(recorder) = &egui_state.recorder {
+                    ui.label(format!("recording, {} frames", recorder.frames()));
+                }
+                if let Some(name) = &egui_state.script_name {
+                    ui.label(format!("{name} running"));
+                }
+                ui.label(match chip8.get_rom_name() {
+                    Some(name) => format!("{name} loaded"),
+                    None if chip8.is_loaded() => "rom loaded".to_string(),
+                    None => "no rom loaded".to_string(),
+                });
+                if let Some(fault) = chip8.fault() {
+                    ui.label(format!("stopped: {fault}"));
+                }
+                ui.horizontal(|ui| {
+                    ui.add(egui::Checkbox::new(&mut egui_state.paused, "Paused"));
+                    ui.add(egui::Checkbox::new(
+                        &mut egui_state.slow_motion,
+                        "Slow Motion",
+                    ));
                 });
-                ui.add(egui::Checkbox::new(&mut egui_state.paused, "Paused"));
                 ui.collapsing("Settings", |ui| {
                     ui.add(
                         egui::Slider::new(&mut egui_state.target_fps, 1.0..=200.0)
                             .text("Target FPS"),
                     );
+                    let mut speed = chip8.speed();
+                    if ui
+                        .add(egui::Slider::new(&mut speed, 60..=5000).text("Speed (Hz)"))
+                        .changed()
+                    {
+                        chip8.set_speed(speed);
+                    }
                     ui.add(
-                        egui::Slider::new(&mut egui_state.cycles_per_frame, 1..=20)
-                            .text("Cycles per frame"),
+                        egui::Slider::new(&mut egui_state.fast_forward, 2.0..=16.0)
+                            .text("Fast-forward (hold Tab)"),
                     );
+                    ui.add(
+                        egui::Slider::new(&mut egui_state.slow_motion_rate, 0.1..=0.9)
+                            .text("Slow motion"),
+                    );
+                    let mut platform = chip8.platform();
+                    egui::ComboBox::from_label("Platform")
+                        .selected_text(platform.name())
+                        .show_ui(ui, |ui| {
+                            for preset in Platform::ALL {
+                                ui.selectable_value(&mut platform, preset, preset.name());
+                            }
+                        });
+                    if platform != chip8.platform() {
+                        if let Err(e) = chip8.set_platform(platform) {
+                            eprintln!("failed to switch platform: {e}");
+                        }
+                        total_cycles = 0;
+                    }
+                    let mut quirks = chip8.quirks();
+                    if ui
+                        .checkbox(
+                            &mut quirks.key_release,
+                            "Fx0A waits for key release (COSMAC)",
+                        )
+                        .changed()
+                    {
+                        chip8.set_quirks(quirks);
+                    }
+                    let mut depth = chip8.stack_depth();
+                    if ui
+                        .add(egui::Slider::new(&mut depth, 1..=16).text("Stack depth"))
+                        .changed()
+                    {
+                        chip8.set_stack_depth(depth);
+                    }
+                    let mut memory_stack = chip8.memory_stack();
+                    if ui
+                        .checkbox(&mut memory_stack, "Stack in memory at 0xEA0 (COSMAC)")
+                        .changed()
+                    {
+                        chip8.set_memory_stack(memory_stack);
+                    }
+                    let mut font = chip8.font();
+                    egui::ComboBox::from_label("Font")
+                        .selected_text(font.name())
+                        .show_ui(ui, |ui| {
+                            for preset in FontSet::ALL {
+                                ui.selectable_value(&mut font, preset, preset.name());
+                            }
+                        });
+                    if font != chip8.font() {
+                        chip8.set_font(font);
+                    }
+                    let mut font_address = chip8.font_address();
+                    ui.horizontal(|ui| {
+                        ui.label("Font address");
+                        for address in [0x000, 0x050] {
+                            ui.radio_value(&mut font_address, address, format!("{address:03X}"));
+                        }
+                    });
+                    if font_address != chip8.font_address() {
+                        chip8.set_font_address(font_address).unwrap();
+                    }
                     ui.add(
                         egui::Slider::new(&mut egui_state.game_scale, 1.0..=50.0)
                             .text("Game Scale"),
@@ -151,33 +441,153 @@ async fn main() {
                         &mut egui_state.game_centered,
                         "Game Centered",
                     ));
+                    egui::ComboBox::from_label("Palette")
+                        .selected_text(egui_state.palette.name.as_str())
+                        .show_ui(ui, |ui| {
+                            for preset in Palette::presets() {
+                                let selected = egui_state.palette == preset;
+                                if ui
+                                    .selectable_label(selected, preset.name.as_str())
+                                    .clicked()
+                                {
+                                    egui_state.palette = preset;
+                                }
+                            }
+                        });
+                    let labels = ["Background", "Plane 1", "Plane 2", "Both Planes"];
+                    for (i, (color, label)) in
+                        egui_state.palette.colors.iter_mut().zip(labels).enumerate()
+                    {
+                        ui.horizontal(|ui| {
+                            ui.color_edit_button_rgb(color);
+                            if Palette::is_reserved(i) {
+                                ui.label(format!("Game {label} Color (reserved)"))
+                                    .on_hover_text(
+                                        "XO-CHIP planes aren't emulated, so this colour is \
+                                         only kept for palette files and cartridges",
+                                    );
+                            } else {
+                                ui.label(format!("Game {label} Color"));
+                            }
+                        });
+                    }
                     ui.horizontal(|ui| {
-                        ui.color_edit_button_rgb(&mut egui_state.game_foreground_color);
-                        ui.label("Game Foreground Color");
-                    });
-                    ui.horizontal(|ui| {
-                        ui.color_edit_button_rgb(&mut egui_state.game_background_color);
-                        ui.label("Game Background Color");
+                        if ui.add(egui::Button::new("Import Palette")).clicked() {
+                            let mut dialog = FileDialog::open_file(None);
+                            dialog.open();
+                            egui_state.palette_dialog = Some(dialog);
+                        }
+                        if ui.add(egui::Button::new("Export Palette")).clicked() {
+                            let mut dialog = FileDialog::save_file(None);
+                            dialog.open();
+                            egui_state.palette_dialog = Some(dialog);
+                        }
+                        let rom_path = chip8.rom_path();
+                        if ui
+                            .add_enabled(rom_path.is_some(), egui::Button::new("Use for ROM"))
+                            .clicked()
+                        {
+                            if let Some(rom_path) = rom_path {
+                                let path = palette::rom_palette_path(rom_path);
+                                if let Err(e) = egui_state.palette.save(&path) {
+                                    eprintln!("failed to save palette: {e}");
+                                }
+                            }
+                        }
+                        if let Some(dialog) = &mut egui_state.palette_dialog {
+                            if dialog.show(ctx).selected() {
+                                if let Some(file) = dialog.path() {
+                                    if dialog.dialog_type() == DialogType::SaveFile {
+                                        if let Err(e) = egui_state.palette.save(file) {
+                                            eprintln!("failed to export palette: {e}");
+                                        }
+                                    } else {
+                                        match Palette::load(file) {
+                                            Ok(palette) => egui_state.palette = palette,
+                                            Err(e) => eprintln!("failed to import palette: {e}"),
+                                        }
+                                    }
+                                }
+                            }
+                        }
                     });
                     ui.horizontal(|ui| {
                         ui.color_edit_button_rgb(&mut egui_state.window_color);
                         ui.label("Window Background Color");
                     });
+                    let filter = &mut egui_state.filter;
+                    egui::ComboBox::from_label("Display Filter")
+                        .selected_text(filter.kind.name())
+                        .show_ui(ui, |ui| {
+                            for mode in [FilterKind::Off, FilterKind::Decay, FilterKind::Or] {
+                                ui.selectable_value(&mut filter.kind, mode, mode.name());
+                            }
+                        });
+                    match filter.kind {
+                        FilterKind::Off => {}
+                        FilterKind::Decay => {
+                            ui.add(
+                                egui::Slider::new(&mut filter.decay, 0.0..=0.95)
+                                    .text("Persistence"),
+                            );
+                        }
+                        FilterKind::Or => {
+                            ui.add(egui::Slider::new(&mut filter.frames, 2..=8).text("Frames"));
+                        }
+                    }
                 });
-                ui.label(format!("{} fps, {} cps, {} cycles", get_fps(), egui_state.target_fps as u32 * egui_state.cycles_per_frame, total_cycles));
+                ui.label(format!(
+                    "{} fps, {} Hz x{}, {} cycles",
+                    get_fps(),
+                    chip8.speed(),
+                    egui_state.scheduler.rate(),
+                    total_cycles
+                ));
             });
+            if let Some(path) = egui_state.browser.show(ctx) {
+                if open_rom(&mut egui_state, &mut chip8, &path) {
+                    total_cycles = 0;
+                }
+            }
+            egui_state.keypad.show(ctx, &mut chip8);
+            if egui_state.profiler.open {
+                if let Some(export) = egui_state.profiler.show(ctx, &mut chip8) {
+                    export_profile(&egui_state, &chip8, export);
+                }
+            }
         });
 
-        // render game to texture
-        let mut image = Image::gen_image_color(64, 32, game_background_color);
-        render(
-            &mut image,
-            chip8.get_display(),
-            game_foreground_color,
-            game_background_color,
+        // switching to or from hi-res CHIP-8 or MegaChip mode changes the display size
+        let (width, height) = chip8.get_display_size();
+        if image.width() != width || image.height() != height {
+            image = Image::gen_image_color(width as u16, height as u16, BLACK);
+            texture.delete();
+            texture = Texture2D::from_image(&image);
+            texture.set_filter(FilterMode::Nearest);
+        }
+
+        // render game to texture, only when the display or colours have changed or a filter has
+        // blended in another emulated frame
+        let filtered = match egui_state.filter.kind {
+            FilterKind::Off => 0,
+            _ => egui_state.filter.generation(),
+        };
+        let key = (
+            chip8.display_generation(),
+            egui_state.palette.colors,
+            egui_state.filter.kind,
+            filtered,
         );
-        let texture = Texture2D::from_image(&image);
-        texture.set_filter(FilterMode::Nearest);
+        if rendered != Some(key) {
+            let intensity = egui_state.filter.intensity(chip8.get_display());
+            match (chip8.get_display_rgba(), chip8.get_display_colors()) {
+                (Some(pixels), _) => render_rgba(&mut image, pixels),
+                (None, Some(colors)) => render_colors(&mut image, intensity, &colors),
+                (None, None) => render(&mut image, chip8.get_display(), intensity, &game_colors),
+            }
+            texture.update(&image);
+            rendered = Some(key);
+        }
 
         // render texture to screen
         draw_texture_ex(
@@ -195,8 +605,8 @@ async fn main() {
             WHITE,
             DrawTextureParams {
                 dest_size: Some(Vec2 {
-                    x: width * egui_state.game_scale,
-                    y: height * egui_state.game_scale,
+                    x: texture.width() * egui_state.game_scale,
+                    y: texture.height() * egui_state.game_scale,
                 }),
                 source: None,
                 rotation: 0.0,
@@ -223,17 +633,61 @@ async fn main() {
         
 
         let _ = next_frame().await;
-        texture.delete();
     }
 }
 
-fn render(image: &mut Image, pixels: &[u8], foreground: Color, background: Color) {
-    for (i, p) in pixels.iter().enumerate() {
-        if *p == 0 {
-            image.set_pixel((i % WIDTH) as u32, (i / WIDTH) as u32, background);
+/// Draw the display in the palette's background and plane 1 colours. Unlit pixels are faded
+/// between the two by `intensity`.
+fn render(image: &mut Image, pixels: &[u8], intensity: &[f32], colors: &[Color; 4]) {
+    let width = image.width();
+    let background = colors[0];
+    let foreground = colors[1];
+    for (i, (pixel, p)) in pixels.iter().zip(intensity).enumerate() {
+        let color = if *pixel != 0 {
+            foreground
+        } else if *p <= 0.0 {
+            background
         } else {
-            image.set_pixel((i % WIDTH) as u32, (i / WIDTH) as u32, foreground);
-        }
+            Color::new(
+                background.r + (foreground.r - background.r) * p,
+                background.g + (foreground.g - background.g) * p,
+                background.b + (foreground.b - background.b) * p,
+                1.0,
+            )
+        };
+        image.set_pixel((i % width) as u32, (i / width) as u32, color);
+    }
+}
+
+/// Render the CHIP-8X display, where the colour of lit pixels depends on their zone.
+fn render_colors(image: &mut Image, intensity: &[f32], colors: &DisplayColors) {
+    let rgb = |code: u8| {
+        let [r, g, b] = color::COLORS[code as usize & 7];
+        Color::from_rgba(r, g, b, 255)
+    };
+    let background = rgb(colors.background);
+    for (i, (p, code)) in intensity.iter().zip(&colors.foreground).enumerate() {
+        let foreground = rgb(*code);
+        let color = Color::new(
+            background.r + (foreground.r - background.r) * p,
+            background.g + (foreground.g - background.g) * p,
+            background.b + (foreground.b - background.b) * p,
+            1.0,
+        );
+        image.set_pixel((i % WIDTH) as u32, (i / WIDTH) as u32, color);
+    }
+}
+
+/// Render the MegaChip display from its ARGB pixels.
+fn render_rgba(image: &mut Image, pixels: &[u32]) {
+    let width = image.width();
+    for (i, pixel) in pixels.iter().enumerate() {
+        let [_, r, g, b] = pixel.to_be_bytes();
+        image.set_pixel(
+            (i % width) as u32,
+            (i / width) as u32,
+            Color::from_rgba(r, g, b, 255),
+        );
     }
 }
 
@@ -243,12 +697,278 @@ fn render(image: &mut Image, pixels: &[u8], foreground: Color, background: Color
 // A S D F    7 8 9 E
 // Z X C V    A 0 B F
 
-fn update_keys(chip8: &mut Chip8) {
+fn update_keys(chip8: &mut Chip8, state: &State) {
+    // keys held by scripts, remote clients and the on-screen keypad stay down regardless of
+    // the keyboard
+    let mut held = state
+        .script
+        .as_ref()
+        .map(Script::held_keys)
+        .unwrap_or_default();
+    for server in state.servers.iter() {
+        for (held, key) in held.iter_mut().zip(server.held_keys()) {
+            *held |= key;
+        }
+    }
+    for (held, key) in held.iter_mut().zip(state.keypad.held_keys()) {
+        *held |= key;
+    }
+    for (i, held) in state.keypad.held_keys2().into_iter().enumerate() {
+        chip8.set_keypad2_state(i, held);
+    }
     for (i, key) in KEY_MAP.iter().enumerate() {
-        if is_key_down(*key) {
+        if is_key_down(*key) || held[i] {
             chip8.set_key_state(i, true);
         } else {
             chip8.set_key_state(i, false);
         }
     }
 }
+
+/// Replace the running script with the one at `path`.
+fn load_script(state: &mut State, chip8: &mut Chip8, path: &Path) {
+    stop_script(state, chip8);
+    let mut script = match Script::load(path) {
+        Ok(script) => script,
+        Err(e) => {
+            eprintln!("failed to load script: {e}");
+            return;
+        }
+    };
+    match script.attach(chip8) {
+        Ok(()) => {
+            state.script = Some(script);
+            state.script_name = path
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string());
+        }
+        Err(e) => {
+            eprintln!("script error: {e}");
+            script.detach(chip8);
+        }
+    }
+}
+
+fn stop_script(state: &mut State, chip8: &mut Chip8) {
+    if let Some(mut script) = state.script.take() {
+        script.detach(chip8);
+    }
+    state.script_name = None;
+}
+
+/// Call into the running script, stopping it if it fails.
+fn run_script(
+    state: &mut State,
+    chip8: &mut Chip8,
+    f: fn(&mut Script, &mut Chip8) -> ScriptResult<()>,
+) {
+    if let Some(script) = &mut state.script {
+        if let Err(e) = f(script, chip8) {
+            eprintln!("script error: {e}");
+            stop_script(state, chip8);
+        }
+    }
+}
+
+/// Load a ROM, or an Octo cartridge along with its speed and colours. Returns false if it
+/// couldn't be loaded.
+fn open_rom(state: &mut State, chip8: &mut Chip8, path: &Path) -> bool {
+    if chip8_carts::is_cartridge(path) {
+        let cartridge = match Cartridge::load(path) {
+            Ok(cartridge) => cartridge,
+            Err(e) => {
+                eprintln!("failed to load cartridge: {e}");
+                return false;
+            }
+        };
+        if let Err(e) = cartridge.apply(chip8) {
+            eprintln!("failed to load cartridge: {e}");
+            return false;
+        }
+        let colors = cartridge.options.display_colors();
+        state.palette = Palette {
+            name: "Cartridge".to_string(),
+            colors: colors.map(|color| color.map(|c| c as f32 / 255.0)),
+        };
+        state.cartridge = Some(cartridge.options);
+        state.browser.add_recent(path);
+        return true;
+    }
+    match chip8.load_rom(path) {
+        Ok(()) => {
+            state.cartridge = None;
+            state.browser.add_recent(path);
+            load_rom_palette(state, chip8);
+            true
+        }
+        Err(e) => {
+            eprintln!("failed to load rom: {e}");
+            false
+        }
+    }
+}
+
+/// Open a file dropped on the window: a palette, a script, or otherwise a ROM. Returns true if
+/// a ROM was loaded.
+fn open_file(state: &mut State, chip8: &mut Chip8, path: &Path) -> bool {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("palette") => {
+            match Palette::load(path) {
+                Ok(palette) => state.palette = palette,
+                Err(e) => eprintln!("failed to load palette: {e}"),
+            }
+            false
+        }
+        Some("rhai") => {
+            load_script(state, chip8, path);
+            false
+        }
+        _ => open_rom(state, chip8, path),
+    }
+}
+
+/// Files dropped on the window since the last call. miniquad only keeps the files of the most
+/// recent drop, so a new drop is noticed by them changing. Dropping files is only reported by
+/// some of miniquad's platforms.
+fn dropped_files(last: &mut Vec<PathBuf>) -> Vec<PathBuf> {
+    // SAFETY: only called from the main thread, between frames
+    let context = unsafe { get_internal_gl() }.quad_context;
+    let files: Vec<PathBuf> = (0..context.dropped_file_count())
+        .filter_map(|i| context.dropped_file_path(i))
+        .collect();
+    if files == *last {
+        return Vec::new();
+    }
+    *last = files.clone();
+    files
+}
+
+/// Save the loaded ROM as an Octo cartridge labelled with the current display.
+fn save_cartridge(state: &State, chip8: &Chip8) {
+    let to_rgb = |color: [f32; 3]| color.map(|c| (c * 255.0).round() as u8);
+    let mut options = state.cartridge.clone().unwrap_or_default();
+    [
+        options.background_color,
+        options.fill_color,
+        options.fill_color2,
+        options.blend_color,
+    ] = state.palette.colors.map(to_rgb);
+    let path = capture_path(state, chip8, "cart.gif");
+    let result = Cartridge::from_chip8(chip8, options)
+        .and_then(|cartridge| cartridge.save(&path, chip8.get_display()));
+    match result {
+        Ok(()) => println!("saved cartridge to {}", path.display()),
+        Err(e) => eprintln!("failed to save cartridge: {e}"),
+    }
+}
+
+/// Switch to the palette saved for the loaded ROM, if there is one.
+fn load_rom_palette(state: &mut State, chip8: &Chip8) {
+    let Some(path) = chip8.rom_path().map(palette::rom_palette_path) else {
+        return;
+    };
+    if path.exists() {
+        match Palette::load(&path) {
+            Ok(palette) => state.palette = palette,
+            Err(e) => eprintln!("failed to load palette: {e}"),
+        }
+    }
+}
+
+fn capture_format(state: &State, chip8: &Chip8) -> Format {
+    let to_rgb = |color: [f32; 3]| color.map(|c| (c * 255.0).round() as u8);
+    let (width, height) = chip8.get_display_size();
+    Format {
+        width,
+        height,
+        scale: state.capture_scale,
+        palette: chip8_capture::Palette {
+            foreground: to_rgb(state.palette.foreground()),
+            background: to_rgb(state.palette.background()),
+        },
+    }
+}
+
+/// A path in the capture directory named after the ROM and the current time.
+fn capture_path(state: &State, chip8: &Chip8, extension: &str) -> PathBuf {
+    let name = chip8.get_rom_name().unwrap_or_else(|| "chip8".to_string());
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_millis());
+    state.capture_dir.join(format!("{name}-{time}.{extension}"))
+}
+
+fn take_screenshot(state: &State, chip8: &Chip8) {
+    let path = capture_path(state, chip8, "png");
+    let format = capture_format(state, chip8);
+    let result = match chip8.get_display_rgb() {
+        Some(pixels) => chip8_capture::save_png_rgb(&path, &format, &pixels),
+        None => chip8_capture::save_png(&path, &format, chip8.get_display()),
+    };
+    match result {
+        Ok(()) => println!("saved screenshot to {}", path.display()),
+        Err(e) => eprintln!("failed to save screenshot: {e}"),
+    }
+}
+
+/// Save the profile to the capture directory, as CSV or as folded stacks for flamegraphs.
+fn export_profile(state: &State, chip8: &Chip8, export: Export) {
+    let Some(profile) = chip8.profile() else {
+        return;
+    };
+    let extension = match export {
+        Export::Csv => "csv",
+        Export::Folded => "folded",
+    };
+    let path = capture_path(state, chip8, extension);
+    let result = File::create(&path).and_then(|file| {
+        let mut w = BufWriter::new(file);
+        match export {
+            Export::Csv => profile.write_csv(&mut w, |address| chip8.disassemble(address))?,
+            Export::Folded => {
+                let name = chip8.get_rom_name().unwrap_or_else(|| "chip8".to_string());
+                profile.write_folded(&mut w, &name)?;
+            }
+        }
+        w.flush()
+    });
+    match result {
+        Ok(()) => println!("saved profile to {}", path.display()),
+        Err(e) => eprintln!("failed to save profile: {e}"),
+    }
+}
+
+fn record_frame(state: &mut State, chip8: &Chip8) {
+    if let Some(recorder) = &mut state.recorder {
+        let result = match chip8.get_display_rgb() {
+            Some(pixels) => recorder.record_frame_rgb(&pixels),
+            None => recorder.record_frame(chip8.get_display()),
+        };
+        if let Err(e) = result {
+            eprintln!("recording failed: {e}");
+            state.recorder = None;
+        }
+    }
+}
+
+fn toggle_recording(state: &mut State, chip8: &Chip8) {
+    if let Some(recorder) = state.recorder.take() {
+        match recorder.finish() {
+            Ok(()) => println!("recording saved"),
+            Err(e) => eprintln!("failed to save recording: {e}"),
+        }
+    } else {
+        let path = capture_path(state, chip8, "gif");
+        start_recording(state, chip8, &path);
+    }
+}
+
+fn start_recording(state: &mut State, chip8: &Chip8, path: &Path) {
+    match Recorder::create(path, capture_format(state, chip8)) {
+        Ok(recorder) => {
+            println!("recording to {}", path.display());
+            state.recorder = Some(recorder);
+        }
+        Err(e) => eprintln!("failed to start recording: {e}"),
+    }
+}
@@ -0,0 +1,151 @@
+use std::{
+    fmt::Write as _,
+    io::{Error, ErrorKind},
+    path::{Path, PathBuf},
+};
+
+/// Display colours, indexed by which XO-CHIP planes a pixel is lit in: unlit, plane 1,
+/// plane 2 and both planes. XO-CHIP planes aren't emulated, so only the first two are drawn;
+/// the others are reserved, kept so palette files and Octo cartridges round-trip.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Palette {
+    pub name: String,
+    pub colors: [[f32; 3]; 4],
+}
+
+/// Names of the colours in palette files and the settings window.
+pub const COLOR_NAMES: [&str; 4] = ["background", "plane1", "plane2", "both"];
+
+impl Palette {
+    fn preset(name: &str, colors: [u32; 4]) -> Palette {
+        Palette {
+            name: name.to_string(),
+            colors: colors.map(rgb),
+        }
+    }
+
+    /// Built in palettes, the first being the default.
+    pub fn presets() -> Vec<Palette> {
+        vec![
+            Palette::preset("Classic", [0x000000, 0xFFFFFF, 0xAAAAAA, 0x555555]),
+            Palette::preset("Green phosphor", [0x0A1A0A, 0x33FF66, 0x1F9940, 0x99FFB3]),
+            Palette::preset("Amber", [0x1A1000, 0xFFB000, 0x996A00, 0xFFD780]),
+            Palette::preset("LCD", [0x9BBC0F, 0x0F380F, 0x306230, 0x8BAC0F]),
+            Palette::preset("High contrast", [0x000000, 0xFFFF00, 0x00FFFF, 0xFFFFFF]),
+            // Okabe-Ito colours, distinguishable with the common forms of colour blindness
+            Palette::preset(
+                "Colour-blind safe",
+                [0x000000, 0xE69F00, 0x56B4E9, 0xF0E442],
+            ),
+        ]
+    }
+
+    /// Parse a palette file made of `key = value` lines, where the keys are `name` and the
+    /// colours in [`COLOR_NAMES`] as `#RRGGBB`. Colours that are left out keep their value
+    /// from the default palette.
+    pub fn parse(source: &str) -> std::io::Result<Palette> {
+        let mut palette = Palette::default();
+        for (number, line) in source.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = |msg: String| {
+                Error::new(
+                    ErrorKind::InvalidData,
+                    format!("line {}: {msg}", number + 1),
+                )
+            };
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| invalid("expected `key = value`".to_string()))?;
+            let (key, value) = (key.trim(), value.trim());
+            if key == "name" {
+                palette.name = value.to_string();
+                continue;
+            }
+            let index = COLOR_NAMES
+                .iter()
+                .position(|name| *name == key)
+                .ok_or_else(|| invalid(format!("unknown key `{key}`")))?;
+            palette.colors[index] =
+                parse_color(value).ok_or_else(|| invalid(format!("invalid colour `{value}`")))?;
+        }
+        Ok(palette)
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> std::io::Result<Palette> {
+        Palette::parse(&std::fs::read_to_string(path)?)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> std::io::Result<()> {
+        let mut file = format!("name = {}\n", self.name);
+        for (name, color) in COLOR_NAMES.iter().zip(self.colors) {
+            let [r, g, b] = color.map(|c| (c * 255.0).round() as u8);
+            let _ = writeln!(file, "{name} = #{r:02X}{g:02X}{b:02X}");
+        }
+        std::fs::write(path, file)
+    }
+
+    pub fn background(&self) -> [f32; 3] {
+        self.colors[0]
+    }
+
+    pub fn foreground(&self) -> [f32; 3] {
+        self.colors[1]
+    }
+
+    /// Whether the colour at `index` is one of the reserved, never drawn, plane colours.
+    pub fn is_reserved(index: usize) -> bool {
+        index >= 2
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette::presets().remove(0)
+    }
+}
+
+/// Where the palette chosen for a ROM is kept, next to the ROM itself.
+pub fn rom_palette_path(rom: &Path) -> PathBuf {
+    rom.with_extension("palette")
+}
+
+fn parse_color(value: &str) -> Option<[f32; 3]> {
+    let hex = value.strip_prefix('#')?;
+    if hex.len() != 6 {
+        return None;
+    }
+    u32::from_str_radix(hex, 16).ok().map(rgb)
+}
+
+/// Convert a colour written as `0xRRGGBB`.
+fn rgb(color: u32) -> [f32; 3] {
+    [16, 8, 0].map(|shift| ((color >> shift) & 0xFF) as f32 / 255.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        let palette =
+            Palette::parse("# comment\n\nname = Mine\nbackground = #FF8000\n  plane1=#00ff00  \n")
+                .unwrap();
+        assert_eq!(palette.name, "Mine");
+        assert_eq!(palette.background(), [1.0, 128.0 / 255.0, 0.0]);
+        assert_eq!(palette.foreground(), [0.0, 1.0, 0.0]);
+        // left out, so taken from the default
+        assert_eq!(palette.colors[2], Palette::default().colors[2]);
+
+        assert!(Palette::parse("background #000000").is_err());
+        assert!(Palette::parse("plane3 = #000000").is_err());
+        for color in ["000000", "#00000", "#0000000", "#GG0000"] {
+            let err = Palette::parse(&format!("name = x\nplane1 = {color}")).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidData);
+            assert!(err.to_string().starts_with("line 2:"));
+        }
+    }
+}
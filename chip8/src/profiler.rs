@@ -0,0 +1,182 @@
+use chip8_core::{
+    chip8::Chip8,
+    profiler::{InstructionClass, Profile},
+};
+
+/// Side of a cell in the memory heatmap, in points.
+const CELL_SIZE: f32 = 5.0;
+/// Addresses per row of the memory heatmap.
+const CELLS_PER_ROW: usize = 64;
+/// Instructions can only execute in the first 64 KiB.
+const MAX_ADDRESS: usize = 0x10000;
+
+/// A file the user asked to export the profile to.
+pub enum Export {
+    Csv,
+    Folded,
+}
+
+/// A window for profiling the running ROM, showing how often each address executed as a
+/// heatmap over the disassembly and over memory.
+pub struct Profiler {
+    pub open: bool,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Self { open: false }
+    }
+
+    /// Show the profiler window. Returns the export the user asked for, if any.
+    pub fn show(&mut self, ctx: &egui::Context, chip8: &mut Chip8) -> Option<Export> {
+        let mut export = None;
+        let mut open = self.open;
+        egui::Window::new("Profiler")
+            .open(&mut open)
+            .default_width(360.0)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    let mut profiling = chip8.profiling();
+                    if ui.checkbox(&mut profiling, "Record").changed() {
+                        chip8.set_profiling(profiling);
+                    }
+                    if ui.button("Clear").clicked() {
+                        chip8.clear_profile();
+                    }
+                    if ui.button("Export CSV").clicked() {
+                        export = Some(Export::Csv);
+                    }
+                    if ui.button("Export folded stacks").clicked() {
+                        export = Some(Export::Folded);
+                    }
+                });
+                match chip8.profile() {
+                    Some(profile) => show_profile(ui, chip8, profile),
+                    None => {
+                        ui.label("check Record to count the instructions the ROM executes");
+                    }
+                }
+            });
+        self.open = open;
+        export.filter(|_| chip8.profile().is_some())
+    }
+}
+
+fn show_profile(ui: &mut egui::Ui, chip8: &Chip8, profile: &Profile) {
+    let seconds = |frames: u64| frames as f64 / 60.0;
+    ui.label(format!(
+        "{} instructions in {:.1} s",
+        profile.total(),
+        seconds(profile.frames())
+    ));
+    ui.label(format!(
+        "waiting for a key (Fx0A): {:.1} s, in delay timer loops: {:.1} s",
+        seconds(profile.key_wait_frames()),
+        seconds(profile.timer_wait_frames())
+    ));
+    egui::Grid::new("profiler classes").show(ui, |ui| {
+        for class in InstructionClass::ALL {
+            let count = profile.class_count(class);
+            ui.label(class.name());
+            ui.label(count.to_string());
+            ui.label(format!(
+                "{:.1}%",
+                count as f64 * 100.0 / profile.total().max(1) as f64
+            ));
+            ui.end_row();
+        }
+    });
+
+    let max = profile.max_count();
+    egui::CollapsingHeader::new("Disassembly")
+        .default_open(true)
+        .show(ui, |ui| disassembly(ui, chip8, profile, max));
+    egui::CollapsingHeader::new("Memory").show(ui, |ui| memory(ui, chip8, profile, max));
+}
+
+/// List the program's instructions with their execution counts, coloured by how hot they are.
+fn disassembly(ui: &mut egui::Ui, chip8: &Chip8, profile: &Profile, max: u64) {
+    let start = chip8.program_start() as usize;
+    let end = (start + chip8.rom().map_or(0, <[u8]>::len)).min(MAX_ADDRESS);
+    // the program in steps of 2, plus anything else that executed, such as code at odd
+    // addresses or outside the ROM
+    let in_program =
+        |address: usize| (start..end).contains(&address) && (address - start).is_multiple_of(2);
+    let mut addresses: Vec<usize> = (start..end).step_by(2).collect();
+    addresses.extend(
+        profile
+            .counts()
+            .iter()
+            .enumerate()
+            .filter(|(address, count)| **count > 0 && !in_program(*address))
+            .map(|(address, _)| address),
+    );
+    addresses.sort_unstable();
+
+    let row_height = ui.text_style_height(&egui::TextStyle::Monospace);
+    egui::ScrollArea::vertical()
+        .id_source("profiler disassembly")
+        .max_height(300.0)
+        .show_rows(ui, row_height, addresses.len(), |ui, rows| {
+            for address in &addresses[rows] {
+                let address = *address as u16;
+                let count = profile.count(address);
+                let (opcode, text) = chip8.disassemble(address).unwrap_or_default();
+                let mut line =
+                    egui::RichText::new(format!("{address:03X}  {opcode:04X}  {text:<16} {count}"))
+                        .monospace();
+                if count > 0 {
+                    line = line.background_color(heat(count, max));
+                }
+                ui.label(line);
+            }
+        });
+}
+
+/// Show every address as a cell coloured by how often the instruction there executed.
+fn memory(ui: &mut egui::Ui, chip8: &Chip8, profile: &Profile, max: u64) {
+    let size = chip8.platform().memory_size().min(MAX_ADDRESS);
+    let rows = size.div_ceil(CELLS_PER_ROW);
+    egui::ScrollArea::vertical()
+        .id_source("profiler memory")
+        .max_height(320.0)
+        .show_rows(ui, CELL_SIZE, rows, |ui, rows| {
+            let first = rows.start;
+            let (rect, response) = ui.allocate_exact_size(
+                egui::vec2(
+                    CELLS_PER_ROW as f32 * CELL_SIZE,
+                    rows.len() as f32 * CELL_SIZE,
+                ),
+                egui::Sense::hover(),
+            );
+            let painter = ui.painter_at(rect);
+            painter.rect_filled(rect, 0.0, ui.visuals().extreme_bg_color);
+            for row in rows {
+                for column in 0..CELLS_PER_ROW {
+                    let count = profile.count((row * CELLS_PER_ROW + column) as u16);
+                    if count == 0 {
+                        continue;
+                    }
+                    let min = rect.min
+                        + egui::vec2(column as f32 * CELL_SIZE, (row - first) as f32 * CELL_SIZE);
+                    let cell = egui::Rect::from_min_size(min, egui::vec2(CELL_SIZE, CELL_SIZE));
+                    painter.rect_filled(cell, 0.0, heat(count, max));
+                }
+            }
+            if let Some(pos) = response.hover_pos() {
+                let offset = pos - rect.min;
+                let row = first + (offset.y / CELL_SIZE) as usize;
+                let column = ((offset.x / CELL_SIZE) as usize).min(CELLS_PER_ROW - 1);
+                let address = (row * CELLS_PER_ROW + column) as u16;
+                response.on_hover_text(format!("{address:03X}: {}", profile.count(address)));
+            }
+        });
+}
+
+/// Colour for an execution count, from blue for rarely executed to yellow for the hottest
+/// address, on a logarithmic scale so a few hot loops don't wash out everything else.
+fn heat(count: u64, max: u64) -> egui::Color32 {
+    let t = ((count as f64).ln_1p() / (max.max(1) as f64).ln_1p()) as f32;
+    let lerp = |from: u8, to: u8| (from as f32 + (to as f32 - from as f32) * t) as u8;
+    egui::Color32::from_rgb(lerp(40, 255), lerp(60, 220), lerp(160, 0))
+}